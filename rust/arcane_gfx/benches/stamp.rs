@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+#![feature(test)]
+extern crate test;
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{vec4, Vector4, Matrix4, PerspectiveFov, Rad};
+use cgmath::prelude::*;
+
+use arcane_gfx::Image;
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::stamp::{try_stamp_camera_image_with_options, StampOptions};
+
+/// The original implementation of `stamp_camera_image`, which walks every
+/// texel of the destination image. Used as the baseline.
+fn stamp_naive(
+    dst_image: &mut Image<&mut [Vector4<f32>]>,
+    dst_inv_view_proj_mat: Matrix4<f32>,
+    src_image: &Image<&[Vector4<u8>]>,
+    src_view_proj_mat: Matrix4<f32>,
+) {
+    let m = src_view_proj_mat * dst_inv_view_proj_mat;
+    let v_base = m * vec4(0.0, 0.0, 1.0, 1.0);
+    let v_u = m * vec4(1.0, 0.0, 0.0, 0.0);
+    let v_v = m * vec4(0.0, 1.0, 0.0, 0.0);
+
+    let src_width = src_image.width;
+    let src_height = src_image.height;
+    let src_data = &src_image.data[0..src_width * src_height];
+
+    let dst_width = dst_image.width;
+    let dst_height = dst_image.height;
+    let dst_data = &mut dst_image.data[0..dst_width * dst_height];
+
+    for y in 0..dst_height {
+        let cs1_y = (y as f32 + 0.5) * (2.0 / dst_height as f32) - 1.0;
+
+        let line1 = v_base - v_u + v_v * cs1_y;
+        let line2 = v_base + v_u + v_v * cs1_y;
+
+        if line1.w <= 0.0 && line2.w <= 0.0 {
+            continue;
+        }
+
+        let mut cs2 = line1;
+        let dcs2 = (line2 - line1) * (1.0 / dst_width as f32);
+        cs2 += dcs2 * 0.5;
+
+        let out_line = &mut dst_data[y * dst_width..(y + 1) * dst_width];
+
+        for x in 0..dst_width {
+            if cs2.w > 0.0 && cs2.x.abs() < cs2.w && cs2.y.abs() < cs2.w {
+                let rcp_w = 1.0 / cs2.w;
+                let vp_x = ((cs2.x * rcp_w + 1.0) * (0.5 * src_width as f32)) as usize;
+                let vp_y = ((cs2.y * rcp_w + 1.0) * (0.5 * src_height as f32)) as usize;
+                let vp_y = (src_height - 1).wrapping_sub(vp_y);
+
+                if let Some(src) = src_data.get(vp_x + vp_y * src_width) {
+                    let mut pixel = src.cast::<u32>();
+                    pixel.x *= pixel.x;
+                    pixel.y *= pixel.y;
+                    pixel.z *= pixel.z;
+                    pixel.w = 1;
+                    out_line[x] = pixel.cast();
+                }
+            }
+            cs2 += dcs2;
+        }
+    }
+}
+
+/// Stamps a 640x480 image from a typical phone camera (60° vertical FOV)
+/// looking slightly upward onto all faces of a cube map. `tile_size == None`
+/// selects `stamp_naive`.
+fn run_single(b: &mut test::Bencher, size: usize, tile_size: Option<usize>) {
+    let (width, height) = (640, 480);
+    let src_data = vec![Vector4::new(128u8, 128, 128, 255); width * height];
+    let src = Image { data: &src_data[..], width, height };
+
+    let proj: Matrix4<f32> = PerspectiveFov {
+        fovy: Rad(1.05),
+        aspect: width as f32 / height as f32,
+        near: 0.1,
+        far: 10.0,
+    }.into();
+    let matrix = proj * Matrix4::from_angle_x(Rad(-0.3)) * Matrix4::from_angle_y(Rad(0.4));
+
+    let mut dst = vec![vec![Vector4::zero(); size * size]; 6];
+    b.iter(move || for (face, dst) in CUBE_FACES.iter().zip(dst.iter_mut()) {
+        let mut dst_image = Image { data: &mut dst[..], width: size, height: size };
+        let inv_view_proj_mat = face.info().inv_view_proj_mat;
+        if let Some(tile_size) = tile_size {
+            let options = StampOptions { tile_size, ..StampOptions::default() };
+            try_stamp_camera_image_with_options(
+                &mut dst_image,
+                inv_view_proj_mat,
+                &src,
+                matrix,
+                &options,
+            ).unwrap();
+        } else {
+            stamp_naive(&mut dst_image, inv_view_proj_mat, &src, matrix);
+        }
+    })
+}
+
+#[bench]
+fn stamp_64(b: &mut test::Bencher) {
+    run_single(b, 64, Some(16))
+}
+
+#[bench]
+fn stamp_64_no_culling(b: &mut test::Bencher) {
+    run_single(b, 64, Some(0))
+}
+
+#[bench]
+fn stamp_64_naive(b: &mut test::Bencher) {
+    run_single(b, 64, None)
+}
+
+#[bench]
+fn stamp_128(b: &mut test::Bencher) {
+    run_single(b, 128, Some(16))
+}
+
+#[bench]
+fn stamp_128_no_culling(b: &mut test::Bencher) {
+    run_single(b, 128, Some(0))
+}
+
+#[bench]
+fn stamp_128_naive(b: &mut test::Bencher) {
+    run_single(b, 128, None)
+}
+
+#[bench]
+fn stamp_256(b: &mut test::Bencher) {
+    run_single(b, 256, Some(16))
+}
+
+#[bench]
+fn stamp_256_no_culling(b: &mut test::Bencher) {
+    run_single(b, 256, Some(0))
+}
+
+#[bench]
+fn stamp_256_naive(b: &mut test::Bencher) {
+    run_single(b, 256, None)
+}
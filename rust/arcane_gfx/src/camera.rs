@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+use cgmath::{Vector3, Matrix3, Matrix4, Quaternion, Rad};
+use cgmath::prelude::*;
+
+use {Error, Result};
+
+/// A perspective camera.
+///
+/// The camera looks toward `-Z` with `+Y` up in its local space, and its
+/// projection matrix follows the OpenGL clip space convention (`+Y` up in
+/// the image, `w` is the distance along the view direction).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    /// Rotates a vector from the camera's local space into the world space.
+    pub orientation: Quaternion<f32>,
+
+    /// The camera position in the world space.
+    pub position: Vector3<f32>,
+
+    /// The vertical field of view.
+    pub fov_y: Rad<f32>,
+
+    /// The ratio of the image width to its height.
+    pub aspect: f32,
+
+    /// The distance to the near clipping plane. The far plane is at infinity.
+    pub near: f32,
+}
+
+impl Camera {
+    /// Constructs a `Camera` located at the origin.
+    pub fn new(orientation: Quaternion<f32>, fov_y: Rad<f32>, aspect: f32) -> Self {
+        Self {
+            orientation,
+            position: Vector3::zero(),
+            fov_y,
+            aspect,
+            near: 0.01,
+        }
+    }
+
+    /// Returns `Err(Error::InvalidCamera)` if any of the parameters is out
+    /// of range.
+    pub fn validate(&self) -> Result<()> {
+        let q = self.orientation;
+        let values = [
+            q.s, q.v.x, q.v.y, q.v.z,
+            self.position.x, self.position.y, self.position.z,
+        ];
+        if !values.iter().all(|x| x.is_finite()) || !(q.magnitude2() > 0.0) ||
+            !(self.fov_y.0 > 0.0 && self.fov_y.0 < ::std::f32::consts::PI) ||
+            !(self.aspect > 0.0 && self.aspect.is_finite()) ||
+            !(self.near > 0.0 && self.near.is_finite())
+        {
+            return Err(Error::InvalidCamera);
+        }
+        Ok(())
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        self.rotation_view_matrix() * Matrix4::from_translation(-self.position)
+    }
+
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        let f = 1.0 / (self.fov_y.0 * 0.5).tan();
+        Matrix4::new(
+            f / self.aspect, 0.0, 0.0, 0.0,
+            0.0, f, 0.0, 0.0,
+            0.0, 0.0, -1.0, -1.0,
+            0.0, 0.0, -2.0 * self.near, 0.0,
+        )
+    }
+
+    pub fn view_proj_matrix(&self) -> Matrix4<f32> {
+        self.projection_matrix() * self.view_matrix()
+    }
+
+    /// Returns the view-projection matrix without the translation, which is
+    /// what environment maps (being infinitely far away) are stamped with.
+    pub fn environment_view_proj_matrix(&self) -> Matrix4<f32> {
+        self.projection_matrix() * self.rotation_view_matrix()
+    }
+
+    fn rotation_view_matrix(&self) -> Matrix4<f32> {
+        // The inverse of the (normalized) orientation
+        Matrix4::from(Matrix3::from(self.orientation.normalize()).transpose())
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+use cgmath::{Vector3, Matrix3};
+use cgmath::prelude::*;
+
+use cubemap::{CubeFace, CUBE_FACES, clip_to_texel};
+
+/// Describes the world coordinate system a cube map is used in.
+///
+/// All graphics APIs supported here look up a cube map using a world-space
+/// direction as-is, and agree on the orientation of the faces relative to the
+/// lookup direction (see `CubeFace`). What differs is the world space itself,
+/// so a convention is described by the transformation from its world space
+/// into the canonical one used by this crate: right-handed, `+X` right, `+Y`
+/// up and `-Z` forward (the same as OpenGL and WebGL).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CubeMapConvention {
+    /// Transforms a direction in this convention's world space into the
+    /// canonical world space. Must be orthonormal.
+    pub to_canonical: Matrix3<f32>,
+}
+
+/// Constructs a `Matrix3` from columns in a constant expression.
+macro_rules! mat3 {
+    ([$($x:expr),*], [$($y:expr),*], [$($z:expr),*]) => {
+        Matrix3 {
+            x: vec3!($($x),*),
+            y: vec3!($($y),*),
+            z: vec3!($($z),*),
+        }
+    };
+}
+
+macro_rules! vec3 {
+    ($x:expr, $y:expr, $z:expr) => { Vector3 { x: $x, y: $y, z: $z } };
+}
+
+impl CubeMapConvention {
+    /// OpenGL and WebGL: right-handed, `+Y` up, `-Z` forward.
+    pub const OPENGL: Self = CubeMapConvention {
+        to_canonical: mat3!([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    };
+
+    /// Direct3D: left-handed, `+Y` up, `+Z` forward.
+    pub const DIRECT3D: Self = CubeMapConvention {
+        to_canonical: mat3!([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    };
+
+    /// Vulkan. The clip space Y axis is flipped compared to OpenGL, but that
+    /// does not affect cube map lookups, so this is identical to `OPENGL`.
+    pub const VULKAN: Self = Self::OPENGL;
+
+    /// Right-handed, `+X` right, `+Y` forward, `+Z` up (e.g., Blender).
+    pub const Z_UP_RIGHT_HANDED: Self = CubeMapConvention {
+        to_canonical: mat3!([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    };
+
+    /// Left-handed, `+X` forward, `+Y` right, `+Z` up (e.g., Unreal Engine).
+    pub const Z_UP_LEFT_HANDED: Self = CubeMapConvention {
+        to_canonical: mat3!([0.0, 0.0, -1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    };
+
+    /// The names accepted by `by_name`.
+    pub const NAMES: &'static [&'static str] =
+        &["opengl", "direct3d", "vulkan", "z-up-rh", "z-up-lh"];
+
+    /// Looks up a predefined convention by its name (see `NAMES`).
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "opengl" => Some(Self::OPENGL),
+            "direct3d" => Some(Self::DIRECT3D),
+            "vulkan" => Some(Self::VULKAN),
+            "z-up-rh" => Some(Self::Z_UP_RIGHT_HANDED),
+            "z-up-lh" => Some(Self::Z_UP_LEFT_HANDED),
+            _ => None,
+        }
+    }
+
+    /// Transforms a direction in the canonical world space into this
+    /// convention's world space.
+    pub fn from_canonical(&self) -> Matrix3<f32> {
+        self.to_canonical.transpose()
+    }
+
+    /// Returns the matrix that transforms a direction in the world space of
+    /// `self` into that of `to`.
+    pub fn remap_matrix(&self, to: &CubeMapConvention) -> Matrix3<f32> {
+        to.from_canonical() * self.to_canonical
+    }
+}
+
+impl Default for CubeMapConvention {
+    fn default() -> Self {
+        Self::OPENGL
+    }
+}
+
+/// Converts a cube map (six faces of `size²` texels in `CUBE_FACES` order)
+/// from the convention `from` to `to`.
+///
+/// The predefined conventions only differ by axis permutations and
+/// reflections, in which case the conversion is lossless. Otherwise the
+/// nearest texel is used.
+pub fn remap_cube_map<T: Copy>(
+    dst: &mut [&mut [T]],
+    src: &[&[T]],
+    size: usize,
+    from: &CubeMapConvention,
+    to: &CubeMapConvention,
+) {
+    assert_eq!(dst.len(), 6);
+    assert_eq!(src.len(), 6);
+
+    let m = to.remap_matrix(from);
+    for (face, dst_face) in CUBE_FACES.iter().zip(dst.iter_mut()) {
+        let dst_face = &mut dst_face[0..size * size];
+        for y in 0..size {
+            for x in 0..size {
+                let d = m * face.texel_direction(x, y, size);
+                let src_face = CubeFace::from_direction(d);
+                let p = src_face.project(d);
+                let src_x = clip_to_texel(p.x, size);
+                let src_y = clip_to_texel(p.y, size);
+                dst_face[x + y * size] = src[src_face as usize][src_x + src_y * size];
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Brute-force spherical convolution of cube maps.
+//!
+//! This is very slow (`O(n^2)` in the number of texels) and is only meant to
+//! be used as a reference for validating faster approximations.
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use cubemap::{CUBE_FACES, texel_solid_angle};
+
+/// A rotationally symmetric kernel on the unit sphere.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SphericalKernel {
+    /// The von Mises–Fisher distribution `exp(κ (cos θ - 1))`.
+    VonMisesFisher { kappa: f32 },
+
+    /// A Gaussian of the angular distance `exp(-θ² / 2σ²)` (in radians).
+    Gaussian { sigma: f32 },
+}
+
+impl SphericalKernel {
+    /// Returns a von Mises–Fisher kernel approximating a Gaussian of the
+    /// angular standard deviation `sigma` (in radians).
+    pub fn von_mises_fisher_from_sigma(sigma: f32) -> Self {
+        SphericalKernel::VonMisesFisher { kappa: 1.0 / (sigma * sigma) }
+    }
+
+    /// Evaluates the (unnormalized) kernel for the angle `θ` between two
+    /// directions, given as `cos θ`.
+    pub fn weight(&self, cos_theta: f32) -> f32 {
+        let cos_theta = cos_theta.max(-1.0).min(1.0);
+        match self {
+            &SphericalKernel::VonMisesFisher { kappa } => (kappa * (cos_theta - 1.0)).exp(),
+            &SphericalKernel::Gaussian { sigma } => {
+                let theta = cos_theta.acos();
+                (theta * theta * (-0.5 / (sigma * sigma))).exp()
+            }
+        }
+    }
+}
+
+/// Convolves the cube map `src` (six faces in `CUBE_FACES` order, each
+/// `src_size²` texels) with `kernel` and writes the result to `dst` (six faces
+/// of `dst_size²` texels).
+///
+/// Each source texel is weighted by the solid angle it subtends, and the
+/// result is normalized by the total weight, so a constant cube map is
+/// preserved.
+pub fn convolve_cube_map(
+    dst: &mut [&mut [Vector4<f32>]],
+    dst_size: usize,
+    src: &[&[Vector4<f32>]],
+    src_size: usize,
+    kernel: SphericalKernel,
+) {
+    assert_eq!(dst.len(), 6);
+    assert_eq!(src.len(), 6);
+
+    // Precompute the direction and solid angle of every source texel
+    let mut samples = Vec::with_capacity(src_size * src_size * 6);
+    for (face, src_face) in CUBE_FACES.iter().zip(src.iter()) {
+        let src_face = &src_face[0..src_size * src_size];
+        for y in 0..src_size {
+            for x in 0..src_size {
+                samples.push((
+                    face.texel_direction(x, y, src_size).normalize(),
+                    texel_solid_angle(x, y, src_size),
+                    src_face[x + y * src_size],
+                ));
+            }
+        }
+    }
+
+    for (face, dst_face) in CUBE_FACES.iter().zip(dst.iter_mut()) {
+        let dst_face = &mut dst_face[0..dst_size * dst_size];
+        for y in 0..dst_size {
+            for x in 0..dst_size {
+                let dir: Vector3<f32> = face.texel_direction(x, y, dst_size).normalize();
+
+                let mut sum = Vector4::zero();
+                let mut total_weight = 0.0;
+                for &(sample_dir, solid_angle, value) in samples.iter() {
+                    let weight = kernel.weight(dir.dot(sample_dir)) * solid_angle;
+                    sum += value * weight;
+                    total_weight += weight;
+                }
+
+                dst_face[x + y * dst_size] = sum / total_weight;
+            }
+        }
+    }
+}
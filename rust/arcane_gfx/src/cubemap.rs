@@ -5,7 +5,7 @@
  * comes with the source code for use conditions.
  */
 use std::ops;
-use cgmath::{Vector3, Matrix4};
+use cgmath::{Vector2, Vector3, Matrix4, BaseFloat};
 use cgmath::num_traits::NumCast;
 use cgmath::prelude::*;
 
@@ -97,6 +97,75 @@ impl CubeFace {
             Self::from_ordinal_unchecked(self.as_ordinal() & !1)
         }
     }
+
+    /// Returns the face intersected by the ray from the center of the cube
+    /// in the direction `d`.
+    pub fn from_direction<T: BaseFloat>(d: Vector3<T>) -> CubeFace {
+        let a = Vector3::new(d.x.abs(), d.y.abs(), d.z.abs());
+        if a.x >= a.y && a.x >= a.z {
+            if d.x >= T::zero() { CubeFace::PositiveX } else { CubeFace::NegativeX }
+        } else if a.y >= a.z {
+            if d.y >= T::zero() { CubeFace::PositiveY } else { CubeFace::NegativeY }
+        } else {
+            if d.z >= T::zero() { CubeFace::PositiveZ } else { CubeFace::NegativeZ }
+        }
+    }
+
+    /// Projects the direction `d` onto the plane of this face. Returns the
+    /// face's clip-space coordinates, which are in the range `[-1, 1]` if `d`
+    /// is inside this face.
+    ///
+    /// This is equivalent to transforming `d` by `info().view_proj_mat`.
+    pub fn project<T: BaseFloat>(&self, d: Vector3<T>) -> Vector2<T> {
+        let rcp_z = T::one() / self.normal().dot(d);
+        Vector2::new(self.u_vec().dot(d) * rcp_z, self.v_vec().dot(d) * rcp_z)
+    }
+
+    /// Returns the (unnormalized) direction corresponding to the specified
+    /// clip-space coordinates of this face. The inverse of `project`.
+    pub fn unproject<T: BaseFloat>(&self, p: Vector2<T>) -> Vector3<T> {
+        self.normal::<T>() + self.u_vec::<T>() * p.x + self.v_vec::<T>() * p.y
+    }
+
+    /// Returns the (unnormalized) direction of the center of the texel
+    /// `(x, y)` of this face, whose size is `size` texels.
+    pub fn texel_direction(&self, x: usize, y: usize, size: usize) -> Vector3<f32> {
+        self.unproject(Vector2::new(
+            texel_to_clip(x, size),
+            texel_to_clip(y, size),
+        ))
+    }
+}
+
+/// Converts a texel index to the clip-space coordinate of the texel's center.
+pub fn texel_to_clip(i: usize, size: usize) -> f32 {
+    (i as f32 + 0.5) * (2.0 / size as f32) - 1.0
+}
+
+/// Returns the solid angle subtended by the texel `(x, y)` of a cube face
+/// whose size is `size` texels.
+pub fn texel_solid_angle(x: usize, y: usize, size: usize) -> f32 {
+    fn f(x: f32, y: f32) -> f32 {
+        (x * y).atan2((x * x + y * y + 1.0).sqrt())
+    }
+    let scale = 2.0 / size as f32;
+    let x0 = x as f32 * scale - 1.0;
+    let y0 = y as f32 * scale - 1.0;
+    let x1 = x0 + scale;
+    let y1 = y0 + scale;
+    f(x0, y0) - f(x0, y1) - f(x1, y0) + f(x1, y1)
+}
+
+/// Converts a clip-space coordinate to the index of the texel containing it,
+/// clamped to the range `[0, size - 1]`.
+pub fn clip_to_texel(x: f32, size: usize) -> usize {
+    let i = (x + 1.0) * (0.5 * size as f32);
+    if !(i >= 1.0) {
+        // Also handles NaN
+        0
+    } else {
+        (i as usize).min(size - 1)
+    }
 }
 
 impl ops::Neg for CubeFace {
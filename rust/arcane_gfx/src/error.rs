@@ -0,0 +1,83 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Defines the error type shared by `arcane_gfx` and its dependents.
+use std::{fmt, error, result};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// The dimensions of an image are invalid for the operation, or do not
+    /// match the size of the buffer backing it.
+    DimensionMismatch,
+
+    /// The specified mip level does not exist.
+    InvalidMipLevel(usize),
+
+    /// The specified cube face index is not in the range `0..6`.
+    InvalidCubeFace(usize),
+
+    /// A memory allocation request could not be fulfilled.
+    AllocationFailed,
+
+    /// A matrix contains a NaN or infinite element.
+    NonFiniteMatrix,
+
+    /// The parameters of a `Camera` are out of range.
+    InvalidCamera,
+
+    /// A matrix is singular (or nearly so).
+    SingularMatrix,
+
+    /// The weight or exposure of a stamped frame is out of range.
+    InvalidFrameParams,
+
+    /// A camera response curve is not monotonic or out of range.
+    InvalidResponseCurve,
+
+    /// The parameters of a confidence curve are not finite and positive.
+    InvalidConfidenceCurve,
+
+    /// A pointer passed to a C API function is null.
+    InvalidArgument,
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Error::DimensionMismatch => write!(f, "image dimensions are invalid or mismatched"),
+            &Error::InvalidMipLevel(i) => write!(f, "mip level {} is out of range", i),
+            &Error::InvalidCubeFace(i) => write!(f, "cube face index {} is out of range", i),
+            &Error::AllocationFailed => write!(f, "memory allocation failed"),
+            &Error::NonFiniteMatrix => write!(f, "matrix contains a non-finite element"),
+            &Error::InvalidCamera => write!(f, "camera parameters are out of range"),
+            &Error::SingularMatrix => write!(f, "matrix is singular"),
+            &Error::InvalidFrameParams => write!(f, "frame weight or exposure is out of range"),
+            &Error::InvalidResponseCurve => write!(f, "camera response curve is invalid"),
+            &Error::InvalidConfidenceCurve => write!(f, "confidence curve is invalid"),
+            &Error::InvalidArgument => write!(f, "a required pointer is null"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self {
+            &Error::DimensionMismatch => "image dimensions are invalid or mismatched",
+            &Error::InvalidMipLevel(_) => "mip level is out of range",
+            &Error::InvalidCubeFace(_) => "cube face index is out of range",
+            &Error::AllocationFailed => "memory allocation failed",
+            &Error::NonFiniteMatrix => "matrix contains a non-finite element",
+            &Error::InvalidCamera => "camera parameters are out of range",
+            &Error::SingularMatrix => "matrix is singular",
+            &Error::InvalidFrameParams => "frame weight or exposure is out of range",
+            &Error::InvalidResponseCurve => "camera response curve is invalid",
+            &Error::InvalidConfidenceCurve => "confidence curve is invalid",
+            &Error::InvalidArgument => "a required pointer is null",
+        }
+    }
+}
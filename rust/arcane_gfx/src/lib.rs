@@ -9,10 +9,20 @@ extern crate cgmath;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod camera;
+pub mod convention;
+pub mod convolve;
 pub mod cubemap;
+pub mod error;
+pub mod lights;
+pub mod response;
+pub mod sg;
+pub mod sky;
 pub mod stamp;
 mod accessor;
 
+pub use error::{Error, Result};
+
 #[derive(Debug, Copy, Clone)]
 pub struct Image<T> {
     pub data: T,
@@ -42,4 +52,16 @@ impl<T> Image<T> {
             height: self.height,
         }
     }
+
+    /// Returns `Err(Error::DimensionMismatch)` if `data` is too small to hold
+    /// `width * height` elements.
+    pub fn check_len<E>(&self) -> Result<()>
+    where
+        T: AsRef<[E]>,
+    {
+        match self.width.checked_mul(self.height) {
+            Some(len) if len <= self.data.as_ref().len() => Ok(()),
+            _ => Err(Error::DimensionMismatch),
+        }
+    }
 }
@@ -0,0 +1,204 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Extraction of dominant light sources from a cube map.
+use std::cmp::Ordering;
+use cgmath::{Vector3, Vector4, Rad};
+use cgmath::prelude::*;
+
+use cubemap::{CUBE_FACES, texel_solid_angle};
+
+/// A light source approximating a bright lobe of an environment map.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    /// The (normalized) direction toward the light source.
+    pub direction: Vector3<f32>,
+
+    /// The radiance of the lobe above the ambient level, integrated over its
+    /// solid angle. This is the irradiance the light source contributes to a
+    /// surface facing it.
+    pub color: Vector3<f32>,
+
+    /// The RMS angular distance of the lobe's energy from `direction`.
+    pub spread: Rad<f32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightExtractionParams {
+    /// The maximum number of lights to extract.
+    pub max_lights: usize,
+
+    /// A lobe is only extracted if its peak luminance is at least this many
+    /// times the ambient luminance.
+    pub min_peak_ratio: f32,
+
+    /// The maximum angular radius of a lobe.
+    pub max_radius: Rad<f32>,
+
+    /// Subtract the extracted lobes from the input, leaving the residual
+    /// ambient lighting.
+    pub remove: bool,
+}
+
+impl Default for LightExtractionParams {
+    fn default() -> Self {
+        Self {
+            max_lights: 4,
+            min_peak_ratio: 4.0,
+            max_radius: Rad(0.4),
+            remove: false,
+        }
+    }
+}
+
+fn luminance(c: Vector3<f32>) -> f32 {
+    c.dot(Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Finds up to `params.max_lights` bright lobes in a cube map in the RGB +
+/// weight format (like the output of `stamp::stamp_camera_image`), in the
+/// decreasing order of the peak luminance. Texels with a zero weight or a
+/// non-finite value are ignored.
+///
+/// Each lobe consists of the texels within `params.max_radius` from the
+/// peak that are brighter than the ambient level (the average of the
+/// environment outside `params.max_radius` from the peak, excluding the
+/// lobes found so far). Only the excess over the
+/// ambient level is attributed to the light. If `params.remove` is set, the
+/// excess is subtracted from `faces`.
+pub fn extract_lights(
+    faces: &mut [&mut [Vector4<f32>]],
+    size: usize,
+    params: &LightExtractionParams,
+) -> Vec<DirectionalLight> {
+    assert_eq!(faces.len(), 6);
+    for face in faces.iter() {
+        assert!(face.len() >= size * size);
+    }
+
+    // Precompute the direction, solid angle, and radiance of each texel
+    struct Texel {
+        face: usize,
+        index: usize,
+        direction: Vector3<f32>,
+        solid_angle: f32,
+        color: Vector3<f32>,
+        luminance: f32,
+        /// The index of the lobe this texel belongs to.
+        lobe: Option<usize>,
+    }
+
+    let mut texels = Vec::with_capacity(size * size * 6);
+    for (face_i, face) in faces.iter().enumerate() {
+        for y in 0..size {
+            for x in 0..size {
+                let value = face[x + y * size];
+                if !(value.w > 0.0) {
+                    continue;
+                }
+                let color = value.truncate() / value.w;
+                if !(color.x.is_finite() && color.y.is_finite() && color.z.is_finite()) {
+                    continue;
+                }
+                texels.push(Texel {
+                    face: face_i,
+                    index: x + y * size,
+                    direction: CUBE_FACES[face_i].texel_direction(x, y, size).normalize(),
+                    solid_angle: texel_solid_angle(x, y, size),
+                    color,
+                    luminance: luminance(color),
+                    lobe: None,
+                });
+            }
+        }
+    }
+
+    let cos_max_radius = params.max_radius.0.cos();
+    let mut lights = Vec::new();
+
+    while lights.len() < params.max_lights {
+        let peak = texels
+            .iter()
+            .filter(|t| t.lobe.is_none())
+            .max_by(|a, b| {
+                a.luminance.partial_cmp(&b.luminance).unwrap_or(Ordering::Less)
+            });
+        let (peak_direction, peak_luminance) = match peak {
+            Some(t) => (t.direction, t.luminance),
+            None => break,
+        };
+
+        // Compute the ambient level from the texels neither belonging to any
+        // of the lobes found so far nor close to the peak
+        let mut ambient_sum = Vector3::zero();
+        let mut total_solid_angle = 0.0;
+        for t in texels.iter().filter(|t| {
+            t.lobe.is_none() && t.direction.dot(peak_direction) < cos_max_radius
+        })
+        {
+            ambient_sum += t.color * t.solid_angle;
+            total_solid_angle += t.solid_angle;
+        }
+        if !(total_solid_angle > 0.0) {
+            break;
+        }
+        let ambient = ambient_sum / total_solid_angle;
+        let ambient_luminance = luminance(ambient);
+
+        if !(peak_luminance > ambient_luminance * params.min_peak_ratio && peak_luminance > 0.0) {
+            break;
+        }
+
+        // Gather the excess energy of the lobe
+        let mut color = Vector3::zero();
+        let mut weighted_direction = Vector3::zero();
+        for t in texels.iter_mut() {
+            if t.lobe.is_some() || t.direction.dot(peak_direction) < cos_max_radius ||
+                t.luminance <= ambient_luminance
+            {
+                continue;
+            }
+            let excess = t.color - ambient;
+            color += excess * t.solid_angle;
+            weighted_direction += t.direction * ((t.luminance - ambient_luminance) * t.solid_angle);
+            t.lobe = Some(lights.len());
+        }
+
+        let direction = if weighted_direction.magnitude2() > 0.0 {
+            weighted_direction.normalize()
+        } else {
+            peak_direction
+        };
+
+        // Compute the spread and remove the lobe
+        let mut sum_sq_angle = 0.0;
+        let mut sum_weight = 0.0;
+        for t in texels.iter().filter(|t| t.lobe == Some(lights.len())) {
+            let weight = (t.luminance - ambient_luminance) * t.solid_angle;
+            let angle = t.direction.dot(direction).max(-1.0).min(1.0).acos();
+            sum_sq_angle += angle * angle * weight;
+            sum_weight += weight;
+
+            if params.remove {
+                let w = faces[t.face][t.index].w;
+                let residual = ambient * w;
+                faces[t.face][t.index] = residual.extend(w);
+            }
+        }
+
+        lights.push(DirectionalLight {
+            direction,
+            color,
+            spread: Rad(if sum_weight > 0.0 {
+                (sum_sq_angle / sum_weight).sqrt()
+            } else {
+                0.0
+            }),
+        });
+    }
+
+    lights
+}
@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Camera response curves, which map 8-bit pixel values to linear radiance.
+use std::fmt;
+
+use {Error, Result};
+
+/// The linear value corresponding to the pixel value `255`. The linear
+/// values are scaled by this so that the gamma 2 curve maps a pixel value
+/// `x` to exactly `x²`.
+pub const LINEAR_WHITE: f32 = 65025.0;
+
+/// Maps 8-bit pixel values to linear values in the range
+/// `[0, LINEAR_WHITE]`.
+#[derive(Copy, Clone)]
+pub struct ResponseCurve {
+    table: [f32; 256],
+}
+
+impl ResponseCurve {
+    /// Constructs a response curve of the form `x^gamma`.
+    pub fn gamma(gamma: f32) -> Self {
+        Self::from_fn(|x| x.powf(gamma as f64))
+    }
+
+    /// Constructs a response curve from the sRGB transfer function.
+    pub fn srgb() -> Self {
+        Self::from_fn(|x| if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        })
+    }
+
+    /// Constructs a response curve from a calibrated table of 256 linear
+    /// values in the range `[0, 1]`, indexed by the pixel value.
+    ///
+    /// Returns `Err(Error::InvalidResponseCurve)` if the table does not have
+    /// exactly 256 elements or is not a non-decreasing sequence of finite
+    /// values in the range.
+    pub fn from_table(table: &[f32]) -> Result<Self> {
+        if table.len() != 256 {
+            return Err(Error::InvalidResponseCurve);
+        }
+        if !table.iter().all(|&x| x >= 0.0 && x <= 1.0) ||
+            !table.windows(2).all(|x| x[0] <= x[1])
+        {
+            return Err(Error::InvalidResponseCurve);
+        }
+        Ok(Self::from_fn(|x| table[(x * 255.0).round() as usize] as f64))
+    }
+
+    /// Constructs a response curve from a table of 256 linear values in the
+    /// range `[0, LINEAR_WHITE]`, such as one returned by `table`.
+    ///
+    /// Returns `Err(Error::InvalidResponseCurve)` under the same conditions
+    /// as `from_table`.
+    pub fn from_linear_table(table: &[f32]) -> Result<Self> {
+        if table.len() != 256 {
+            return Err(Error::InvalidResponseCurve);
+        }
+        if !table.iter().all(|&x| x >= 0.0 && x <= LINEAR_WHITE) ||
+            !table.windows(2).all(|x| x[0] <= x[1])
+        {
+            return Err(Error::InvalidResponseCurve);
+        }
+        let mut result = Self { table: [0.0; 256] };
+        result.table.copy_from_slice(table);
+        Ok(result)
+    }
+
+    fn from_fn<F: FnMut(f64) -> f64>(mut f: F) -> Self {
+        let mut table = [0.0; 256];
+        for (i, x) in table.iter_mut().enumerate() {
+            *x = (f(i as f64 / 255.0) * LINEAR_WHITE as f64) as f32;
+        }
+        Self { table }
+    }
+
+    /// Returns the linear value in the range `[0, LINEAR_WHITE]` for the
+    /// specified pixel value.
+    pub fn linearize(&self, x: u8) -> f32 {
+        self.table[x as usize]
+    }
+
+    /// Returns the lookup table indexed by the pixel value.
+    pub fn table(&self) -> &[f32; 256] {
+        &self.table
+    }
+}
+
+impl Default for ResponseCurve {
+    /// Returns the gamma 2 curve.
+    fn default() -> Self {
+        Self::gamma(2.0)
+    }
+}
+
+impl PartialEq for ResponseCurve {
+    fn eq(&self, other: &Self) -> bool {
+        self.table[..] == other.table[..]
+    }
+}
+
+impl fmt::Debug for ResponseCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.table.iter()).finish()
+    }
+}
+
+/// Specifies how the reliability of a pixel is derived from its value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelWeighting {
+    /// All pixels have the same weight.
+    Uniform,
+
+    /// Pixels are down-weighted as their brightest channel approaches zero
+    /// (where they are dominated by noise and quantization errors) or 255
+    /// (where they are likely to be clipped). This is suitable for merging
+    /// frames with different exposures.
+    Hat,
+}
+
+impl PixelWeighting {
+    /// Returns the weight of a pixel in the range `(0, 1]` given the value
+    /// of its brightest channel.
+    pub fn weight(&self, max_channel: u8) -> f32 {
+        match self {
+            &PixelWeighting::Uniform => 1.0,
+            &PixelWeighting::Hat => {
+                // Pixels are never completely ignored so that regions only
+                // observed with clipped or black pixels are still filled
+                let t = max_channel as f32 * (2.0 / 255.0) - 1.0;
+                (1.0 - t.powi(8)).max(1.0 / 256.0)
+            }
+        }
+    }
+
+    /// Returns the lookup table of `weight` indexed by the value of the
+    /// brightest channel.
+    pub fn table(&self) -> [f32; 256] {
+        let mut table = [0.0; 256];
+        for (i, x) in table.iter_mut().enumerate() {
+            *x = self.weight(i as u8);
+        }
+        table
+    }
+}
+
+impl Default for PixelWeighting {
+    fn default() -> Self {
+        PixelWeighting::Uniform
+    }
+}
@@ -0,0 +1,214 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Spherical Gaussian (SG) approximation of environment maps.
+use std::f64::consts::PI;
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use cubemap::{CUBE_FACES, texel_solid_angle};
+
+/// The maximum number of sweeps done by `solve_nonnegative`.
+const NNLS_MAX_ITERATIONS: usize = 1000;
+
+/// A spherical Gaussian `G(d) = amplitude · exp(sharpness · (axis·d - 1))`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SphericalGaussian {
+    /// The (normalized) direction of the lobe.
+    pub axis: Vector3<f32>,
+    pub sharpness: f32,
+    pub amplitude: Vector3<f32>,
+}
+
+impl SphericalGaussian {
+    pub fn evaluate(&self, d: Vector3<f32>) -> Vector3<f32> {
+        self.amplitude * (self.sharpness * (self.axis.dot(d) - 1.0)).exp()
+    }
+}
+
+/// Evaluates the sum of `lobes` in the direction `d`.
+pub fn evaluate_sum(lobes: &[SphericalGaussian], d: Vector3<f32>) -> Vector3<f32> {
+    lobes.iter().fold(Vector3::zero(), |sum, lobe| sum + lobe.evaluate(d))
+}
+
+/// Returns `count` directions distributed evenly over the sphere (a
+/// Fibonacci lattice).
+pub fn fibonacci_axes(count: usize) -> Vec<Vector3<f32>> {
+    let golden_angle = PI * (3.0 - 5.0f64.sqrt());
+    (0..count)
+        .map(|i| {
+            let z = 1.0 - (i as f64 + 0.5) * (2.0 / count as f64);
+            let r = (1.0 - z * z).sqrt();
+            let phi = golden_angle * i as f64;
+            Vector3::new((r * phi.cos()) as f32, z as f32, (r * phi.sin()) as f32)
+        })
+        .collect()
+}
+
+/// Returns the sharpness at which `count` evenly distributed lobes fall off
+/// to one half midway between neighboring lobes.
+pub fn default_sharpness(count: usize) -> f32 {
+    // The average angular distance between neighboring lobes
+    let spacing = (4.0 * PI / count.max(1) as f64).sqrt();
+    (2.0f64.ln() / (1.0 - (spacing * 0.5).cos())) as f32
+}
+
+/// Fits `num_lobes` spherical Gaussians to a cube map in the RGB + weight
+/// format. Texels with a zero weight are ignored.
+///
+/// Only the amplitudes are fitted. The lobes have the fixed axes given by
+/// `fibonacci_axes` and the sharpness given by `default_sharpness`, and
+/// their amplitudes are found by a (slightly regularized) non-negative least
+/// squares fit weighted by the solid angle, so the sum of the lobes never
+/// evaluates to a negative radiance.
+pub fn fit_spherical_gaussians(
+    faces: &[&[Vector4<f32>]],
+    size: usize,
+    num_lobes: usize,
+) -> Vec<SphericalGaussian> {
+    assert_eq!(faces.len(), 6);
+    for face in faces.iter() {
+        assert!(face.len() >= size * size);
+    }
+
+    let axes = fibonacci_axes(num_lobes);
+    let sharpness = default_sharpness(num_lobes);
+    let n = num_lobes;
+
+    // Accumulate the normal equations `AᵀWA x = AᵀWb`
+    let mut ata = vec![0.0f64; n * n];
+    let mut atb = vec![Vector3::<f64>::zero(); n];
+    let mut basis = vec![0.0f64; n];
+    let mut total_solid_angle = 0.0;
+    for (face_i, face) in faces.iter().enumerate() {
+        for y in 0..size {
+            for x in 0..size {
+                let value = face[x + y * size];
+                if !(value.w > 0.0) {
+                    continue;
+                }
+                let color = (value.truncate() / value.w).cast::<f64>();
+                let d = CUBE_FACES[face_i].texel_direction(x, y, size).normalize();
+                let solid_angle = texel_solid_angle(x, y, size) as f64;
+                total_solid_angle += solid_angle;
+
+                for (b, axis) in basis.iter_mut().zip(axes.iter()) {
+                    *b = (sharpness * (axis.dot(d) - 1.0)).exp() as f64;
+                }
+                for i in 0..n {
+                    let bi = basis[i] * solid_angle;
+                    atb[i] += color * bi;
+                    for j in 0..n {
+                        ata[i * n + j] += bi * basis[j];
+                    }
+                }
+            }
+        }
+    }
+
+    // The regularization keeps the system well-conditioned when some lobes
+    // are not covered by observed texels
+    let lambda = 1.0e-4 * total_solid_angle.max(1.0e-10) / n.max(1) as f64;
+    for i in 0..n {
+        ata[i * n + i] += lambda;
+    }
+
+    // Start from the unconstrained solution and then enforce the constraint
+    let amplitudes = solve_cholesky(&mut ata.clone(), &atb, n);
+    let amplitudes = solve_nonnegative(&ata, &atb, amplitudes, n);
+
+    axes.iter()
+        .zip(amplitudes.iter())
+        .map(|(&axis, amplitude)| {
+            SphericalGaussian {
+                axis,
+                sharpness,
+                amplitude: amplitude.cast(),
+            }
+        })
+        .collect()
+}
+
+/// Solves `Ax = b` for a symmetric positive definite matrix `A` (row-major),
+/// destroying `a` in the process.
+fn solve_cholesky(a: &mut [f64], b: &[Vector3<f64>], n: usize) -> Vec<Vector3<f64>> {
+    // Decompose `A = LLᵀ`, storing `L` in the lower triangle of `a`
+    for j in 0..n {
+        let mut d = a[j * n + j];
+        for k in 0..j {
+            d -= a[j * n + k] * a[j * n + k];
+        }
+        let d = d.max(1.0e-30).sqrt();
+        a[j * n + j] = d;
+        for i in j + 1..n {
+            let mut s = a[i * n + j];
+            for k in 0..j {
+                s -= a[i * n + k] * a[j * n + k];
+            }
+            a[i * n + j] = s / d;
+        }
+    }
+
+    // Forward substitution (`Ly = b`)
+    let mut y = b.to_vec();
+    for i in 0..n {
+        for k in 0..i {
+            let t = y[k] * a[i * n + k];
+            y[i] -= t;
+        }
+        y[i] /= a[i * n + i];
+    }
+
+    // Back substitution (`Lᵀx = y`)
+    let mut x = y;
+    for i in (0..n).rev() {
+        for k in i + 1..n {
+            let t = x[k] * a[k * n + i];
+            x[i] -= t;
+        }
+        x[i] /= a[i * n + i];
+    }
+
+    x
+}
+
+/// Minimizes `‖Ax - b‖` (in the sense of the normal equations `Ax = b`,
+/// `A` being symmetric positive definite) subject to `x ≥ 0` for each
+/// component, by projected Gauss-Seidel iterations starting from `x`.
+fn solve_nonnegative(
+    a: &[f64],
+    b: &[Vector3<f64>],
+    mut x: Vec<Vector3<f64>>,
+    n: usize,
+) -> Vec<Vector3<f64>> {
+    fn max0(x: Vector3<f64>) -> Vector3<f64> {
+        Vector3::new(x.x.max(0.0), x.y.max(0.0), x.z.max(0.0))
+    }
+
+    for x in x.iter_mut() {
+        *x = max0(*x);
+    }
+
+    for _ in 0..NNLS_MAX_ITERATIONS {
+        let (mut max_delta, mut max_x) = (0.0f64, 0.0f64);
+        for i in 0..n {
+            let mut r = b[i];
+            for j in 0..n {
+                r -= x[j] * a[i * n + j];
+            }
+            let new_x = max0(x[i] + r / a[i * n + i]);
+            let delta = new_x - x[i];
+            max_delta = max_delta.max(delta.x.abs()).max(delta.y.abs()).max(delta.z.abs());
+            max_x = max_x.max(new_x.x).max(new_x.y).max(new_x.z);
+            x[i] = new_x;
+        }
+        if max_delta <= max_x * 1.0e-7 {
+            break;
+        }
+    }
+
+    x
+}
@@ -0,0 +1,282 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Analytic sky model based on "A Practical Analytic Model for Daylight"
+//! (Preetham et al. 1999).
+//!
+//! The model only describes the scattered skylight; the sun disk itself is
+//! not included. The ground is a Lambertian plane lit by the sky.
+//!
+//! The model outputs the radiance in kcd/m², which is multiplied by
+//! `SkyParams::scale`. `envmapgen` (e.g., its fallback environment map)
+//! instead expects `1.0` to correspond to the pixel value `255` at the
+//! reference exposure, which depends on the camera. A camera exposing for
+//! the sky maps roughly its zenith to that value, so a reasonable choice is
+//! `scale = 1 / zenith_luminance` where `zenith_luminance` is the value
+//! returned by `PreethamSky::zenith_luminance`.
+use std::f32::consts::PI;
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use Image;
+use cubemap::CUBE_FACES;
+
+/// The range of the turbidity the model is fitted to.
+pub const MIN_TURBIDITY: f32 = 1.7;
+pub const MAX_TURBIDITY: f32 = 10.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkyParams {
+    /// The direction toward the sun (+Y is up). The sun elevation is
+    /// clamped to the horizon since the model is only valid in daylight.
+    pub sun_direction: Vector3<f32>,
+
+    /// The atmospheric turbidity, ranging from `2` (very clear) to `10`
+    /// (hazy). Clamped to `[MIN_TURBIDITY, MAX_TURBIDITY]`.
+    pub turbidity: f32,
+
+    /// The linear RGB albedo of the ground.
+    pub ground_albedo: Vector3<f32>,
+
+    /// The factor applied to the output radiance. `1` (the default) yields
+    /// the radiance in kcd/m².
+    pub scale: f32,
+}
+
+impl Default for SkyParams {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vector3::new(0.4, 0.7, -0.6).normalize(),
+            turbidity: 3.0,
+            ground_albedo: Vector3::new(0.3, 0.3, 0.3),
+            scale: 1.0,
+        }
+    }
+}
+
+/// The coefficients `A`–`E` of the Perez sky luminance distribution.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    fn new(t: f32, coefs: [[f32; 2]; 5]) -> Self {
+        let mut x = [0.0; 5];
+        for (x, c) in x.iter_mut().zip(coefs.iter()) {
+            *x = c[0] * t + c[1];
+        }
+        Perez(x)
+    }
+
+    /// `cos_theta` is the cosine of the zenith angle of the view direction,
+    /// and `gamma` is the angle between the view direction and the sun.
+    fn evaluate(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let (a, b, c, d, e) = (self.0[0], self.0[1], self.0[2], self.0[3], self.0[4]);
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The Preetham sky model evaluated for a specific `SkyParams`.
+#[derive(Debug, Clone)]
+pub struct PreethamSky {
+    params: SkyParams,
+    sun_direction: Vector3<f32>,
+
+    /// The Perez distributions of `Y`, `x`, and `y`.
+    perez: [Perez; 3],
+
+    /// The zenith values of `Y`, `x`, and `y` divided by the Perez
+    /// distribution at the zenith.
+    zenith: [f32; 3],
+
+    zenith_luminance: f32,
+
+    ground_radiance: Vector3<f32>,
+}
+
+impl PreethamSky {
+    pub fn new(params: &SkyParams) -> Self {
+        let t = params.turbidity.max(MIN_TURBIDITY).min(MAX_TURBIDITY);
+
+        let mut sun_direction = params.sun_direction.normalize();
+        if !(sun_direction.y >= 0.0) {
+            // Also handles NaN
+            let horizontal = Vector3::new(sun_direction.x, 0.0, sun_direction.z);
+            sun_direction = if horizontal.magnitude2() > 0.0 {
+                horizontal.normalize()
+            } else {
+                Vector3::new(1.0, 0.0, 0.0)
+            };
+        }
+        let theta_s = sun_direction.y.min(1.0).acos();
+
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    [0.1787, -1.4630],
+                    [-0.3554, 0.4275],
+                    [-0.0227, 5.3251],
+                    [0.1206, -2.5771],
+                    [-0.0670, 0.3703],
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    [-0.0193, -0.2592],
+                    [-0.0665, 0.0008],
+                    [-0.0004, 0.2125],
+                    [-0.0641, -0.8989],
+                    [-0.0033, 0.0452],
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    [-0.0167, -0.2608],
+                    [-0.0950, 0.0092],
+                    [-0.0079, 0.2102],
+                    [-0.0441, -1.6537],
+                    [-0.0109, 0.0529],
+                ],
+            ),
+        ];
+
+        // The zenith luminance (in kcd/m²) and chromaticity
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f32; 4]; 3]| {
+            let poly = |c: [f32; 4]| ((c[0] * theta_s + c[1]) * theta_s + c[2]) * theta_s + c[3];
+            (poly(m[0]) * t + poly(m[1])) * t + poly(m[2])
+        };
+        let zenith_cx = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_cy = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let mut zenith = [zenith_y, zenith_cx, zenith_cy];
+        for (z, p) in zenith.iter_mut().zip(perez.iter()) {
+            *z /= p.evaluate(1.0, theta_s);
+        }
+
+        let mut sky = Self {
+            params: *params,
+            sun_direction,
+            perez,
+            zenith,
+            zenith_luminance: zenith_y,
+            ground_radiance: Vector3::zero(),
+        };
+
+        // The radiance of a Lambertian ground is `albedo · E / π` where `E`
+        // is the irradiance from the sky
+        let irradiance = sky.sky_irradiance();
+        sky.ground_radiance = params.ground_albedo.mul_element_wise(irradiance) / PI;
+
+        sky
+    }
+
+    pub fn params(&self) -> &SkyParams {
+        &self.params
+    }
+
+    /// Returns the luminance of the sky at the zenith in kcd/m², regardless
+    /// of `SkyParams::scale`.
+    pub fn zenith_luminance(&self) -> f32 {
+        self.zenith_luminance
+    }
+
+    /// Returns the radiance of the ground in the same unit as `evaluate`.
+    pub fn ground_radiance(&self) -> Vector3<f32> {
+        self.ground_radiance
+    }
+
+    /// Evaluates the linear sRGB radiance (in kcd/m², multiplied by
+    /// `SkyParams::scale`) in the normalized direction `d`.
+    pub fn evaluate(&self, d: Vector3<f32>) -> Vector3<f32> {
+        if d.y < 0.0 {
+            self.ground_radiance
+        } else {
+            self.evaluate_sky(d)
+        }
+    }
+
+    fn evaluate_sky(&self, d: Vector3<f32>) -> Vector3<f32> {
+        // Avoid the division by zero in the Perez function at the horizon
+        let cos_theta = d.y.max(1.0e-3);
+        let gamma = d.dot(self.sun_direction).max(-1.0).min(1.0).acos();
+
+        let mut x = [0.0; 3];
+        for ((x, p), z) in x.iter_mut().zip(self.perez.iter()).zip(self.zenith.iter()) {
+            *x = p.evaluate(cos_theta, gamma) * z;
+        }
+        let (luminance, cx, cy) = (x[0] * self.params.scale, x[1], x[2]);
+        if !(cy > 0.0) {
+            return Vector3::zero();
+        }
+
+        // xyY → XYZ → linear sRGB
+        let xyz = Vector3::new(cx / cy * luminance, luminance, (1.0 - cx - cy) / cy * luminance);
+        Vector3::new(
+            (3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z).max(0.0),
+            (-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z).max(0.0),
+            (0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z).max(0.0),
+        )
+    }
+
+    /// Computes the irradiance on a horizontal surface from the sky by a
+    /// numerical integration over the upper hemisphere.
+    fn sky_irradiance(&self) -> Vector3<f32> {
+        const NUM_ELEVATIONS: usize = 16;
+        const NUM_AZIMUTHS: usize = 32;
+
+        let mut sum = Vector3::zero();
+        for i in 0..NUM_ELEVATIONS {
+            // Integrate over `u = cos²θ` so that each sample carries the same
+            // weight (`cosθ dω = π du` after the azimuthal integration)
+            let u = (i as f32 + 0.5) / NUM_ELEVATIONS as f32;
+            let cos_theta = u.sqrt();
+            let sin_theta = (1.0 - u).sqrt();
+            for k in 0..NUM_AZIMUTHS {
+                let phi = (k as f32 + 0.5) * (2.0 * PI / NUM_AZIMUTHS as f32);
+                let d = Vector3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                sum += self.evaluate_sky(d);
+            }
+        }
+        sum * (PI / (NUM_ELEVATIONS * NUM_AZIMUTHS) as f32)
+    }
+
+    /// Renders the model into a cube map of the size `size`, consisting of
+    /// six faces in the `CUBE_FACES` order. The texels are in the RGB +
+    /// weight format (the weight is always `1`), which the spherical blur
+    /// pyramid accepts as is, and are evaluated at their centers.
+    pub fn render_cube_map(&self, size: usize) -> Vec<Image<Vec<Vector4<f32>>>> {
+        CUBE_FACES
+            .iter()
+            .map(|face| {
+                let mut data = Vec::with_capacity(size * size);
+                for y in 0..size {
+                    for x in 0..size {
+                        let d = face.texel_direction(x, y, size).normalize();
+                        data.push(self.evaluate(d).extend(1.0));
+                    }
+                }
+                Image {
+                    data,
+                    width: size,
+                    height: size,
+                }
+            })
+            .collect()
+    }
+}
@@ -4,69 +4,374 @@
  * This file is a part of ARcane. Please read the license text that
  * comes with the source code for use conditions.
  */
+use std::ops::Range;
 use cgmath::{vec4, Vector4, Matrix4};
-use Image;
+use cgmath::prelude::*;
+use {Image, Error, Result};
+use convention::CubeMapConvention;
+use response::{ResponseCurve, PixelWeighting};
+
+/// Specifies which corner of an image its first pixel is located at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageOrigin {
+    TopLeft,
+    BottomLeft,
+}
+
+/// Specifies how stamped texels are combined with the existing contents of
+/// the destination image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite the existing texels.
+    Replace,
+
+    /// Add to the existing texels. Since the output is in the RGB + weight
+    /// format, this computes a weighted sum of overlapping images.
+    Add,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StampOptions {
+    /// The convention of the world space the source view-projection matrix
+    /// was defined in. The destination is always in the canonical convention.
+    pub convention: CubeMapConvention,
+
+    /// The row order of the source image.
+    pub src_origin: ImageOrigin,
+
+    /// The destination image is divided into tiles of this size (in texels),
+    /// and tiles that are entirely outside the source image's view frustum
+    /// are skipped. Zero disables the culling.
+    pub tile_size: usize,
+
+    /// Multiplies the (linearized) color values of the source image, e.g., to
+    /// compensate for its exposure.
+    pub scale: f32,
+
+    /// The weight of the stamped texels. The color values are premultiplied
+    /// by this value.
+    pub weight: f32,
+
+    pub blend: BlendMode,
+
+    /// Maps the pixel values of the source image to linear values.
+    pub response: ResponseCurve,
+
+    /// Scales the weight of each stamped texel based on the value of the
+    /// source pixel.
+    pub weighting: PixelWeighting,
+}
+
+impl Default for StampOptions {
+    fn default() -> Self {
+        Self {
+            convention: CubeMapConvention::OPENGL,
+            src_origin: ImageOrigin::TopLeft,
+            tile_size: 16,
+            scale: 1.0,
+            weight: 1.0,
+            blend: BlendMode::Replace,
+            response: ResponseCurve::default(),
+            weighting: PixelWeighting::Uniform,
+        }
+    }
+}
 
 pub fn stamp_camera_image(
     dst_image: &mut Image<&mut [Vector4<f32>]>,
     dst_inv_view_proj_mat: Matrix4<f32>,
     src_image: &Image<&[Vector4<u8>]>,
     src_view_proj_mat: Matrix4<f32>,
-) {
-    assert!(dst_image.data.len() >= dst_image.width * dst_image.height);
-    assert!(src_image.data.len() >= src_image.width * src_image.height);
+) -> usize {
+    try_stamp_camera_image(
+        dst_image,
+        dst_inv_view_proj_mat,
+        src_image,
+        src_view_proj_mat,
+    ).unwrap()
+}
 
-    let m = src_view_proj_mat * dst_inv_view_proj_mat;
-    let v_base = m * vec4(0.0, 0.0, 1.0, 1.0);
-    let v_u = m * vec4(1.0, 0.0, 0.0, 0.0);
-    let v_v = m * vec4(0.0, 1.0, 0.0, 0.0);
+/// Fallible version of `stamp_camera_image`.
+///
+/// Returns the number of texels written to `dst_image`.
+pub fn try_stamp_camera_image(
+    dst_image: &mut Image<&mut [Vector4<f32>]>,
+    dst_inv_view_proj_mat: Matrix4<f32>,
+    src_image: &Image<&[Vector4<u8>]>,
+    src_view_proj_mat: Matrix4<f32>,
+) -> Result<usize> {
+    try_stamp_camera_image_with_options(
+        dst_image,
+        dst_inv_view_proj_mat,
+        src_image,
+        src_view_proj_mat,
+        &StampOptions::default(),
+    )
+}
 
-    let src_width = src_image.width;
-    let src_height = src_image.height;
-    let src_data = &src_image.data[0..src_width * src_height];
+/// `try_stamp_camera_image` with a non-default source convention, image
+/// origin, or blending parameters.
+pub fn try_stamp_camera_image_with_options(
+    dst_image: &mut Image<&mut [Vector4<f32>]>,
+    dst_inv_view_proj_mat: Matrix4<f32>,
+    src_image: &Image<&[Vector4<u8>]>,
+    src_view_proj_mat: Matrix4<f32>,
+    options: &StampOptions,
+) -> Result<usize> {
+    dst_image.check_len()?;
+    src_image.check_len()?;
+    if src_image.width == 0 || src_image.height == 0 {
+        return Err(Error::DimensionMismatch);
+    }
+
+    if !is_matrix_finite(&dst_inv_view_proj_mat) || !is_matrix_finite(&src_view_proj_mat) {
+        return Err(Error::NonFiniteMatrix);
+    }
+    if is_matrix_singular(&dst_inv_view_proj_mat) || is_matrix_singular(&src_view_proj_mat) {
+        return Err(Error::SingularMatrix);
+    }
+
+    let m = src_view_proj_mat * Matrix4::from(options.convention.from_canonical()) *
+        dst_inv_view_proj_mat;
 
     let dst_width = dst_image.width;
     let dst_height = dst_image.height;
+    if dst_width == 0 || dst_height == 0 {
+        return Ok(0);
+    }
+
+    let raster = Rasterizer {
+        v_base: m * vec4(0.0, 0.0, 1.0, 1.0),
+        v_u: m * vec4(1.0, 0.0, 0.0, 0.0),
+        v_v: m * vec4(0.0, 1.0, 0.0, 0.0),
+        flip_y: options.src_origin == ImageOrigin::TopLeft,
+        src_image: Image {
+            data: &src_image.data[0..src_image.width * src_image.height],
+            width: src_image.width,
+            height: src_image.height,
+        },
+        dst_width,
+        dst_height,
+        linear_table: {
+            let color_scale = options.scale * options.weight;
+            let mut table = *options.response.table();
+            for x in table.iter_mut() {
+                *x *= color_scale;
+            }
+            table
+        },
+        pixel_weight_table: options.weighting.table(),
+        weight: options.weight,
+        blend: options.blend,
+    };
     let dst_data = &mut dst_image.data[0..dst_width * dst_height];
 
-    for y in 0..dst_image.height {
-        // The Y coordinate in the dstination image's clip space
-        let cs1_y = (y as f32 + 0.5) * (2.0 / dst_height as f32) - 1.0;
+    let tile_size = options.tile_size;
+    if tile_size == 0 {
+        return Ok(raster.stamp_region(dst_data, 0..dst_width, 0..dst_height));
+    }
+
+    // Compute the outcodes of the tile corners
+    let num_tiles_x = (dst_width + tile_size - 1) / tile_size;
+    let num_tiles_y = (dst_height + tile_size - 1) / tile_size;
+    let outcodes: Vec<u8> = (0..num_tiles_y + 1)
+        .flat_map(|tile_y| {
+            let y = (tile_y * tile_size).min(dst_height);
+            let raster = &raster;
+            (0..num_tiles_x + 1).map(move |tile_x| {
+                let x = (tile_x * tile_size).min(dst_width);
+                raster.outcode(x as f32, y as f32)
+            })
+        })
+        .collect();
 
-        let line1 = v_base - v_u + v_v * cs1_y;
-        let line2 = v_base + v_u + v_v * cs1_y;
+    // Cull the whole image first (this is the common case for cube faces
+    // facing away from the camera). A convex region is outside a half-space
+    // if all of its corners are.
+    if outcodes.iter().fold(!0, |x, y| x & y) != 0 {
+        return Ok(0);
+    }
 
-        if line1.w <= 0.0 && line2.w <= 0.0 {
-            // Cull the scanline
-            continue;
+    let mut num_texels = 0;
+    for tile_y in 0..num_tiles_y {
+        let y_range = tile_y * tile_size..((tile_y + 1) * tile_size).min(dst_height);
+        let row1 = &outcodes[tile_y * (num_tiles_x + 1)..][0..num_tiles_x + 1];
+        let row2 = &outcodes[(tile_y + 1) * (num_tiles_x + 1)..][0..num_tiles_x + 1];
+
+        // Find the span of the tiles that are not culled in this row of
+        // tiles. Clipping each scanline against the frustum is faster than
+        // splitting it at tile boundaries.
+        let mut x1 = dst_width;
+        let mut x2 = 0;
+        for tile_x in 0..num_tiles_x {
+            let code = row1[tile_x] & row1[tile_x + 1] & row2[tile_x] & row2[tile_x + 1];
+            if code == 0 {
+                x1 = x1.min(tile_x * tile_size);
+                x2 = ((tile_x + 1) * tile_size).min(dst_width);
+            }
         }
 
-        let mut cs2 = line1;
-        let dcs2 = (line2 - line1) * (1.0 / dst_width as f32);
-        cs2 += dcs2 * 0.5;
-
-        let out_line = &mut dst_data[y * dst_width..(y + 1) * dst_width];
-
-        for x in 0..dst_width {
-            if cs2.w > 0.0 && cs2.x.abs() < cs2.w && cs2.y.abs() < cs2.w {
-                // Perform perspective division & map them to the source image's viewport space
-                let rcp_w = 1.0 / cs2.w;
-                let vp_x = ((cs2.x * rcp_w + 1.0) * (0.5 * src_width as f32)) as usize;
-                let vp_y = ((cs2.y * rcp_w + 1.0) * (0.5 * src_height as f32)) as usize;
-
-                // Flip the Y coordinate
-                let vp_y = (src_height - 1).wrapping_sub(vp_y);
-
-                if let Some(src) = src_data.get(vp_x + vp_y * src_width) {
-                    let mut pixel = src.cast::<u32>();
-                    pixel.x *= pixel.x;
-                    pixel.y *= pixel.y;
-                    pixel.z *= pixel.z;
-                    pixel.w = 1;
-                    out_line[x] = pixel.cast();
+        if x1 < x2 {
+            num_texels += raster.stamp_region(dst_data, x1..x2, y_range);
+        }
+    }
+
+    Ok(num_texels)
+}
+
+struct Rasterizer<'a> {
+    /// The source clip space coordinates at the center of the destination
+    /// image.
+    v_base: Vector4<f32>,
+
+    /// The derivatives of the source clip space coordinates with respect to
+    /// the destination clip space X and Y coordinates.
+    v_u: Vector4<f32>,
+    v_v: Vector4<f32>,
+
+    flip_y: bool,
+    src_image: Image<&'a [Vector4<u8>]>,
+    dst_width: usize,
+    dst_height: usize,
+
+    /// Maps a pixel value to a linear value multiplied by the color scale.
+    linear_table: [f32; 256],
+
+    /// Maps the value of the brightest channel to the pixel weight.
+    pixel_weight_table: [f32; 256],
+
+    weight: f32,
+    blend: BlendMode,
+}
+
+impl<'a> Rasterizer<'a> {
+    /// Returns the source clip space coordinates at the specified point of the
+    /// destination image's viewport space.
+    fn clip_coords(&self, x: f32, y: f32) -> Vector4<f32> {
+        self.v_base + self.v_u * (x * (2.0 / self.dst_width as f32) - 1.0) +
+            self.v_v * (y * (2.0 / self.dst_height as f32) - 1.0)
+    }
+
+    /// Returns a bit mask of the source view frustum's planes the specified
+    /// point of the destination image's viewport space is outside of.
+    fn outcode(&self, x: f32, y: f32) -> u8 {
+        let c = self.clip_coords(x, y);
+        ((c.w <= 0.0) as u8) | (((c.w - c.x) <= 0.0) as u8) << 1 |
+            (((c.w + c.x) <= 0.0) as u8) << 2 | (((c.w - c.y) <= 0.0) as u8) << 3 |
+            (((c.w + c.y) <= 0.0) as u8) << 4
+    }
+
+    /// Stamps the specified region of the destination image. Returns the
+    /// number of texels written.
+    fn stamp_region(
+        &self,
+        dst_data: &mut [Vector4<f32>],
+        x_range: Range<usize>,
+        y_range: Range<usize>,
+    ) -> usize {
+        let src_width = self.src_image.width;
+        let src_height = self.src_image.height;
+        let src_data = self.src_image.data;
+        let dst_width = self.dst_width;
+
+        let mut num_texels = 0;
+
+        for y in y_range {
+            // The Y coordinate in the dstination image's clip space
+            let cs1_y = (y as f32 + 0.5) * (2.0 / self.dst_height as f32) - 1.0;
+
+            let line1 = self.v_base - self.v_u + self.v_v * cs1_y;
+            let line2 = self.v_base + self.v_u + self.v_v * cs1_y;
+
+            // Find the range of texels inside the source image's view frustum
+            let (t1, t2) = match clip_line(line1, line2) {
+                Some(x) => x,
+                None => continue, // Cull the scanline
+            };
+            let x1 = (t1 * dst_width as f32 - 0.5).ceil().max(0.0) as usize;
+            let x2 = ((t2 * dst_width as f32 - 0.5).floor() + 1.0).max(0.0) as usize;
+            let x1 = x1.max(x_range.start);
+            let x2 = x2.min(x_range.end);
+
+            let dcs2 = (line2 - line1) * (1.0 / dst_width as f32);
+            let mut cs2 = line1 + dcs2 * (x1 as f32 + 0.5);
+
+            let out_line = &mut dst_data[y * dst_width..(y + 1) * dst_width];
+
+            for x in x1..x2 {
+                // Reject texels on the boundary that are outside due to
+                // rounding errors
+                if cs2.w > 0.0 && cs2.x.abs() < cs2.w && cs2.y.abs() < cs2.w {
+                    // Perform perspective division & map them to the source image's viewport space
+                    let rcp_w = 1.0 / cs2.w;
+                    let vp_x = ((cs2.x * rcp_w + 1.0) * (0.5 * src_width as f32)) as usize;
+                    let vp_y = ((cs2.y * rcp_w + 1.0) * (0.5 * src_height as f32)) as usize;
+                    let vp_x = vp_x.min(src_width - 1);
+                    let vp_y = vp_y.min(src_height - 1);
+
+                    // The clip space Y axis points upward
+                    let vp_y = if self.flip_y { src_height - 1 - vp_y } else { vp_y };
+
+                    let pixel = src_data[vp_x + vp_y * src_width];
+                    let max_channel = pixel.x.max(pixel.y).max(pixel.z);
+                    let pixel_weight = self.pixel_weight_table[max_channel as usize];
+                    let value = vec4(
+                        self.linear_table[pixel.x as usize] * pixel_weight,
+                        self.linear_table[pixel.y as usize] * pixel_weight,
+                        self.linear_table[pixel.z as usize] * pixel_weight,
+                        self.weight * pixel_weight,
+                    );
+                    match self.blend {
+                        BlendMode::Replace => out_line[x] = value,
+                        BlendMode::Add => out_line[x] += value,
+                    }
+                    num_texels += 1;
                 }
+                cs2 += dcs2;
             }
-            cs2 += dcs2;
         }
+
+        num_texels
     }
 }
+
+/// Computes the range `[t1, t2]` of the parameter `t ∈ [0, 1]` for which the
+/// point `p1 + (p2 - p1) t` (in homogeneous clip space) satisfies `w > 0`,
+/// `|x| < w` and `|y| < w`. Returns `None` if there is no such point.
+fn clip_line(p1: Vector4<f32>, p2: Vector4<f32>) -> Option<(f32, f32)> {
+    let mut t1 = 0.0f32;
+    let mut t2 = 1.0f32;
+
+    // Each half-space is given by a function which is linear in `t` and
+    // positive inside it, evaluated at `t = 0` and `t = 1`
+    let planes = [
+        (p1.w, p2.w),
+        (p1.w - p1.x, p2.w - p2.x),
+        (p1.w + p1.x, p2.w + p2.x),
+        (p1.w - p1.y, p2.w - p2.y),
+        (p1.w + p1.y, p2.w + p2.y),
+    ];
+    for &(a, b) in planes.iter() {
+        if a <= 0.0 && b <= 0.0 {
+            return None;
+        } else if a <= 0.0 {
+            t1 = t1.max(a / (a - b));
+        } else if b <= 0.0 {
+            t2 = t2.min(a / (a - b));
+        }
+    }
+
+    if t1 <= t2 { Some((t1, t2)) } else { None }
+}
+
+fn is_matrix_finite(m: &Matrix4<f32>) -> bool {
+    let m: &[f32; 16] = m.as_ref();
+    m.iter().all(|x| x.is_finite())
+}
+
+/// Checks if the determinant of `m` is (nearly) zero compared to the
+/// magnitude of its columns.
+fn is_matrix_singular(m: &Matrix4<f32>) -> bool {
+    let scale = m.x.magnitude() * m.y.magnitude() * m.z.magnitude() * m.w.magnitude();
+    !(m.determinant().abs() > scale * 1.0e-6)
+}
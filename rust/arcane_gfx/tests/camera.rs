@@ -0,0 +1,75 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{Vector3, Vector4, Quaternion, Rad, Deg};
+use cgmath::prelude::*;
+
+use arcane_gfx::Error;
+use arcane_gfx::camera::Camera;
+
+fn project(camera: &Camera, d: Vector3<f32>) -> Vector3<f32> {
+    let clip = camera.view_proj_matrix() * d.extend(1.0);
+    assert!(clip.w > 0.0, "{:?} is behind the camera", d);
+    clip.truncate() / clip.w
+}
+
+fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+    assert!((a - b).magnitude() < 1.0e-5, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn frustum_edges() {
+    let camera = Camera::new(Quaternion::one(), Deg(60.0).into(), 2.0);
+    let t = (30.0f32).to_radians().tan();
+
+    let center = project(&camera, Vector3::new(0.0, 0.0, -1.0));
+    assert!(center.x.abs() < 1.0e-6 && center.y.abs() < 1.0e-6);
+
+    let top = project(&camera, Vector3::new(0.0, t, -1.0));
+    assert!((top.y - 1.0).abs() < 1.0e-5, "{:?}", top);
+
+    let right = project(&camera, Vector3::new(t * 2.0, 0.0, -1.0));
+    assert!((right.x - 1.0).abs() < 1.0e-5, "{:?}", right);
+
+    // Points on the near plane are mapped to `z = -1`
+    let near = project(&camera, Vector3::new(0.0, 0.0, -camera.near));
+    assert!((near.z + 1.0).abs() < 1.0e-5, "{:?}", near);
+}
+
+#[test]
+fn orientation_and_position() {
+    // Turn left by 90 degrees so that the camera faces `-X`
+    let mut camera = Camera::new(Quaternion::from_angle_y(Deg(90.0)), Deg(60.0).into(), 1.0);
+    camera.position = Vector3::new(1.0, 2.0, 3.0);
+
+    let p = project(&camera, camera.position + Vector3::new(-1.0, 0.0, 0.0));
+    assert!(p.x.abs() < 1.0e-5 && p.y.abs() < 1.0e-5, "{:?}", p);
+
+    // The position is ignored by `environment_view_proj_matrix`
+    let clip = camera.environment_view_proj_matrix() * Vector4::new(-1.0, 0.0, 0.0, 1.0);
+    assert_close(clip.truncate() / clip.w, p);
+}
+
+#[test]
+fn validate() {
+    let camera = Camera::new(Quaternion::one(), Rad(1.0), 1.5);
+    assert_eq!(camera.validate(), Ok(()));
+
+    let invalid = [
+        Camera { fov_y: Rad(0.0), ..camera },
+        Camera { fov_y: Rad(4.0), ..camera },
+        Camera { aspect: -1.0, ..camera },
+        Camera { near: 0.0, ..camera },
+        Camera { orientation: Quaternion::zero(), ..camera },
+        Camera { position: Vector3::new(::std::f32::NAN, 0.0, 0.0), ..camera },
+    ];
+    for c in invalid.iter() {
+        assert_eq!(c.validate(), Err(Error::InvalidCamera), "{:?}", c);
+    }
+}
@@ -0,0 +1,123 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{Vector4, Matrix3, Matrix4, PerspectiveFov, Rad};
+use cgmath::prelude::*;
+
+use arcane_gfx::Image;
+use arcane_gfx::convention::{remap_cube_map, CubeMapConvention};
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::stamp::{try_stamp_camera_image_with_options, ImageOrigin, StampOptions};
+
+fn all_conventions() -> Vec<CubeMapConvention> {
+    CubeMapConvention::NAMES
+        .iter()
+        .map(|name| CubeMapConvention::by_name(name).unwrap())
+        .collect()
+}
+
+fn remap(src: &[Vec<u32>], size: usize, from: &CubeMapConvention, to: &CubeMapConvention) -> Vec<Vec<u32>> {
+    let mut dst = vec![vec![0; size * size]; 6];
+    remap_cube_map(
+        dst.iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+        src.iter().map(|f| &f[..]).collect::<Vec<_>>().as_slice(),
+        size,
+        from,
+        to,
+    );
+    dst
+}
+
+#[test]
+fn orthonormal() {
+    for conv in all_conventions() {
+        let m = conv.to_canonical * conv.from_canonical();
+        assert_eq!(m, Matrix3::identity(), "{:?}", conv);
+    }
+}
+
+#[test]
+fn remap_round_trip() {
+    let size = 8;
+    let src: Vec<Vec<u32>> = (0..6)
+        .map(|face| (0..size * size).map(|i| (face * 1000 + i) as u32).collect())
+        .collect();
+    for from in all_conventions() {
+        for to in all_conventions() {
+            let there = remap(&src, size, &from, &to);
+            assert_eq!(remap(&there, size, &to, &from), src, "{:?} -> {:?}", from, to);
+        }
+    }
+}
+
+#[test]
+fn direct3d_swaps_z_faces() {
+    let size = 4;
+    let src: Vec<Vec<u32>> = (0..6).map(|face| vec![face as u32; size * size]).collect();
+    let dst = remap(&src, size, &CubeMapConvention::OPENGL, &CubeMapConvention::DIRECT3D);
+    let faces: Vec<u32> = dst.iter().map(|face| face[0]).collect();
+    assert_eq!(faces, vec![0, 1, 2, 3, 5, 4]);
+}
+
+fn stamp(src: &Image<&[Vector4<u8>]>, matrix: Matrix4<f32>, options: &StampOptions) -> Vec<Vec<Vector4<f32>>> {
+    CUBE_FACES
+        .iter()
+        .map(|face| {
+            let mut data = vec![Vector4::zero(); 16 * 16];
+            try_stamp_camera_image_with_options(
+                &mut Image { data: &mut data[..], width: 16, height: 16 },
+                face.info().inv_view_proj_mat,
+                src,
+                matrix,
+                options,
+            ).unwrap();
+            data
+        })
+        .collect()
+}
+
+#[test]
+fn stamp_convention() {
+    // Stamping with a matrix defined in another convention should produce the
+    // same result as stamping with the equivalent canonical matrix
+    let width = 32;
+    let height = 24;
+    let src_data: Vec<Vector4<u8>> = (0..width * height)
+        .map(|i| Vector4::new((i % width * 8) as u8, (i / width * 10) as u8, 128, 255))
+        .collect();
+    let src = Image { data: &src_data[..], width, height };
+
+    let proj: Matrix4<f32> = PerspectiveFov {
+        fovy: Rad(1.0),
+        aspect: width as f32 / height as f32,
+        near: 0.1,
+        far: 10.0,
+    }.into();
+    let view = Matrix4::from_angle_y(Rad(0.7)) * Matrix4::from_angle_x(Rad(-0.3));
+    let canonical_mat = proj * view;
+
+    let expected = stamp(&src, canonical_mat, &StampOptions::default());
+    assert!(expected.iter().any(|face| face.iter().any(|x| x.w > 0.0)));
+
+    for conv in all_conventions() {
+        let options = StampOptions { convention: conv, ..StampOptions::default() };
+        let matrix = canonical_mat * Matrix4::from(conv.to_canonical);
+        assert_eq!(stamp(&src, matrix, &options), expected, "{:?}", conv);
+    }
+
+    // A bottom-up image must be stamped with `ImageOrigin::BottomLeft`
+    let flipped_data: Vec<Vector4<u8>> = src_data
+        .chunks(width)
+        .rev()
+        .flat_map(|row| row.iter().cloned())
+        .collect();
+    let src = Image { data: &flipped_data[..], width, height };
+    let options = StampOptions { src_origin: ImageOrigin::BottomLeft, ..StampOptions::default() };
+    assert_eq!(stamp(&src, canonical_mat, &options), expected);
+}
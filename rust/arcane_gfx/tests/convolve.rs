@@ -0,0 +1,82 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use arcane_gfx::convolve::{convolve_cube_map, SphericalKernel};
+use arcane_gfx::cubemap::{CubeFace, CUBE_FACES, texel_solid_angle};
+
+fn convolve(src: &[Vec<Vector4<f32>>], src_size: usize, dst_size: usize, kernel: SphericalKernel)
+    -> Vec<Vec<Vector4<f32>>>
+{
+    let mut dst = vec![vec![Vector4::zero(); dst_size * dst_size]; 6];
+    convolve_cube_map(
+        dst.iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+        dst_size,
+        src.iter().map(|f| &f[..]).collect::<Vec<_>>().as_slice(),
+        src_size,
+        kernel,
+    );
+    dst
+}
+
+#[test]
+fn preserves_constant() {
+    let src = vec![vec![Vector4::new(0.25, 0.5, 1.0, 1.0); 8 * 8]; 6];
+    for &kernel in [
+        SphericalKernel::Gaussian { sigma: 0.1 },
+        SphericalKernel::Gaussian { sigma: 2.0 },
+        SphericalKernel::von_mises_fisher_from_sigma(0.3),
+    ].iter()
+    {
+        for face in convolve(&src, 8, 4, kernel) {
+            for x in face {
+                assert!((x - Vector4::new(0.25, 0.5, 1.0, 1.0)).magnitude() < 1.0e-4, "{:?}", x);
+            }
+        }
+    }
+}
+
+#[test]
+fn impulse_response_width() {
+    // Blur a single texel at the center of +Z and measure the angular
+    // standard deviation of the result
+    let size = 32;
+    let sigma = 0.2;
+    let mut src = vec![vec![Vector4::zero(); size * size]; 6];
+    src[CubeFace::PositiveZ as usize][size / 2 + size / 2 * size] = Vector4::new(1.0, 0.0, 0.0, 0.0);
+    let center = CubeFace::PositiveZ.texel_direction(size / 2, size / 2, size).normalize();
+
+    for &kernel in [
+        SphericalKernel::Gaussian { sigma },
+        SphericalKernel::von_mises_fisher_from_sigma(sigma),
+    ].iter()
+    {
+        let dst = convolve(&src, size, size, kernel);
+
+        let mut moment = 0.0;
+        let mut total = 0.0;
+        for (face, dst_face) in CUBE_FACES.iter().zip(dst.iter()) {
+            for y in 0..size {
+                for x in 0..size {
+                    let d: Vector3<f32> = face.texel_direction(x, y, size).normalize();
+                    let theta = d.dot(center).max(-1.0).min(1.0).acos();
+                    let weight = dst_face[x + y * size].x * texel_solid_angle(x, y, size);
+                    moment += theta * theta * weight;
+                    total += weight;
+                }
+            }
+        }
+
+        // A 2D Gaussian has E[θ²] = 2σ²
+        let measured = (moment / total / 2.0).sqrt();
+        assert!((measured - sigma).abs() < sigma * 0.1, "{:?}: {}", kernel, measured);
+    }
+}
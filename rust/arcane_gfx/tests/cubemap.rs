@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Tests for the cube map topology.
+//!
+//! The cube map layout follows the OpenGL (and WebGL) convention, which is
+//! defined in a left-handed coordinate system as seen from the center of the
+//! cube: for every face, `u × v = -n` where `u` and `v` point along the
+//! texture's X axis (to the right) and Y axis (downward in the image), and `n`
+//! is the face's outward normal. For example, the `+X` face is spanned by
+//! `u = -Z` and `v = -Y`.
+extern crate cgmath;
+extern crate rand;
+extern crate arcane_gfx;
+
+use cgmath::{Vector2, Vector3, Vector4};
+use cgmath::prelude::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use arcane_gfx::cubemap::{CubeFace, CUBE_FACES, texel_to_clip};
+
+fn random_direction<R: Rng>(rng: &mut R) -> Vector3<f32> {
+    loop {
+        let v: Vector3<f32> = Vector3::new(
+            rng.gen_range(-1.0, 1.0),
+            rng.gen_range(-1.0, 1.0),
+            rng.gen_range(-1.0, 1.0),
+        );
+        let len2 = v.magnitude2();
+        if len2 > 1.0e-4 && len2 <= 1.0 {
+            return v / len2.sqrt();
+        }
+    }
+}
+
+fn assert_close(a: Vector3<f32>, b: Vector3<f32>) {
+    assert!((a - b).magnitude() < 1.0e-5, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn handedness() {
+    for &face in CUBE_FACES.iter() {
+        let u: Vector3<f32> = face.u_vec();
+        let v: Vector3<f32> = face.v_vec();
+        let n: Vector3<f32> = face.normal();
+        assert_eq!(u.cross(v), -n, "{:?}", face);
+    }
+}
+
+#[test]
+fn orthogonal_axes() {
+    for &face in CUBE_FACES.iter() {
+        for &axis in [face.u_face(), face.v_face()].iter() {
+            assert!(axis.abs() != face.abs(), "{:?}", face);
+        }
+        assert!(face.u_face().abs() != face.v_face().abs(), "{:?}", face);
+    }
+}
+
+#[test]
+fn negation() {
+    for &face in CUBE_FACES.iter() {
+        assert_eq!(-(-face), face);
+        assert!(-face != face);
+        assert_eq!((-face).abs(), face.abs());
+        assert_eq!(face.abs().as_ordinal() & 1, 0);
+        assert_eq!((-face).normal::<f32>(), -face.normal::<f32>());
+    }
+}
+
+#[test]
+fn ordinal_round_trip() {
+    for (i, &face) in CUBE_FACES.iter().enumerate() {
+        assert_eq!(face.as_ordinal(), i);
+        assert_eq!(CubeFace::from_ordinal(i), Some(face));
+    }
+    assert_eq!(CubeFace::from_ordinal(6), None);
+    assert_eq!(CubeFace::from_ordinal(usize::max_value()), None);
+}
+
+#[test]
+fn view_proj_mat_matches_project() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for _ in 0..1000 {
+        let d = random_direction(&mut rng);
+        let face = CubeFace::from_direction(d);
+
+        let p = face.project(d);
+        assert!(p.x.abs() <= 1.0 + 1.0e-5 && p.y.abs() <= 1.0 + 1.0e-5, "{:?}", p);
+
+        let clip = face.info().view_proj_mat * d.extend(1.0);
+        assert!(clip.w > 0.0);
+        assert!((clip.x / clip.w - p.x).abs() < 1.0e-5);
+        assert!((clip.y / clip.w - p.y).abs() < 1.0e-5);
+
+        let back = face.info().inv_view_proj_mat * Vector4::new(p.x, p.y, 1.0, 1.0);
+        assert_close(back.truncate().normalize(), d);
+        assert_close(face.unproject(p).normalize(), d);
+    }
+}
+
+#[test]
+fn shared_edges() {
+    // Walk along each edge of each face at the positions of texel centres and
+    // check that the same direction is on the corresponding edge of the
+    // adjacent face, also at a texel centre
+    for &size in [1, 2, 7, 16, 64].iter() {
+        for &face in CUBE_FACES.iter() {
+            let edges = [
+                (Vector2::new(1.0, 0.0), face.u_face()),
+                (Vector2::new(-1.0, 0.0), -face.u_face()),
+                (Vector2::new(0.0, 1.0), face.v_face()),
+                (Vector2::new(0.0, -1.0), -face.v_face()),
+            ];
+            for &(dir, neighbor) in edges.iter() {
+                for i in 0..size {
+                    let t = texel_to_clip(i, size);
+                    let p = dir + Vector2::new(dir.y, dir.x) * t;
+                    let d = face.unproject(p);
+
+                    // Just outside the edge belongs to `neighbor`
+                    let outside = face.unproject(p * 1.001);
+                    assert_eq!(CubeFace::from_direction(outside), neighbor);
+
+                    let q = neighbor.project(d);
+                    assert_close(neighbor.unproject(q).normalize(), d.normalize());
+                    assert!(
+                        (q.x.abs() - 1.0).abs() < 1.0e-5 || (q.y.abs() - 1.0).abs() < 1.0e-5,
+                        "{:?} is not on an edge of {:?}",
+                        q,
+                        neighbor
+                    );
+                    let along = if (q.x.abs() - 1.0).abs() < 1.0e-5 { q.y } else { q.x };
+                    let j = ((along + 1.0) * 0.5 * size as f32 - 0.5).round();
+                    assert!(
+                        (texel_to_clip(j as usize, size) - along).abs() < 1.0e-5,
+                        "{:?} does not line up with a texel of {:?}",
+                        q,
+                        neighbor
+                    );
+                }
+            }
+        }
+    }
+}
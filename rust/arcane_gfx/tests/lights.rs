@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use std::f32::consts::PI;
+use cgmath::{Vector3, Vector4, Rad};
+use cgmath::prelude::*;
+
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::lights::{extract_lights, LightExtractionParams};
+
+const SIZE: usize = 64;
+const AMBIENT: f32 = 0.1;
+
+#[derive(Copy, Clone)]
+struct Disk {
+    direction: Vector3<f32>,
+    radius: f32,
+    radiance: Vector3<f32>,
+}
+
+impl Disk {
+    /// The irradiance above the ambient level.
+    fn expected_color(&self) -> Vector3<f32> {
+        (self.radiance - Vector3::new(AMBIENT, AMBIENT, AMBIENT)) * (2.0 * PI * (1.0 - self.radius.cos()))
+    }
+}
+
+fn render(disks: &[Disk]) -> Vec<Vec<Vector4<f32>>> {
+    CUBE_FACES
+        .iter()
+        .map(|face| {
+            let mut data = Vec::with_capacity(SIZE * SIZE);
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let d = face.texel_direction(x, y, SIZE).normalize();
+                    let color = disks
+                        .iter()
+                        .find(|disk| d.dot(disk.direction) > disk.radius.cos())
+                        .map(|disk| disk.radiance)
+                        .unwrap_or(Vector3::new(AMBIENT, AMBIENT, AMBIENT));
+                    data.push(color.extend(1.0));
+                }
+            }
+            data
+        })
+        .collect()
+}
+
+fn extract(faces: &mut [Vec<Vector4<f32>>], params: &LightExtractionParams) -> Vec<arcane_gfx::lights::DirectionalLight> {
+    extract_lights(
+        faces.iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+        SIZE,
+        params,
+    )
+}
+
+#[test]
+fn two_lights() {
+    let disks = [
+        Disk {
+            direction: Vector3::new(0.3, 0.8, -0.5).normalize(),
+            radius: 0.1,
+            radiance: Vector3::new(40.0, 36.0, 30.0),
+        },
+        Disk {
+            direction: Vector3::new(-0.9, 0.1, 0.4).normalize(),
+            radius: 0.2,
+            radiance: Vector3::new(2.0, 2.0, 3.0),
+        },
+    ];
+    let mut faces = render(&disks);
+    let params = LightExtractionParams {
+        remove: true,
+        ..LightExtractionParams::default()
+    };
+    let lights = extract(&mut faces, &params);
+    assert_eq!(lights.len(), 2, "{:?}", lights);
+
+    for (light, disk) in lights.iter().zip(disks.iter()) {
+        let angle = light.direction.dot(disk.direction).min(1.0).acos();
+        assert!(angle < 0.02, "{:?} vs {:?}", light.direction, disk.direction);
+
+        let expected = disk.expected_color();
+        let error = (light.color - expected).magnitude() / expected.magnitude();
+        assert!(error < 0.1, "{:?} vs {:?}", light.color, expected);
+
+        // The RMS angle of a uniform disk is `radius / sqrt(2)`
+        let expected_spread = disk.radius / 2.0f32.sqrt();
+        assert!((light.spread.0 - expected_spread).abs() < 0.02, "{:?} vs {}", light.spread, expected_spread);
+    }
+
+    // Only the ambient lighting remains
+    for face in faces.iter() {
+        for x in face.iter() {
+            assert!(x.x < AMBIENT * 1.5 && x.y < AMBIENT * 1.5 && x.z < AMBIENT * 1.5, "{:?}", x);
+        }
+    }
+}
+
+#[test]
+fn no_lights() {
+    let mut faces = render(&[]);
+    let original = faces.clone();
+    let params = LightExtractionParams {
+        remove: true,
+        ..LightExtractionParams::default()
+    };
+    assert_eq!(extract(&mut faces, &params), vec![]);
+    assert!(faces == original);
+
+    // Unobserved texels are ignored
+    let mut faces = render(&[
+        Disk {
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            radius: 0.1,
+            radiance: Vector3::new(10.0, 10.0, 10.0),
+        },
+    ]);
+    for face in faces.iter_mut() {
+        for x in face.iter_mut() {
+            if x.x > 1.0 {
+                *x = Vector4::zero();
+            }
+        }
+    }
+    assert_eq!(extract(&mut faces, &params), vec![]);
+}
+
+#[test]
+fn max_lights_and_radius() {
+    let disk = Disk {
+        direction: Vector3::new(0.0, 1.0, 0.0),
+        radius: 0.3,
+        radiance: Vector3::new(10.0, 10.0, 10.0),
+    };
+    let mut faces = render(&[disk]);
+
+    let params = LightExtractionParams {
+        max_lights: 1,
+        max_radius: Rad(0.1),
+        ..LightExtractionParams::default()
+    };
+    let lights = extract(&mut faces, &params);
+    assert_eq!(lights.len(), 1);
+
+    // The lobe is truncated at `max_radius`
+    assert!(lights[0].spread.0 < 0.1);
+    assert!(lights[0].color.x < disk.expected_color().x * 0.2);
+}
+
+#[test]
+fn non_finite_texels() {
+    let disk = Disk {
+        direction: Vector3::new(0.0, 0.0, -1.0),
+        radius: 0.1,
+        radiance: Vector3::new(10.0, 10.0, 10.0),
+    };
+    let mut faces = render(&[disk]);
+    let nan = ::std::f32::NAN;
+    let inf = ::std::f32::INFINITY;
+    faces[0][0] = Vector4::new(nan, nan, nan, 1.0);
+    faces[1][0] = Vector4::new(inf, 1.0, 1.0, 1.0);
+    faces[2][0] = Vector4::new(1.0, 1.0, 1.0, inf);
+
+    // Non-finite texels are ignored
+    let lights = extract(&mut faces, &LightExtractionParams::default());
+    assert_eq!(lights.len(), 1, "{:?}", lights);
+    assert!(lights[0].direction.dot(disk.direction) > 0.99, "{:?}", lights[0]);
+}
@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{Vector3, Vector4, Matrix4};
+use cgmath::prelude::*;
+
+use arcane_gfx::{Image, Error};
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::response::{ResponseCurve, PixelWeighting, LINEAR_WHITE};
+use arcane_gfx::stamp::{try_stamp_camera_image_with_options, StampOptions};
+
+#[test]
+fn gamma2_is_exact() {
+    let curve = ResponseCurve::default();
+    for i in 0..256 {
+        assert_eq!(curve.linearize(i as u8), (i * i) as f32);
+    }
+}
+
+#[test]
+fn curves_are_monotonic() {
+    for curve in [ResponseCurve::gamma(2.2), ResponseCurve::srgb()].iter() {
+        assert_eq!(curve.linearize(0), 0.0);
+        assert!((curve.linearize(255) - LINEAR_WHITE).abs() < 1.0e-2);
+        assert!(curve.table().windows(2).all(|x| x[0] < x[1]), "{:?}", curve);
+    }
+}
+
+#[test]
+fn from_table() {
+    let table: Vec<f32> = (0..256).map(|i| i as f32 / 255.0).collect();
+    let curve = ResponseCurve::from_table(&table).unwrap();
+    assert_eq!(curve.linearize(51), LINEAR_WHITE * 0.2);
+
+    assert_eq!(ResponseCurve::from_table(&table[1..]), Err(Error::InvalidResponseCurve));
+
+    let mut bad = table.clone();
+    bad[100] = 0.0;
+    assert_eq!(ResponseCurve::from_table(&bad), Err(Error::InvalidResponseCurve));
+    bad[100] = ::std::f32::NAN;
+    assert_eq!(ResponseCurve::from_table(&bad), Err(Error::InvalidResponseCurve));
+}
+
+#[test]
+fn from_linear_table() {
+    let curve = ResponseCurve::srgb();
+    assert_eq!(ResponseCurve::from_linear_table(curve.table()), Ok(curve));
+
+    let mut bad = *curve.table();
+    bad[255] = LINEAR_WHITE * 2.0;
+    assert_eq!(ResponseCurve::from_linear_table(&bad), Err(Error::InvalidResponseCurve));
+    assert_eq!(ResponseCurve::from_linear_table(&bad[1..]), Err(Error::InvalidResponseCurve));
+}
+
+#[test]
+fn hat_weighting() {
+    let w = PixelWeighting::Hat;
+    assert!(w.weight(128) > 0.99);
+    assert!(w.weight(0) > 0.0 && w.weight(0) < 0.01);
+    assert!(w.weight(255) > 0.0 && w.weight(255) < 0.01);
+    assert!(w.weight(250) < w.weight(200));
+    assert!(w.weight(5) < w.weight(50));
+    assert_eq!(PixelWeighting::Uniform.weight(255), 1.0);
+}
+
+#[test]
+fn stamp_with_hat_weighting() {
+    // The left half of the image is clipped
+    let src_data: Vec<Vector4<u8>> = (0..8 * 8)
+        .map(|i| if i % 8 < 4 {
+            Vector4::new(255, 255, 255, 255)
+        } else {
+            Vector4::new(128, 64, 32, 255)
+        })
+        .collect();
+    let src = Image { data: &src_data[..], width: 8, height: 8 };
+
+    // Looking at `-Z` with a 90° field of view, which matches the `-Z` face
+    let matrix = Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, -1.0,
+        0.0, 0.0, 1.0, 0.0,
+    );
+    let mut dst = vec![Vector4::zero(); 8 * 8];
+    let options = StampOptions {
+        weighting: PixelWeighting::Hat,
+        ..StampOptions::default()
+    };
+    try_stamp_camera_image_with_options(
+        &mut Image { data: &mut dst[..], width: 8, height: 8 },
+        CUBE_FACES[5].info().inv_view_proj_mat,
+        &src,
+        matrix,
+        &options,
+    ).unwrap();
+
+    let clipped = dst.iter().filter(|x| x.w > 0.0 && x.w < 0.01).count();
+    let unclipped = dst.iter().filter(|x| x.w > 0.5).count();
+    assert_eq!(clipped, 32);
+    assert_eq!(unclipped, 32);
+
+    // The color values are premultiplied by the weight
+    let white = Vector3::new(255.0 * 255.0, 255.0 * 255.0, 255.0 * 255.0);
+    let color = Vector3::new(128.0 * 128.0, 64.0 * 64.0, 32.0 * 32.0);
+    for x in dst.iter() {
+        let x = x.truncate() / x.w;
+        let error = (x - white).magnitude().min((x - color).magnitude());
+        assert!(error < 0.1, "{:?}", x);
+    }
+}
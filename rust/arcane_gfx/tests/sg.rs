@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use arcane_gfx::cubemap::{CUBE_FACES, texel_solid_angle};
+use arcane_gfx::sg::{fit_spherical_gaussians, evaluate_sum, fibonacci_axes, default_sharpness,
+                     SphericalGaussian};
+
+const SIZE: usize = 8;
+
+fn render<F: Fn(Vector3<f32>) -> Vector3<f32>>(f: F) -> Vec<Vec<Vector4<f32>>> {
+    CUBE_FACES
+        .iter()
+        .map(|face| {
+            let mut data = Vec::with_capacity(SIZE * SIZE);
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    let d = face.texel_direction(x, y, SIZE).normalize();
+                    data.push(f(d).extend(1.0));
+                }
+            }
+            data
+        })
+        .collect()
+}
+
+fn fit(faces: &[Vec<Vector4<f32>>], num_lobes: usize) -> Vec<SphericalGaussian> {
+    fit_spherical_gaussians(
+        faces.iter().map(|f| &f[..]).collect::<Vec<_>>().as_slice(),
+        SIZE,
+        num_lobes,
+    )
+}
+
+/// Returns the relative RMS error of the map reconstructed from `lobes`,
+/// weighted by the solid angle.
+fn reconstruction_error(faces: &[Vec<Vector4<f32>>], lobes: &[SphericalGaussian]) -> f32 {
+    let (mut sum_sq_error, mut sum_sq) = (0.0, 0.0);
+    for (face, data) in CUBE_FACES.iter().zip(faces.iter()) {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let d = face.texel_direction(x, y, SIZE).normalize();
+                let expected = data[x + y * SIZE].truncate();
+                let actual = evaluate_sum(lobes, d);
+                let solid_angle = texel_solid_angle(x, y, SIZE);
+                sum_sq_error += (actual - expected).magnitude2() * solid_angle;
+                sum_sq += expected.magnitude2() * solid_angle;
+            }
+        }
+    }
+    (sum_sq_error / sum_sq).sqrt()
+}
+
+/// A sky-like environment: a bright, bluish upper hemisphere with a warm
+/// glow and a dim ground.
+fn sky(d: Vector3<f32>) -> Vector3<f32> {
+    let sun = Vector3::new(0.5, 0.6, -0.6).normalize();
+    let glow = (d.dot(sun).max(0.0)).powi(4) * 2.0;
+    if d.y > 0.0 {
+        Vector3::new(0.4, 0.6, 1.0) * (0.5 + d.y * 0.5) + Vector3::new(glow, glow * 0.8, glow * 0.5)
+    } else {
+        Vector3::new(0.2, 0.15, 0.1) + Vector3::new(glow, glow * 0.8, glow * 0.5) * 0.2
+    }
+}
+
+#[test]
+fn axes_are_normalized() {
+    let axes = fibonacci_axes(32);
+    assert_eq!(axes.len(), 32);
+    for axis in axes.iter() {
+        assert!((axis.magnitude() - 1.0).abs() < 1.0e-5, "{:?}", axis);
+    }
+
+    // The centroid of evenly distributed points is close to the origin
+    let centroid = axes.iter().fold(Vector3::zero(), |s, &x| s + x) / 32.0;
+    assert!(centroid.magnitude() < 0.05, "{:?}", centroid);
+
+    assert!(default_sharpness(32) > default_sharpness(8));
+}
+
+#[test]
+fn recovers_lobes() {
+    // Lobes with the same axes and sharpness as the fitted ones are
+    // reproduced exactly
+    let num_lobes = 12;
+    let sharpness = default_sharpness(num_lobes);
+    let lobes: Vec<_> = fibonacci_axes(num_lobes)
+        .into_iter()
+        .enumerate()
+        .map(|(i, axis)| {
+            SphericalGaussian {
+                axis,
+                sharpness,
+                amplitude: Vector3::new(i as f32, 1.0, (i % 3) as f32 * 0.5),
+            }
+        })
+        .collect();
+    let faces = render(|d| evaluate_sum(&lobes, d));
+
+    let fitted = fit(&faces, num_lobes);
+    assert_eq!(fitted.len(), num_lobes);
+    for (expected, actual) in lobes.iter().zip(fitted.iter()) {
+        assert_eq!(expected.axis, actual.axis);
+        assert_eq!(expected.sharpness, actual.sharpness);
+        assert!(
+            (expected.amplitude - actual.amplitude).magnitude() < 1.0e-2,
+            "{:?} vs {:?}",
+            expected,
+            actual
+        );
+    }
+}
+
+#[test]
+fn reconstruction() {
+    // The error is dominated by the sharp horizon, which a small number of
+    // smooth lobes cannot represent
+    let faces = render(sky);
+    let mut last_error = ::std::f32::INFINITY;
+    for &(num_lobes, max_error) in [(6, 0.45), (12, 0.3), (24, 0.2)].iter() {
+        let lobes = fit(&faces, num_lobes);
+        let error = reconstruction_error(&faces, &lobes);
+        println!("{} lobes: relative RMSE = {}", num_lobes, error);
+        assert!(error < max_error, "{} lobes: {}", num_lobes, error);
+        assert!(error < last_error);
+        last_error = error;
+    }
+}
+
+#[test]
+fn ignores_unobserved_texels() {
+    // Only the upper hemisphere is observed; the rest is garbage
+    let faces: Vec<_> = render(sky)
+        .into_iter()
+        .zip(render(|d| Vector3::new(d.y, d.y, d.y)))
+        .map(|(face, up)| {
+            face.iter()
+                .zip(up.iter())
+                .map(|(&x, u)| if u.x > 0.0 { x } else { Vector4::new(100.0, 0.0, 0.0, 0.0) })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let lobes = fit(&faces, 12);
+    for lobe in lobes.iter() {
+        assert!(lobe.amplitude.x.is_finite() && lobe.amplitude.x.abs() < 10.0, "{:?}", lobe);
+    }
+}
+
+#[test]
+fn nonnegative_amplitudes() {
+    // A small, bright light source, which a least squares fit would
+    // approximate with negative lobes around it
+    let light = Vector3::new(0.3, 0.8, -0.5).normalize();
+    let faces = render(|d| {
+        let x = (d.dot(light).max(0.0)).powi(64) * 50.0 + 0.05;
+        Vector3::new(x, x * 0.9, x * 0.8)
+    });
+    for &num_lobes in [6, 12, 24].iter() {
+        let lobes = fit(&faces, num_lobes);
+        for lobe in lobes.iter() {
+            let a = lobe.amplitude;
+            assert!(a.x >= 0.0 && a.y >= 0.0 && a.z >= 0.0, "{:?}", lobe);
+        }
+        let error = reconstruction_error(&faces, &lobes);
+        println!("{} lobes: relative RMSE = {}", num_lobes, error);
+
+        // The light source is much sharper than the lobes, so just check
+        // that the fit is better than nothing
+        assert!(error < 1.0, "{} lobes: {}", num_lobes, error);
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+
+use cgmath::Vector3;
+use cgmath::prelude::*;
+
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::sky::{PreethamSky, SkyParams};
+
+fn luminance(c: Vector3<f32>) -> f32 {
+    c.dot(Vector3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn sky_with(sun_elevation: f32, turbidity: f32) -> PreethamSky {
+    PreethamSky::new(&SkyParams {
+        sun_direction: Vector3::new(0.0, sun_elevation.sin(), -sun_elevation.cos()),
+        turbidity,
+        ..SkyParams::default()
+    })
+}
+
+#[test]
+fn zenith_luminance() {
+    // The zenith luminance matches the formula given by Preetham et al.
+    for &t in [2.0, 3.0, 6.0].iter() {
+        for &elevation in [0.3f32, 0.7, 1.2].iter() {
+            let theta_s = std::f32::consts::FRAC_PI_2 - elevation;
+            let chi = (4.0 / 9.0 - t / 120.0) * (std::f32::consts::PI - 2.0 * theta_s);
+            let expected = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+
+            let sky = sky_with(elevation, t);
+            assert!((sky.zenith_luminance() - expected).abs() < expected * 1.0e-4);
+            let actual = luminance(sky.evaluate(Vector3::new(0.0, 1.0, 0.0)));
+            assert!(
+                (actual - expected).abs() < expected * 0.02,
+                "T = {}, elevation = {}: {} vs {}",
+                t,
+                elevation,
+                actual,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn sky_distribution() {
+    let sky = sky_with(0.5, 2.5);
+    let sun = sky.params().sun_direction;
+
+    // The clear sky is blue
+    let zenith = sky.evaluate(Vector3::new(0.0, 1.0, 0.0));
+    assert!(zenith.z > zenith.x, "{:?}", zenith);
+
+    // Brighter around the sun than on the opposite side
+    let near_sun = Vector3::new(sun.x, sun.y + 0.2, sun.z).normalize();
+    let away = Vector3::new(-near_sun.x, near_sun.y, -near_sun.z);
+    assert!(luminance(sky.evaluate(near_sun)) > luminance(sky.evaluate(away)) * 2.0);
+
+    // Brighter toward the horizon away from the sun
+    let low = Vector3::new(0.2, 0.1, 1.0).normalize();
+    let high = Vector3::new(0.2, 0.8, 1.0).normalize();
+    assert!(luminance(sky.evaluate(low)) > luminance(sky.evaluate(high)));
+
+    // A hazy sky is whiter
+    let hazy = sky_with(0.5, 8.0).evaluate(Vector3::new(0.0, 1.0, 0.0));
+    assert!(hazy.z / hazy.x < zenith.z / zenith.x, "{:?} vs {:?}", hazy, zenith);
+}
+
+#[test]
+fn ground() {
+    let params = SkyParams::default();
+    let sky = PreethamSky::new(&params);
+    let ground = sky.ground_radiance();
+    assert_eq!(sky.evaluate(Vector3::new(0.3, -0.5, 0.1).normalize()), ground);
+    assert!(ground.x > 0.0 && ground.y > 0.0 && ground.z > 0.0, "{:?}", ground);
+
+    // Darker than the sky, and proportional to the albedo
+    assert!(luminance(ground) < luminance(sky.evaluate(Vector3::new(0.0, 1.0, 0.0))));
+    let brighter = PreethamSky::new(&SkyParams {
+        ground_albedo: params.ground_albedo * 2.0,
+        ..params
+    });
+    assert!((brighter.ground_radiance() - ground * 2.0).magnitude() < ground.magnitude() * 1.0e-4);
+}
+
+#[test]
+fn scale() {
+    let params = SkyParams::default();
+    let sky = PreethamSky::new(&params);
+    let scaled = PreethamSky::new(&SkyParams {
+        scale: 1.0 / sky.zenith_luminance(),
+        ..params
+    });
+    assert_eq!(scaled.zenith_luminance(), sky.zenith_luminance());
+
+    // The zenith is mapped to `1`
+    let up = Vector3::new(0.0, 1.0, 0.0);
+    assert!((luminance(scaled.evaluate(up)) - 1.0).abs() < 0.02, "{:?}", scaled.evaluate(up));
+
+    let d = Vector3::new(0.5, 0.2, 0.3).normalize();
+    let expected = sky.evaluate(d) / sky.zenith_luminance();
+    assert!((scaled.evaluate(d) - expected).magnitude() < expected.magnitude() * 1.0e-4);
+    let expected = sky.ground_radiance() / sky.zenith_luminance();
+    assert!((scaled.ground_radiance() - expected).magnitude() < expected.magnitude() * 1.0e-4);
+}
+
+#[test]
+fn sun_below_horizon() {
+    let sky = PreethamSky::new(&SkyParams {
+        sun_direction: Vector3::new(0.0, -0.5, 1.0),
+        ..SkyParams::default()
+    });
+    let x = sky.evaluate(Vector3::new(0.0, 0.5, 1.0).normalize());
+    assert!(x.x.is_finite() && x.y.is_finite() && x.z.is_finite(), "{:?}", x);
+}
+
+#[test]
+fn cube_map() {
+    let sky = PreethamSky::new(&SkyParams::default());
+    for &size in [1, 16, 24].iter() {
+        let faces = sky.render_cube_map(size);
+        assert_eq!(faces.len(), 6);
+        for (face, image) in CUBE_FACES.iter().zip(faces.iter()) {
+            assert_eq!((image.width, image.height), (size, size));
+            assert_eq!(image.data.len(), size * size);
+            for y in 0..size {
+                for x in 0..size {
+                    let texel = image.data[x + y * size];
+                    let d = face.texel_direction(x, y, size).normalize();
+                    assert_eq!(texel.truncate(), sky.evaluate(d));
+                    assert_eq!(texel.w, 1.0);
+                    assert!(texel.x >= 0.0 && texel.x.is_finite(), "{:?}", texel);
+                }
+            }
+        }
+    }
+}
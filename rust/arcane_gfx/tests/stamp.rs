@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Fuzz-style tests for `stamp_camera_image`.
+extern crate cgmath;
+extern crate rand;
+extern crate arcane_gfx;
+
+use cgmath::{vec4, Vector4, Matrix4, PerspectiveFov, Rad};
+use cgmath::prelude::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use arcane_gfx::{Image, Error};
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::stamp::{try_stamp_camera_image, try_stamp_camera_image_with_options, StampOptions};
+
+fn random_matrix<R: Rng>(rng: &mut R) -> Matrix4<f32> {
+    let mut elements = [[0.0f32; 4]; 4];
+    for column in elements.iter_mut() {
+        for x in column.iter_mut() {
+            *x = match rng.gen_range(0, 20) {
+                0 => 0.0,
+                1 => rng.gen_range(-1.0e-20, 1.0e-20),
+                2 => rng.gen_range(-1.0e20, 1.0e20),
+                _ => rng.gen_range(-2.0, 2.0),
+            };
+        }
+    }
+    Matrix4::from(elements)
+}
+
+fn random_camera_matrix<R: Rng>(rng: &mut R) -> Matrix4<f32> {
+    let proj: Matrix4<f32> = PerspectiveFov {
+        fovy: Rad(rng.gen_range(0.2, 2.5)),
+        aspect: rng.gen_range(0.3, 3.0),
+        near: 0.1,
+        far: 10.0,
+    }.into();
+    proj * Matrix4::from_angle_x(Rad(rng.gen_range(-3.2, 3.2))) *
+        Matrix4::from_angle_y(Rad(rng.gen_range(-3.2, 3.2))) *
+        Matrix4::from_angle_z(Rad(rng.gen_range(-3.2, 3.2)))
+}
+
+fn random_image<R: Rng>(rng: &mut R) -> Image<Vec<Vector4<u8>>> {
+    let width = rng.gen_range(1, 40);
+    let height = rng.gen_range(1, 40);
+    Image {
+        data: (0..width * height)
+            .map(|_| vec4(rng.gen(), rng.gen(), rng.gen(), 255))
+            .collect(),
+        width,
+        height,
+    }
+}
+
+#[test]
+fn random_matrices() {
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    let mut num_ok = 0;
+    for _ in 0..2000 {
+        let src = random_image(&mut rng);
+        let size = rng.gen_range(0, 24);
+        let mut dst = vec![Vector4::zero(); size * size];
+        let face = CUBE_FACES[rng.gen_range(0, 6)];
+        let matrix = if rng.gen() {
+            random_matrix(&mut rng)
+        } else {
+            random_camera_matrix(&mut rng)
+        };
+
+        let result = try_stamp_camera_image(
+            &mut Image { data: &mut dst[..], width: size, height: size },
+            face.info().inv_view_proj_mat,
+            &src.as_ref(),
+            matrix,
+        );
+        match result {
+            Ok(num_texels) => {
+                let written = dst.iter().filter(|x| x.w > 0.0).count();
+                assert_eq!(num_texels, written, "{:?}", matrix);
+                num_ok += 1;
+            }
+            Err(Error::SingularMatrix) => {}
+            Err(e) => panic!("unexpected error {:?} for {:?}", e, matrix),
+        }
+    }
+    assert!(num_ok > 1000);
+}
+
+#[test]
+fn non_finite_and_singular() {
+    let src_data = vec![Vector4::new(1, 2, 3, 255); 4 * 4];
+    let src = Image { data: &src_data[..], width: 4, height: 4 };
+    let mut dst = vec![Vector4::zero(); 8 * 8];
+    let inv_view_proj_mat = CUBE_FACES[0].info().inv_view_proj_mat;
+
+    let mut stamp = |matrix: Matrix4<f32>| {
+        try_stamp_camera_image(
+            &mut Image { data: &mut dst[..], width: 8, height: 8 },
+            inv_view_proj_mat,
+            &src,
+            matrix,
+        )
+    };
+
+    for &x in [::std::f32::NAN, ::std::f32::INFINITY, ::std::f32::NEG_INFINITY].iter() {
+        let mut m = Matrix4::identity();
+        m[2][1] = x;
+        assert_eq!(stamp(m), Err(Error::NonFiniteMatrix));
+    }
+
+    assert_eq!(stamp(Matrix4::zero()), Err(Error::SingularMatrix));
+    let mut m = Matrix4::identity();
+    m[3] = m[2];
+    assert_eq!(stamp(m), Err(Error::SingularMatrix));
+
+    // Tiny but well-conditioned matrices are fine
+    assert!(stamp(Matrix4::identity() * 1.0e-5).is_ok());
+}
+
+#[test]
+fn partially_behind_camera() {
+    // Compare against evaluating the frustum test for every texel directly.
+    // The incremental evaluation might disagree on texels very close to the
+    // frustum boundary, which are ignored
+    let mut rng = XorShiftRng::from_seed([9, 10, 11, 12]);
+    let size = 32;
+    let mut num_checked = 0;
+    for _ in 0..200 {
+        let matrix = random_camera_matrix(&mut rng);
+        let src_data = vec![Vector4::new(1, 1, 1, 255); 16 * 16];
+        let src = Image { data: &src_data[..], width: 16, height: 16 };
+
+        for face in CUBE_FACES.iter() {
+            let inv_view_proj_mat = face.info().inv_view_proj_mat;
+            let mut dst = vec![Vector4::zero(); size * size];
+            try_stamp_camera_image(
+                &mut Image { data: &mut dst[..], width: size, height: size },
+                inv_view_proj_mat,
+                &src,
+                matrix,
+            ).unwrap();
+
+            let m = matrix * inv_view_proj_mat;
+            for y in 0..size {
+                for x in 0..size {
+                    let cs1 = vec4(
+                        (x as f32 + 0.5) * (2.0 / size as f32) - 1.0,
+                        (y as f32 + 0.5) * (2.0 / size as f32) - 1.0,
+                        1.0,
+                        1.0,
+                    );
+                    let cs2 = m * cs1;
+                    let margin = (cs2.w - cs2.x.abs()).min(cs2.w - cs2.y.abs());
+                    if margin.abs() < 1.0e-4 * cs2.truncate().magnitude() {
+                        continue;
+                    }
+                    let inside = cs2.w > 0.0 && margin > 0.0;
+                    assert_eq!(
+                        dst[x + y * size].w > 0.0,
+                        inside,
+                        "{:?} at ({}, {}) of {:?}",
+                        matrix,
+                        x,
+                        y,
+                        face
+                    );
+                    num_checked += 1;
+                }
+            }
+        }
+    }
+    assert!(num_checked > 1000);
+}
+
+#[test]
+fn tiled_matches_untiled() {
+    let mut rng = XorShiftRng::from_seed([13, 14, 15, 16]);
+    for _ in 0..100 {
+        let matrix = random_camera_matrix(&mut rng);
+        let src = random_image(&mut rng);
+        let size = rng.gen_range(1, 50);
+
+        for face in CUBE_FACES.iter() {
+            let stamp = |tile_size: usize| {
+                let mut dst = vec![Vector4::zero(); size * size];
+                let num_texels = try_stamp_camera_image_with_options(
+                    &mut Image { data: &mut dst[..], width: size, height: size },
+                    face.info().inv_view_proj_mat,
+                    &src.as_ref(),
+                    matrix,
+                    &StampOptions { tile_size, ..StampOptions::default() },
+                ).unwrap();
+                (num_texels, dst)
+            };
+
+            let expected = stamp(0);
+            for &tile_size in [1, 7, 16, 64].iter() {
+                assert!(stamp(tile_size) == expected, "tile_size = {}", tile_size);
+            }
+        }
+    }
+}
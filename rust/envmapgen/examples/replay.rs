@@ -0,0 +1,182 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Replays a capture file through `Context` and writes the resulting cube
+//! map and the profiling result.
+//!
+//! The frames are stamped with the settings and grouped into the batches
+//! recorded in the capture file, so the result matches that of the recorded
+//! session.
+extern crate cgmath;
+extern crate clap;
+extern crate image;
+extern crate envmapgen;
+extern crate arcane_gfx;
+
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::Path;
+use cgmath::Matrix4;
+
+use arcane_gfx::convention::{remap_cube_map, CubeMapConvention};
+use arcane_gfx::cubemap::CubeFace;
+use envmapgen::capture::{CaptureReader, Frame};
+use envmapgen::context::Context;
+
+fn face_name(face: CubeFace) -> &'static str {
+    match face {
+        CubeFace::PositiveX => "posx",
+        CubeFace::NegativeX => "negx",
+        CubeFace::PositiveY => "posy",
+        CubeFace::NegativeY => "negy",
+        CubeFace::PositiveZ => "posz",
+        CubeFace::NegativeZ => "negz",
+    }
+}
+
+fn main() {
+    use clap::{App, Arg};
+    let matches = App::new("replay")
+        .author("ARcane Developers")
+        .about("Replays a capture file through the environment map generator")
+        .arg(
+            Arg::with_name("input")
+                .required(true)
+                .index(1)
+                .value_name("CAPTURE")
+                .help("Specifies the path to a capture file."),
+        )
+        .arg(
+            Arg::with_name("output")
+                .required(true)
+                .index(2)
+                .value_name("OUTDIR")
+                .help(
+                    "Specifies the directory to save the generated cube map into. \
+                     Each face of each mip level is saved as `LEVEL/FACE.png` \
+                     (e.g., `0/posx.png`), and the profiling result is saved as \
+                     `profile.txt`.",
+                ),
+        )
+        .arg(
+            Arg::with_name("final_only")
+                .long("final-only")
+                .help(
+                    "Process the cube map only once after all frames were \
+                     stamped, instead of after every frame like the web \
+                     application does",
+                ),
+        )
+        .arg(
+            Arg::with_name("convention")
+                .long("convention")
+                .takes_value(true)
+                .value_name("NAME")
+                .possible_values(CubeMapConvention::NAMES)
+                .default_value("opengl")
+                .help("Specifies the convention of the camera matrices in the capture file."),
+        )
+        .arg(
+            Arg::with_name("output_convention")
+                .long("output-convention")
+                .takes_value(true)
+                .value_name("NAME")
+                .possible_values(CubeMapConvention::NAMES)
+                .default_value("opengl")
+                .help("Specifies the convention of the saved cube map."),
+        )
+        .get_matches();
+
+    let input_path = Path::new(matches.value_of_os("input").unwrap());
+    let output_path = Path::new(matches.value_of_os("output").unwrap());
+    let final_only = matches.is_present("final_only");
+    let convention =
+        CubeMapConvention::by_name(matches.value_of("convention").unwrap()).unwrap();
+    let output_convention =
+        CubeMapConvention::by_name(matches.value_of("output_convention").unwrap()).unwrap();
+
+    let mut reader = CaptureReader::new(BufReader::new(File::open(input_path).unwrap())).unwrap();
+    println!("Intrinsics: {:?}", reader.intrinsics());
+
+    let mut context = Context::new();
+    let mut num_frames = 0;
+    let mut batch: Vec<Frame> = Vec::new();
+    let mut frames = reader.by_ref().map(Result::unwrap).peekable();
+    while let Some(mut frame) = frames.next() {
+        frame.camera_matrix = frame.camera_matrix * Matrix4::from(convention.from_canonical());
+        batch.push(frame);
+        if frames.peek().map(|f| f.continues_batch).unwrap_or(false) {
+            continue;
+        }
+
+        context.set_stamp_settings(&batch[0].settings);
+        let result = {
+            let stamp_frames: Vec<_> = batch.iter().map(Frame::stamp_frame).collect();
+            context.try_stamp_batch(&stamp_frames)
+        };
+        if let Err(e) = result {
+            println!(
+                "Frame {}..{} (t = {}): skipped ({})",
+                num_frames,
+                num_frames + batch.len(),
+                batch[0].timestamp,
+                e
+            );
+        }
+        if !final_only {
+            context.process();
+        }
+        num_frames += batch.len();
+        batch.clear();
+    }
+    println!("Replayed {} frame(s)", num_frames);
+
+    if final_only || num_frames == 0 {
+        context.process();
+    }
+
+    // Output the processed images
+    for level in 0..context.num_levels() {
+        let level_path = output_path.join(format!("{}", level));
+        fs::create_dir_all(&level_path).unwrap();
+
+        let size = context.try_output_image(level, 0).unwrap().width;
+        let faces: Vec<_> = (0..6)
+            .map(|i| context.try_output_image(level, i).unwrap().data)
+            .collect();
+        let mut remapped = vec![faces[0].to_vec(); 6];
+        remap_cube_map(
+            remapped
+                .iter_mut()
+                .map(Vec::as_mut_slice)
+                .collect::<Vec<_>>()
+                .as_mut_slice(),
+            &faces,
+            size,
+            &CubeMapConvention::OPENGL,
+            &output_convention,
+        );
+
+        for (i, data) in remapped.iter().enumerate() {
+            let face = CubeFace::from_ordinal(i).unwrap();
+            let mut img = image::RgbaImage::new(size as u32, size as u32);
+            for (y, x) in img.pixels_mut().zip(data.iter()) {
+                y.data = [x.x, x.y, x.z, x.w];
+            }
+
+            let path = level_path.join(format!("{}.png", face_name(face)));
+            println!("Saving {}", path.display());
+            img.save(&path).unwrap();
+        }
+    }
+
+    let profile = format!("{}\n", context.profile());
+    print!("{}", profile);
+    File::create(output_path.join("profile.txt"))
+        .unwrap()
+        .write_all(profile.as_bytes())
+        .unwrap();
+}
@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+
+#ifndef ENVMAPGEN_H
+#define ENVMAPGEN_H
+
+/* Generated with cbindgen from `src/lib.rs` - do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+enum EmgStatus {
+  EMG_STATUS_OK = 0,
+  EMG_STATUS_DIMENSION_MISMATCH = 1,
+  EMG_STATUS_INVALID_MIP_LEVEL = 2,
+  EMG_STATUS_INVALID_CUBE_FACE = 3,
+  EMG_STATUS_ALLOCATION_FAILED = 4,
+  EMG_STATUS_NON_FINITE_MATRIX = 5,
+  EMG_STATUS_INVALID_CAMERA = 6,
+  EMG_STATUS_SINGULAR_MATRIX = 7,
+  EMG_STATUS_INVALID_FRAME_PARAMS = 8,
+  EMG_STATUS_INVALID_RESPONSE_CURVE = 9,
+  EMG_STATUS_INVALID_CONFIDENCE_CURVE = 10,
+  EMG_STATUS_INVALID_ARGUMENT = 11,
+};
+typedef int32_t EmgStatus;
+
+typedef struct EmgContext EmgContext;
+
+/**
+ * The camera parameters passed to `emg_context_stamp_camera`.
+ */
+typedef struct {
+  /**
+   * The camera orientation as a unit quaternion `(x, y, z, w)`, which
+   * rotates a vector from the camera's local space (`-Z` forward, `+Y`
+   * up) into the world space.
+   */
+  float orientation[4];
+  /**
+   * The vertical field of view in radians.
+   */
+  float fov_y;
+  /**
+   * The ratio of the image width to its height.
+   */
+  float aspect;
+} EmgCameraParams;
+
+/**
+ * The intrinsic parameters of the camera used for a capture.
+ */
+typedef struct {
+  uint32_t width;
+  uint32_t height;
+  float focal_length[2];
+  float principal_point[2];
+} EmgIntrinsics;
+
+/**
+ * A light source returned by `emg_context_get_lights`.
+ */
+typedef struct {
+  /**
+   * The normalized direction toward the light source.
+   */
+  float direction[3];
+  /**
+   * The irradiance contributed by the light source, relative to the
+   * reference exposure.
+   */
+  float color[3];
+  /**
+   * The angular spread of the light source in radians.
+   */
+  float spread;
+} EmgLightDesc;
+
+/**
+ * Describes a camera image passed to `emg_context_stamp_batch`.
+ */
+typedef struct {
+  /**
+   * The RGBA image data.
+   */
+  const uint8_t *image;
+  uintptr_t width;
+  uintptr_t height;
+  /**
+   * The view-projection matrix in the column-major order.
+   */
+  float camera_matrix[16];
+  /**
+   * The weight of the frame relative to other frames in the batch.
+   */
+  float weight;
+  /**
+   * The exposure value of the image in stops relative to the reference
+   * exposure, in the range `[-32, 32]`.
+   */
+  float exposure;
+} EmgStampFrameDesc;
+
+/**
+ * A view direction returned by `emg_context_suggest_view_direction`.
+ */
+typedef struct {
+  /**
+   * The normalized direction.
+   */
+  float direction[3];
+  /**
+   * The angular radius (in radians) of the unobserved region that would
+   * be captured.
+   */
+  float cone_angle;
+  /**
+   * The unobserved solid angle (in steradians) that would be captured.
+   */
+  float solid_angle;
+} EmgViewSuggestionDesc;
+
+/**
+ * Returns the length (in bytes) of the UTF-8 message describing the last
+ * error, or zero if no error has occured so far.
+ */
+uintptr_t emg_last_error_len(void);
+
+/**
+ * Copies the message describing the last error into the specified buffer.
+ * Returns the number of bytes written, which is at most `len`. The message
+ * is not null-terminated. Nothing is written if `buffer` is `NULL`.
+ */
+uintptr_t emg_last_error_copy(uint8_t *buffer, uintptr_t len);
+
+/**
+ * Returns a null pointer if the allocation has failed.
+ */
+uint8_t *emg_malloc(uintptr_t size);
+
+/**
+ * Does nothing if `p` is a null pointer.
+ */
+void emg_free(uint8_t *p);
+
+EmgContext *emg_context_new(void);
+
+void emg_context_destroy(EmgContext *this_);
+
+EmgStatus emg_context_stamp(EmgContext *this_,
+                            const uint8_t *image,
+                            uintptr_t width,
+                            uintptr_t height,
+                            const float *camera_matrix);
+
+/**
+ * Stamps a camera image using a camera orientation instead of a matrix.
+ */
+EmgStatus emg_context_stamp_camera(EmgContext *this_,
+                                   const uint8_t *image,
+                                   uintptr_t width,
+                                   uintptr_t height,
+                                   const EmgCameraParams *camera);
+
+/**
+ * Stamps `num_frames` camera images described by the array `frames`.
+ */
+EmgStatus emg_context_stamp_batch(EmgContext *this_,
+                                  const EmgStampFrameDesc *frames,
+                                  uintptr_t num_frames);
+
+/**
+ * Enables or disables the automatic exposure and white balance
+ * compensation.
+ */
+void emg_context_set_auto_gain(EmgContext *this_, bool exposure, bool white_balance);
+
+/**
+ * Stores the gain applied to the last stamped frame (R, G, and B) to
+ * `out_gain[0..3]` unless it is `NULL`. Returns the number of texels the
+ * estimation was based on.
+ */
+uintptr_t emg_context_get_last_gain(EmgContext *this_, float *out_gain);
+
+/**
+ * Sets the camera response curve to `x^gamma`.
+ */
+EmgStatus emg_context_set_response_gamma(EmgContext *this_, float gamma);
+
+/**
+ * Sets the camera response curve to a calibrated table of 256 linear values
+ * in the range `[0, 1]`, indexed by the pixel value.
+ */
+EmgStatus emg_context_set_response_table(EmgContext *this_, const float *table);
+
+/**
+ * Enables or disables the HDR merging of stamped frames.
+ */
+void emg_context_set_hdr_merge(EmgContext *this_, bool enabled);
+
+uintptr_t emg_context_get_image_size(EmgContext *this_);
+
+/**
+ * Updates the output from the stamped images. The status is reserved for
+ * errors reported by future versions; currently it is always
+ * `EMG_STATUS_OK`.
+ */
+EmgStatus emg_context_process(EmgContext *this_);
+
+/**
+ * Stores the pointer to the specified output image to `*out_data`.
+ */
+EmgStatus emg_context_get_output_image_data(EmgContext *this_,
+                                            uintptr_t mip_level,
+                                            uintptr_t cube_face,
+                                            const uint8_t **out_data);
+
+/**
+ * Returns the size (in bytes) of the buffer required by
+ * `emg_context_copy_output`.
+ */
+uintptr_t emg_context_output_size(EmgContext *this_);
+
+/**
+ * Copies all mip levels and faces of the output image into the specified
+ * buffer. See `Context::copy_output` for the layout.
+ */
+EmgStatus emg_context_copy_output(EmgContext *this_, uint8_t *buffer, uintptr_t len);
+
+/**
+ * Copies the mip levels modified since the last copy into the specified
+ * buffer, which must have the same layout as the one used by
+ * `emg_context_copy_output`. A bit mask of the mip levels that were written
+ * is stored to `*out_levels`.
+ */
+EmgStatus emg_context_copy_changed_output(EmgContext *this_,
+                                          uint8_t *buffer,
+                                          uintptr_t len,
+                                          uint32_t *out_levels);
+
+/**
+ * Stores the observed fraction of each cube face to `out_faces[0..6]` unless
+ * it is `NULL`. Returns the observed fraction of the whole environment.
+ */
+float emg_context_get_coverage(EmgContext *this_, float *out_faces);
+
+/**
+ * Returns the number of `f32` values written by
+ * `emg_context_copy_coverage_map` for the specified mip level, or zero if
+ * the mip level is out of range.
+ */
+uintptr_t emg_context_coverage_map_len(EmgContext *this_, uintptr_t mip_level);
+
+/**
+ * Copies the per-texel coverage and confidence of the specified mip level
+ * into the specified buffer. See `Context::copy_coverage_map` for the
+ * layout.
+ */
+EmgStatus emg_context_copy_coverage_map(EmgContext *this_,
+                                        uintptr_t mip_level,
+                                        float *buffer,
+                                        uintptr_t len);
+
+/**
+ * Suggests the view direction that would add the most unobserved solid
+ * angle to the captured environment, assuming a camera whose field of view
+ * is approximated by a cone with the half angle `half_fov` (in radians).
+ *
+ * If `upper_hemisphere_only` is `true`, only directions with a non-negative
+ * Y coordinate are considered. If `reference_direction` is not null, only
+ * directions within `max_angle` (in radians) from the direction
+ * `reference_direction[0..3]` are considered.
+ *
+ * Returns `false` if every allowed direction has already been observed.
+ * Otherwise, the suggestion is stored to `*out_suggestion` unless it is
+ * `NULL`.
+ */
+bool emg_context_suggest_view_direction(EmgContext *this_,
+                                        float half_fov,
+                                        bool upper_hemisphere_only,
+                                        const float *reference_direction,
+                                        float max_angle,
+                                        EmgViewSuggestionDesc *out_suggestion);
+
+/**
+ * Enables the extraction of up to `max_lights` dominant light sources by
+ * `emg_context_process`, or disables it if `max_lights` is zero. If
+ * `remove` is `true`, the extracted lights are removed from the output.
+ */
+void emg_context_set_light_extraction(EmgContext *this_, uintptr_t max_lights, bool remove);
+
+/**
+ * Copies up to `max_lights` lights extracted by the last call to
+ * `emg_context_process` into `out_lights`. Returns the total number of
+ * extracted lights. `out_lights` may be `NULL` if `max_lights` is zero.
+ */
+uintptr_t emg_context_get_lights(EmgContext *this_,
+                                 EmgLightDesc *out_lights,
+                                 uintptr_t max_lights);
+
+/**
+ * Enables or disables the priors for the unobserved directions: a floor
+ * color projected onto a ground plane `ground_height` below the origin
+ * (`ground`), and a sky gradient (`sky`). Both are disabled by default.
+ */
+void emg_context_set_prior(EmgContext *this_, bool ground, float ground_height, bool sky);
+
+/**
+ * Sets the fallback environment map shown in the unobserved directions.
+ * `data` points to six `size`×`size` cube faces in the order `+X`, `-X`,
+ * `+Y`, `-Y`, `+Z`, and `-Z`, each texel consisting of four `f32` values in
+ * the RGBA order, in the same scale as the output of
+ * `emg_context_copy_hdr_output`. The alpha channel is ignored. `size` must
+ * be a multiple or a divisor of the image size. Pass `NULL` to remove the
+ * fallback.
+ */
+EmgStatus emg_context_set_fallback(EmgContext *this_, const float *data, uintptr_t size);
+
+/**
+ * Sets the curve that maps the accumulated weight of each captured texel to
+ * the fraction of the captured value blended with the fallback environment
+ * map: `min(weight / full_weight, 1)^exponent`. Both parameters must be finite
+ * and positive.
+ */
+EmgStatus emg_context_set_confidence_curve(EmgContext *this_, float full_weight, float exponent);
+
+/**
+ * Sets the number of spherical Gaussians fitted to the environment by
+ * `emg_context_process`. Zero (the default) disables the fitting.
+ */
+void emg_context_set_num_sg_lobes(EmgContext *this_, uintptr_t num_lobes);
+
+/**
+ * Returns the number of `f32` values written by
+ * `emg_context_copy_spherical_gaussians`.
+ */
+uintptr_t emg_context_sg_len(EmgContext *this_);
+
+/**
+ * Copies the spherical Gaussians fitted by the last call to
+ * `emg_context_process` into the specified buffer. See
+ * `Context::copy_spherical_gaussians` for the layout.
+ */
+EmgStatus emg_context_copy_spherical_gaussians(EmgContext *this_, float *buffer, uintptr_t len);
+
+/**
+ * Returns the number of `f32` values written by
+ * `emg_context_copy_hdr_output`.
+ */
+uintptr_t emg_context_hdr_output_len(EmgContext *this_);
+
+/**
+ * Copies all mip levels and faces of the output image into the specified
+ * buffer as linear radiance values. See `Context::copy_hdr_output` for the
+ * layout.
+ */
+EmgStatus emg_context_copy_hdr_output(EmgContext *this_, float *buffer, uintptr_t len);
+
+/**
+ * Returns the number of `f64` values written by `emg_context_copy_profile`.
+ */
+uintptr_t emg_context_profile_len(EmgContext *this_);
+
+/**
+ * Copies the profiling result into the specified buffer. See
+ * `Profile::write_flat` for the layout. If `reset` is `true`, the counters
+ * are reset afterwards.
+ */
+EmgStatus emg_context_copy_profile(EmgContext *this_,
+                                   double *buffer,
+                                   uintptr_t len,
+                                   bool reset);
+
+/**
+ * Starts recording the frames stamped by `emg_context_stamp`,
+ * `emg_context_stamp_camera` and `emg_context_stamp_batch` into a capture
+ * file, discarding the current recording if any. See
+ * `Context::start_recording`.
+ */
+EmgStatus emg_context_start_recording(EmgContext *this_,
+                                      const EmgIntrinsics *intrinsics,
+                                      uintptr_t downscale);
+
+/**
+ * Returns the size (in bytes) of the capture file recorded so far, or zero
+ * if not recording.
+ */
+uintptr_t emg_context_recording_len(EmgContext *this_);
+
+/**
+ * Copies the capture file recorded so far into the specified buffer, which
+ * must be at least `emg_context_recording_len` bytes long.
+ */
+EmgStatus emg_context_copy_recording(EmgContext *this_, uint8_t *buffer, uintptr_t len);
+
+/**
+ * Stops recording and discards the recorded capture file.
+ */
+void emg_context_stop_recording(EmgContext *this_);
+
+EmgStatus emg_spherical_blur(uintptr_t size, uint8_t *images);
+
+#endif /* ENVMAPGEN_H */
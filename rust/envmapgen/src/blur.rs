@@ -11,16 +11,23 @@ use cgmath::Vector4;
 use smallvec::SmallVec;
 use hyperenvmap::ltasgblur;
 
+use arcane_gfx::{Error, Result};
+
 use cubemaputils;
 
 const NUM_LEVELS: usize = 5;
 
-pub unsafe fn apply_spherical_blur(size: usize, mut images: *mut Vector4<u8>) {
+/// `size` must be a non-zero multiple of `2^(NUM_LEVELS - 1)` so every mip
+/// level can be produced by halving the previous one.
+pub unsafe fn apply_spherical_blur(size: usize, mut images: *mut Vector4<u8>) -> Result<()> {
+    if size == 0 || size % (1 << (NUM_LEVELS - 1)) != 0 {
+        return Err(Error::DimensionMismatch);
+    }
+
     // Extract each level/face as a slice
     let mut image_levels = (0..NUM_LEVELS)
         .map(|level| {
             let level_size = size >> level;
-            assert_ne!(level_size, 0);
 
             (0..6)
                 .map(|_| {
@@ -38,7 +45,8 @@ pub unsafe fn apply_spherical_blur(size: usize, mut images: *mut Vector4<u8>) {
         .map(SmallVec::as_mut_slice)
         .collect::<SmallVec<[_; 6]>>();
 
-    apply_spherical_blur_inner(size, image_levels.as_mut_slice())
+    apply_spherical_blur_inner(size, image_levels.as_mut_slice());
+    Ok(())
 }
 
 fn apply_spherical_blur_inner(size: usize, image_levels: &mut [&mut [&mut [Vector4<u8>]]]) {
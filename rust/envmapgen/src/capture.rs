@@ -0,0 +1,436 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Reads and writes recorded AR sessions (captures).
+//!
+//! A capture file consists of a header followed by zero or more frames. All
+//! values are little-endian.
+//!
+//! Header:
+//!
+//! | Type      | Description                                     |
+//! | --------- | ----------------------------------------------- |
+//! | `[u8; 8]` | Magic number `EMGCAP\0\0`                       |
+//! | `u32`     | Format version (`2`)                            |
+//! | `u32`     | Camera image width (before downscaling)         |
+//! | `u32`     | Camera image height (before downscaling)        |
+//! | `f32` × 2 | Focal length (`fx`, `fy`) in pixels, or zero   |
+//! | `f32` × 2 | Principal point (`cx`, `cy`) in pixels          |
+//!
+//! The focal length is zero if unknown, e.g., if only the camera matrices
+//! were available to the recorder. `Context::start_recording` records the
+//! frames stamped by `Context` in this format.
+//!
+//! Frame:
+//!
+//! | Type        | Description                                    |
+//! | ----------- | ---------------------------------------------- |
+//! | `f64`       | Timestamp in seconds                           |
+//! | `u32`       | Flags (see below)                              |
+//! | `f32` × 256 | Response curve (only if flag bit 4 is set)     |
+//! | `f32`       | Weight                                         |
+//! | `f32`       | Exposure                                       |
+//! | `f32` × 16  | Camera matrix (column-major)                   |
+//! | `u32`       | Image width (at most the camera image width)   |
+//! | `u32`       | Image height (at most the camera image height) |
+//! | `u8` × 4wh  | Image data (RGBA, row-major)                   |
+//!
+//! The flag bits are:
+//!
+//!  - Bit 0: The frame was stamped by the same `Context::stamp_batch` call
+//!    as the previous frame.
+//!  - Bit 1: `StampSettings::hdr_merge`.
+//!  - Bit 2: `AutoGain::exposure`.
+//!  - Bit 3: `AutoGain::white_balance`.
+//!  - Bit 4: The response curve follows, as the values returned by
+//!    `ResponseCurve::table`. Set for the first frame and whenever the curve
+//!    changes; other frames use the one of the previous frame.
+//!
+//! The weight, the exposure and the camera matrix are those of `StampFrame`.
+//!
+//! Version 1 files lack the flags, the response curve, the weight and the
+//! exposure. Their frames are read as if they were stamped by `Context::stamp`
+//! with the default settings.
+use std::io::{self, Read, Write};
+use cgmath::{Vector4, Matrix4};
+
+use arcane_gfx::Image;
+use arcane_gfx::response::ResponseCurve;
+
+use context::{AutoGain, StampFrame, StampSettings};
+
+const MAGIC: &[u8; 8] = b"EMGCAP\0\0";
+const VERSION: u32 = 2;
+
+const FLAG_CONTINUES_BATCH: u32 = 1 << 0;
+const FLAG_HDR_MERGE: u32 = 1 << 1;
+const FLAG_AUTO_EXPOSURE: u32 = 1 << 2;
+const FLAG_AUTO_WHITE_BALANCE: u32 = 1 << 3;
+const FLAG_RESPONSE_CURVE: u32 = 1 << 4;
+
+/// The intrinsic parameters of the camera used for a capture.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct Intrinsics {
+    pub width: u32,
+    pub height: u32,
+    pub focal_length: [f32; 2],
+    pub principal_point: [f32; 2],
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub timestamp: f64,
+    pub camera_matrix: Matrix4<f32>,
+    pub weight: f32,
+    pub exposure: f32,
+
+    /// Whether this frame was stamped by the same `Context::stamp_batch` call
+    /// as the previous frame.
+    pub continues_batch: bool,
+
+    /// The settings of `Context` when the frame was stamped.
+    pub settings: StampSettings,
+
+    pub image: Image<Vec<Vector4<u8>>>,
+}
+
+impl Frame {
+    pub fn stamp_frame(&self) -> StampFrame {
+        StampFrame {
+            image: self.image.as_ref(),
+            camera_matrix: self.camera_matrix,
+            weight: self.weight,
+            exposure: self.exposure,
+        }
+    }
+}
+
+pub struct CaptureWriter<W> {
+    writer: W,
+    intrinsics: Intrinsics,
+
+    /// The last response curve written.
+    response: Option<ResponseCurve>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, intrinsics: &Intrinsics) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, VERSION)?;
+        write_u32(&mut writer, intrinsics.width)?;
+        write_u32(&mut writer, intrinsics.height)?;
+        for &x in intrinsics.focal_length.iter().chain(intrinsics.principal_point.iter()) {
+            write_f32(&mut writer, x)?;
+        }
+        Ok(Self {
+            writer,
+            intrinsics: *intrinsics,
+            response: None,
+        })
+    }
+
+    pub fn intrinsics(&self) -> &Intrinsics {
+        &self.intrinsics
+    }
+
+    /// Writes a frame stamped by `Context::stamp_frame` with the specified
+    /// settings. The image is downscaled by the factor `downscale` (which
+    /// must be non-zero) using a box filter before being written. The image
+    /// must not be larger than the camera image specified by the intrinsics.
+    pub fn write_frame(
+        &mut self,
+        timestamp: f64,
+        frame: &StampFrame,
+        settings: &StampSettings,
+        downscale: usize,
+    ) -> io::Result<()> {
+        self.write_batch(timestamp, &[*frame], settings, downscale)
+    }
+
+    /// Writes frames stamped by a single call to `Context::stamp_batch`. See
+    /// `write_frame`.
+    pub fn write_batch(
+        &mut self,
+        timestamp: f64,
+        frames: &[StampFrame],
+        settings: &StampSettings,
+        downscale: usize,
+    ) -> io::Result<()> {
+        let valid = {
+            let ref intrinsics = self.intrinsics;
+            frames.iter().all(|frame| {
+                frame.image.check_len().is_ok() &&
+                    frame.image.width <= intrinsics.width as usize &&
+                    frame.image.height <= intrinsics.height as usize
+            })
+        };
+        if !valid || downscale == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid image size or downscaling factor",
+            ));
+        }
+
+        for (i, frame) in frames.iter().enumerate() {
+            let mut flags = 0;
+            if i > 0 {
+                flags |= FLAG_CONTINUES_BATCH;
+            }
+            if settings.hdr_merge {
+                flags |= FLAG_HDR_MERGE;
+            }
+            if settings.auto_gain.exposure {
+                flags |= FLAG_AUTO_EXPOSURE;
+            }
+            if settings.auto_gain.white_balance {
+                flags |= FLAG_AUTO_WHITE_BALANCE;
+            }
+            let write_response = self.response != Some(settings.response);
+            if write_response {
+                flags |= FLAG_RESPONSE_CURVE;
+            }
+
+            write_f64(&mut self.writer, timestamp)?;
+            write_u32(&mut self.writer, flags)?;
+            if write_response {
+                for &x in settings.response.table().iter() {
+                    write_f32(&mut self.writer, x)?;
+                }
+                self.response = Some(settings.response);
+            }
+            write_f32(&mut self.writer, frame.weight)?;
+            write_f32(&mut self.writer, frame.exposure)?;
+            self.write_image(frame.camera_matrix, &frame.image, downscale)?;
+        }
+        Ok(())
+    }
+
+    fn write_image(
+        &mut self,
+        camera_matrix: Matrix4<f32>,
+        image: &Image<&[Vector4<u8>]>,
+        downscale: usize,
+    ) -> io::Result<()> {
+        let ref mut writer = self.writer;
+        let m: &[f32; 16] = camera_matrix.as_ref();
+        for &x in m.iter() {
+            write_f32(writer, x)?;
+        }
+
+        let width = image.width / downscale;
+        let height = image.height / downscale;
+        write_u32(writer, width as u32)?;
+        write_u32(writer, height as u32)?;
+
+        let mut line = Vec::with_capacity(width * 4);
+        for y in 0..height {
+            line.clear();
+            for x in 0..width {
+                let mut sum = Vector4::new(0u32, 0, 0, 0);
+                for sy in y * downscale..(y + 1) * downscale {
+                    for sx in x * downscale..(x + 1) * downscale {
+                        let p = image.data[sx + sy * image.width];
+                        sum += Vector4::new(p.x as u32, p.y as u32, p.z as u32, p.w as u32);
+                    }
+                }
+                let n = (downscale * downscale) as u32;
+                line.extend_from_slice(&[
+                    ((sum.x + n / 2) / n) as u8,
+                    ((sum.y + n / 2) / n) as u8,
+                    ((sum.z + n / 2) / n) as u8,
+                    ((sum.w + n / 2) / n) as u8,
+                ]);
+            }
+            writer.write_all(&line)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub struct CaptureReader<R> {
+    reader: R,
+    version: u32,
+    intrinsics: Intrinsics,
+
+    /// The response curve of the last frame read.
+    response: ResponseCurve,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a capture file"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version < 1 || version > VERSION {
+            return Err(invalid_data("unsupported capture file version"));
+        }
+
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let fx = read_f32(&mut reader)?;
+        let fy = read_f32(&mut reader)?;
+        let cx = read_f32(&mut reader)?;
+        let cy = read_f32(&mut reader)?;
+
+        Ok(Self {
+            reader,
+            version,
+            intrinsics: Intrinsics {
+                width,
+                height,
+                focal_length: [fx, fy],
+                principal_point: [cx, cy],
+            },
+            response: ResponseCurve::default(),
+        })
+    }
+
+    pub fn intrinsics(&self) -> &Intrinsics {
+        &self.intrinsics
+    }
+
+    /// Reads the next frame. Returns `Ok(None)` at the end of the file.
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        let ref mut reader = self.reader;
+
+        // Detect the end of the file
+        let mut timestamp = [0u8; 8];
+        let mut filled = 0;
+        while filled < timestamp.len() {
+            match reader.read(&mut timestamp[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let timestamp = f64::from_bits(le_u64(&timestamp));
+
+        let flags = if self.version >= 2 { read_u32(reader)? } else { 0 };
+        if flags & FLAG_RESPONSE_CURVE != 0 {
+            let mut table = [0f32; 256];
+            for x in table.iter_mut() {
+                *x = read_f32(reader)?;
+            }
+            self.response = ResponseCurve::from_linear_table(&table)
+                .map_err(|_| invalid_data("invalid response curve"))?;
+        }
+        let (weight, exposure) = if self.version >= 2 {
+            (read_f32(reader)?, read_f32(reader)?)
+        } else {
+            (1.0, 0.0)
+        };
+
+        let mut m = [[0f32; 4]; 4];
+        for column in m.iter_mut() {
+            for x in column.iter_mut() {
+                *x = read_f32(reader)?;
+            }
+        }
+        let camera_matrix = Matrix4::from(m);
+
+        // The images are downscaled from the camera image, so the camera
+        // image size specified by the header bounds the allocation
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        if width > self.intrinsics.width || height > self.intrinsics.height {
+            return Err(invalid_data("image is larger than the camera image"));
+        }
+        let (width, height) = (width as usize, height as usize);
+        let len = width.checked_mul(height).and_then(|x| x.checked_mul(4));
+        let len = len.ok_or_else(|| invalid_data("image is too large"))?;
+
+        // Grow the buffer as the data is read so that a truncated file does
+        // not cause a large allocation
+        let mut bytes = Vec::new();
+        Read::by_ref(reader).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let data = bytes
+            .chunks(4)
+            .map(|p| Vector4::new(p[0], p[1], p[2], p[3]))
+            .collect();
+
+        Ok(Some(Frame {
+            timestamp,
+            camera_matrix,
+            weight,
+            exposure,
+            continues_batch: flags & FLAG_CONTINUES_BATCH != 0,
+            settings: StampSettings {
+                response: self.response,
+                hdr_merge: flags & FLAG_HDR_MERGE != 0,
+                auto_gain: AutoGain {
+                    exposure: flags & FLAG_AUTO_EXPOSURE != 0,
+                    white_balance: flags & FLAG_AUTO_WHITE_BALANCE != 0,
+                },
+            },
+            image: Image {
+                data,
+                width,
+                height,
+            },
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_frame() {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn le_u64(b: &[u8; 8]) -> u64 {
+    b.iter().rev().fold(0, |x, &b| (x << 8) | b as u64)
+}
+
+fn write_u32<W: Write>(writer: &mut W, x: u32) -> io::Result<()> {
+    writer.write_all(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8])
+}
+
+fn write_f32<W: Write>(writer: &mut W, x: f32) -> io::Result<()> {
+    write_u32(writer, x.to_bits())
+}
+
+fn write_f64<W: Write>(writer: &mut W, x: f64) -> io::Result<()> {
+    let x = x.to_bits();
+    write_u32(writer, x as u32)?;
+    write_u32(writer, (x >> 32) as u32)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    reader.read_exact(&mut b)?;
+    Ok(b.iter().rev().fold(0, |x, &b| (x << 8) | b as u32))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    read_u32(reader).map(f32::from_bits)
+}
@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Provides a monotonic clock for profiling.
+//!
+//! On WebAssembly, the clock is supplied by the host through the
+//! `emg_clock_now` import, which must return the current time in
+//! milliseconds (e.g., `performance.now()`).
+
+#[cfg(target_arch = "wasm32")]
+mod imp {
+    extern "C" {
+        fn emg_clock_now() -> f64;
+    }
+
+    pub fn now() -> f64 {
+        unsafe { emg_clock_now() * 1.0e-3 }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod imp {
+    use std::time::Instant;
+
+    lazy_static! {
+        static ref EPOCH: Instant = Instant::now();
+    }
+
+    pub fn now() -> f64 {
+        let elapsed = EPOCH.elapsed();
+        elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1.0e-9
+    }
+}
+
+/// Returns the current time in seconds, measured from an unspecified point.
+pub use self::imp::now;
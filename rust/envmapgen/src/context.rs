@@ -4,21 +4,69 @@
  * This file is a part of ARcane. Please read the license text that
  * comes with the source code for use conditions.
  */
-use cgmath::{Vector4, Matrix4};
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+use cgmath::{Vector3, Vector4, Matrix4, Rad};
 use cgmath::prelude::*;
 use smallvec::SmallVec;
 
-use arcane_gfx::Image;
-use arcane_gfx::stamp;
-use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::{Image, Error, Result};
+use arcane_gfx::camera::Camera;
+use arcane_gfx::stamp::{self, BlendMode, StampOptions};
+use arcane_gfx::response::{ResponseCurve, PixelWeighting, LINEAR_WHITE};
+use arcane_gfx::lights::{self, DirectionalLight, LightExtractionParams};
+use arcane_gfx::sg::{self, SphericalGaussian};
+use arcane_gfx::cubemap::{CUBE_FACES, texel_solid_angle};
 
 use hyperenvmap::ltasgblur;
 
+use capture::{CaptureWriter, Intrinsics};
+use clock;
 use cubemaputils;
+use fallback::{ConfidenceCurve, FallbackEnvironment};
+use prior::{EnvironmentPrior, PriorParams};
+use profile::Profile;
 
 const LOG_SIZE: usize = 6;
 const SIZE: usize = 1 << LOG_SIZE;
 
+/// The minimum number of overlapping texels required to estimate the gain.
+const GAIN_MIN_SAMPLES: usize = 64;
+
+/// The estimated gain is clamped to `[1 / GAIN_MAX, GAIN_MAX]`.
+const GAIN_MAX: f32 = 16.0;
+
+/// Linearized values outside this range are likely to be clipped or
+/// dominated by noise and are excluded from the gain estimation.
+const GAIN_SAMPLE_MIN: f32 = 16.0 * 16.0;
+const GAIN_SAMPLE_MAX: f32 = 250.0 * 250.0;
+
+/// When merging HDR frames, the accumulated weight of the previously
+/// observed texels is capped at this value so that the cube map can still
+/// adapt to changes in the scene.
+const HDR_MAX_HISTORY_WEIGHT: f32 = 8.0;
+
+/// The accumulated weight at which a texel is considered to be observed with
+/// full confidence (e.g., by a single well-exposed pixel).
+const CONFIDENCE_FULL_WEIGHT: f32 = 1.0;
+
+/// The largest magnitude of `StampFrame::exposure` accepted by
+/// `Context::stamp_batch`. Beyond this, `2^-exposure` multiplied by the
+/// linearized color values would overflow or underflow `f32`.
+pub const MAX_EXPOSURE: f32 = 32.0;
+
+/// The mip level at which the unobserved solid angle is evaluated by
+/// `Context::suggest_view_direction`. The directions of its texels are the
+/// candidate view directions.
+const VIEW_SUGGESTION_LEVEL: usize = 3;
+
+/// The mip level at which `Context::suggest_view_direction` bounds the
+/// solid angle captured from the candidate view directions to prune them.
+const VIEW_CANDIDATE_LEVEL: usize = VIEW_SUGGESTION_LEVEL + 1;
+
+/// The mip level of `env_cube_levels` the spherical Gaussians are fitted to.
+const SG_FIT_LEVEL: usize = 3;
+
 lazy_static! {
     static ref BLUR_SETUP: cubemaputils::MipPyramidGenSetup =
         cubemaputils::MipPyramidGenParams {
@@ -29,18 +77,219 @@ lazy_static! {
             size: SIZE,
             high_quality: false,
         }.setup();
+
+    /// The solid angle of each texel of a `SIZE`×`SIZE` cube face.
+    static ref TEXEL_SOLID_ANGLES: Vec<f32> = (0..SIZE * SIZE)
+        .map(|i| texel_solid_angle(i % SIZE, i / SIZE, SIZE))
+        .collect();
+
+    static ref FACE_SOLID_ANGLE: f32 = TEXEL_SOLID_ANGLES.iter().sum();
+
+    static ref VIEW_SUGGESTION_DIRECTIONS: Vec<Vector3<f32>> =
+        texel_directions(SIZE >> VIEW_SUGGESTION_LEVEL);
+
+    static ref VIEW_CANDIDATE_DIRECTIONS: Vec<Vector3<f32>> =
+        texel_directions(SIZE >> VIEW_CANDIDATE_LEVEL);
+}
+
+/// Returns the normalized directions of the texels of the cube faces whose
+/// size is `size` texels, in the same order as `Context::copy_output`.
+fn texel_directions(size: usize) -> Vec<Vector3<f32>> {
+    CUBE_FACES
+        .iter()
+        .flat_map(|face| {
+            (0..size * size).map(move |i| face.texel_direction(i % size, i / size, size).normalize())
+        })
+        .collect()
+}
+
+/// A camera image passed to `Context::stamp_batch`.
+#[derive(Debug, Copy, Clone)]
+pub struct StampFrame<'a> {
+    pub image: Image<&'a [Vector4<u8>]>,
+
+    /// The view-projection matrix (see `Context::stamp`).
+    pub camera_matrix: Matrix4<f32>,
+
+    /// The weight of the frame relative to other frames in the same batch.
+    /// Must be non-negative.
+    pub weight: f32,
+
+    /// The exposure value of the image in stops relative to the reference
+    /// exposure. The linearized color values are divided by `2^exposure`.
+    /// Must be in the range `[-MAX_EXPOSURE, MAX_EXPOSURE]`.
+    pub exposure: f32,
+}
+
+impl<'a> StampFrame<'a> {
+    /// Constructs a `StampFrame` with the unit weight and the reference
+    /// exposure.
+    pub fn new(image: Image<&'a [Vector4<u8>]>, camera_matrix: Matrix4<f32>) -> Self {
+        Self {
+            image,
+            camera_matrix,
+            weight: 1.0,
+            exposure: 0.0,
+        }
+    }
+
+    /// Constructs a `StampFrame` from a `Camera`. The camera position is
+    /// ignored.
+    pub fn from_camera(image: Image<&'a [Vector4<u8>]>, camera: &Camera) -> Result<Self> {
+        camera.validate()?;
+        Ok(Self::new(image, camera.environment_view_proj_matrix()))
+    }
+}
+
+/// Controls the compensation of exposure and white balance changes between
+/// stamped frames.
+///
+/// The gain of a new frame is estimated by comparing it against previously
+/// observed texels where they overlap. The first frame thus defines the
+/// reference exposure and white balance.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct AutoGain {
+    /// Estimate a gain common to all color channels.
+    pub exposure: bool,
+
+    /// Estimate the relative gains of the color channels.
+    pub white_balance: bool,
+}
+
+/// The settings of `Context` that affect how frames are stamped, e.g., to
+/// be recorded along with the frames.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct StampSettings {
+    pub response: ResponseCurve,
+    pub hdr_merge: bool,
+    pub auto_gain: AutoGain,
+}
+
+/// The gain applied to the last stamped frame (or batch of frames).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GainEstimate {
+    /// The factors the linearized color values were multiplied by.
+    pub gain: Vector3<f32>,
+
+    /// The number of texels the estimation was based on. Zero if there
+    /// was not enough overlap with previously observed texels, in which case
+    /// `gain` is one.
+    pub num_samples: usize,
+}
+
+impl Default for GainEstimate {
+    fn default() -> Self {
+        Self {
+            gain: Vector3::new(1.0, 1.0, 1.0),
+            num_samples: 0,
+        }
+    }
+}
+
+/// The fraction of the environment observed so far, returned by
+/// `Context::coverage`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coverage {
+    /// The observed fraction of the whole sphere, weighted by the solid
+    /// angle.
+    pub total: f32,
+
+    /// The observed fraction of each cube face, weighted by the solid angle.
+    pub faces: [f32; 6],
+}
+
+/// Parameters for `Context::suggest_view_direction`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewSuggestionParams {
+    /// The half angle of the cone approximating the camera's field of view.
+    pub half_fov: Rad<f32>,
+
+    /// Only consider directions in the upper hemisphere (`+Y`).
+    pub upper_hemisphere_only: bool,
+
+    /// If specified, only consider directions within the specified angle
+    /// from the specified (normalized) direction, e.g., the current camera
+    /// orientation.
+    pub reachable_from: Option<(Vector3<f32>, Rad<f32>)>,
+}
+
+impl Default for ViewSuggestionParams {
+    fn default() -> Self {
+        Self {
+            half_fov: Rad(0.5),
+            upper_hemisphere_only: false,
+            reachable_from: None,
+        }
+    }
+}
+
+/// A view direction returned by `Context::suggest_view_direction`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewSuggestion {
+    /// The normalized direction.
+    pub direction: Vector3<f32>,
+
+    /// The angular radius of the unobserved region that would be captured
+    /// by looking toward `direction`.
+    pub cone_angle: Rad<f32>,
+
+    /// The unobserved solid angle (in steradians) that would be captured.
+    pub solid_angle: f32,
+}
+
+/// A capture being recorded by `Context`.
+struct Recording {
+    writer: CaptureWriter<Vec<u8>>,
+    downscale: usize,
 }
 
 pub struct Context {
-    /// Captured environmental image.
+    /// Captured environmental image. The weight channel indicates whether
+    /// each texel has been observed (`1`) or not (`0`).
     raw_cube: Vec<Image<Vec<Vector4<f32>>>>,
 
+    /// The accumulated weight of each texel of `raw_cube`, used by the HDR
+    /// merging.
+    raw_weights: Vec<Vec<f32>>,
+
     /// Processed (or intermediate) environmental cube map image in the RGB + weight format.
     env_cube_levels: Vec<Vec<Image<Vec<Vector4<f32>>>>>,
 
     /// Processed environmental cube map image, converted to the target format.
     converted_cube_levels: Vec<Vec<Image<Vec<Vector4<u8>>>>>,
 
+    /// Bit mask of the levels of `converted_cube_levels` modified since the
+    /// last call to `copy_output`.
+    changed_levels: u32,
+
+    profile: Profile,
+
+    auto_gain: AutoGain,
+    last_gain: GainEstimate,
+
+    response: ResponseCurve,
+    hdr_merge: bool,
+
+    light_params: Option<LightExtractionParams>,
+
+    /// Lights extracted by the last call to `process`.
+    lights: Vec<DirectionalLight>,
+
+    prior_params: Option<PriorParams>,
+
+    /// The priors estimated by the last call to `process`.
+    prior: Option<EnvironmentPrior>,
+
+    fallback: Option<FallbackEnvironment>,
+    confidence_curve: ConfidenceCurve,
+
+    num_sg_lobes: usize,
+
+    /// Spherical Gaussians fitted by the last call to `process`.
+    sg_lobes: Vec<SphericalGaussian>,
+
+    recording: Option<Recording>,
+
     /// Temporary storage
     temp1: Vec<Vec<Vector4<f32>>>,
     temp2: Vec<Vec<Vector4<f32>>>,
@@ -58,6 +307,7 @@ impl Context {
                     }
                 })
                 .collect(),
+            raw_weights: (0..6).map(|_| vec![0.0; SIZE * SIZE]).collect(),
             env_cube_levels: (0..LOG_SIZE + 1)
                 .map(|lod| {
                     let size = SIZE >> lod;
@@ -86,25 +336,622 @@ impl Context {
                         .collect()
                 })
                 .collect(),
+            changed_levels: (1 << (LOG_SIZE + 1)) - 1,
+            profile: Profile::new(
+                &BLUR_SETUP
+                    .levels
+                    .iter()
+                    .map(|&(_, num_passes)| num_passes)
+                    .collect::<Vec<_>>(),
+            ),
+            auto_gain: AutoGain::default(),
+            last_gain: GainEstimate::default(),
+            response: ResponseCurve::default(),
+            hdr_merge: false,
+            light_params: None,
+            lights: Vec::new(),
+            prior_params: None,
+            prior: None,
+            fallback: None,
+            confidence_curve: ConfidenceCurve::default(),
+            num_sg_lobes: 0,
+            sg_lobes: Vec::new(),
+            recording: None,
             temp1: (0..6).map(|_| vec![Vector4::zero(); SIZE * SIZE]).collect(),
             temp2: (0..6).map(|_| vec![Vector4::zero(); SIZE * SIZE]).collect(),
         }
     }
 
+    /// Enables or disables the automatic gain estimation. It is disabled by
+    /// default.
+    pub fn set_auto_gain(&mut self, auto_gain: AutoGain) {
+        self.auto_gain = auto_gain;
+    }
+
+    pub fn auto_gain(&self) -> AutoGain {
+        self.auto_gain
+    }
+
+    /// Returns the gain applied to the last stamped frame (or batch of
+    /// frames).
+    pub fn last_gain(&self) -> &GainEstimate {
+        &self.last_gain
+    }
+
+    /// Sets the response curve used to linearize camera images. Defaults to
+    /// the gamma 2 curve.
+    pub fn set_response_curve(&mut self, response: ResponseCurve) {
+        self.response = response;
+    }
+
+    pub fn response_curve(&self) -> &ResponseCurve {
+        &self.response
+    }
+
+    /// Enables or disables the HDR merging, which is disabled by default.
+    ///
+    /// When enabled, pixels that are likely to be clipped or dominated by
+    /// noise are down-weighted, and new texels are merged with previously
+    /// observed ones (instead of replacing them) so that frames with
+    /// different exposures complement each other. Use `StampFrame::exposure`
+    /// to specify the exposure of each frame.
+    pub fn set_hdr_merge(&mut self, hdr_merge: bool) {
+        self.hdr_merge = hdr_merge;
+    }
+
+    pub fn hdr_merge(&self) -> bool {
+        self.hdr_merge
+    }
+
+    /// Returns the response curve, the HDR merging and the automatic gain
+    /// settings at once.
+    pub fn stamp_settings(&self) -> StampSettings {
+        StampSettings {
+            response: self.response,
+            hdr_merge: self.hdr_merge,
+            auto_gain: self.auto_gain,
+        }
+    }
+
+    pub fn set_stamp_settings(&mut self, settings: &StampSettings) {
+        self.response = settings.response;
+        self.hdr_merge = settings.hdr_merge;
+        self.auto_gain = settings.auto_gain;
+    }
+
+    /// Enables (`Some`) or disables (`None`) the extraction of dominant light
+    /// sources by `process`. It is disabled by default.
+    ///
+    /// If `LightExtractionParams::remove` is set, the extracted lights are
+    /// removed from the output, which then only contains the residual
+    /// ambient lighting.
+    pub fn set_light_extraction(&mut self, params: Option<LightExtractionParams>) {
+        self.light_params = params;
+        self.lights.clear();
+    }
+
+    /// Returns the lights extracted by the last call to `process`. Their
+    /// colors are relative to the reference exposure like the output of
+    /// `copy_hdr_output`.
+    pub fn lights(&self) -> &[DirectionalLight] {
+        &self.lights
+    }
+
+    /// Enables (`Some`) or disables (`None`) the priors for the unobserved
+    /// directions. They are disabled by default.
+    ///
+    /// When enabled, `process` estimates a floor color and a sky gradient
+    /// from the observed texels and blends them into the unobserved ones
+    /// with a small weight, so they only become dominant in large unobserved
+    /// regions. See the `prior` module for details.
+    pub fn set_prior(&mut self, params: Option<PriorParams>) {
+        self.prior_params = params;
+        self.prior = None;
+    }
+
+    /// Returns the priors estimated by the last call to `process`. Their
+    /// colors are linear values where `LINEAR_WHITE` corresponds to the pixel
+    /// value `255` at the reference exposure.
+    pub fn prior(&self) -> Option<&EnvironmentPrior> {
+        self.prior.as_ref()
+    }
+
+    /// Sets the fallback environment map, which is shown in the directions
+    /// that have not been observed. `images` are the six faces in the
+    /// `CUBE_FACES` order; see `FallbackEnvironment::new` for the format.
+    ///
+    /// `process` blends the fallback with the captured cube map per texel
+    /// according to the confidence curve (see `set_confidence_curve`). The
+    /// fallback supersedes the priors (see `set_prior`) since no texel is
+    /// left unfilled after blending it.
+    pub fn set_fallback(&mut self, images: &[Image<&[Vector4<f32>]>]) -> Result<()> {
+        self.fallback = Some(FallbackEnvironment::new(images, SIZE)?);
+        Ok(())
+    }
+
+    /// Removes the fallback environment map set by `set_fallback`.
+    pub fn clear_fallback(&mut self) {
+        self.fallback = None;
+    }
+
+    pub fn fallback(&self) -> Option<&FallbackEnvironment> {
+        self.fallback.as_ref()
+    }
+
+    /// Sets the curve that determines how much of the fallback environment
+    /// map is visible in each captured texel based on its accumulated weight.
+    /// By default, the fallback is hidden by a single well-exposed pixel.
+    ///
+    /// Returns `Err(Error::InvalidConfidenceCurve)` if the parameters of
+    /// `curve` are not finite and positive.
+    pub fn set_confidence_curve(&mut self, curve: ConfidenceCurve) -> Result<()> {
+        if !curve.is_valid() {
+            return Err(Error::InvalidConfidenceCurve);
+        }
+        self.confidence_curve = curve;
+        Ok(())
+    }
+
+    pub fn confidence_curve(&self) -> &ConfidenceCurve {
+        &self.confidence_curve
+    }
+
+    /// Sets the number of spherical Gaussians fitted to the processed cube
+    /// map by `process`. Zero (the default) disables the fitting.
+    pub fn set_num_sg_lobes(&mut self, num_lobes: usize) {
+        self.num_sg_lobes = num_lobes;
+        self.sg_lobes.clear();
+    }
+
+    pub fn num_sg_lobes(&self) -> usize {
+        self.num_sg_lobes
+    }
+
+    /// Returns the spherical Gaussians fitted by the last call to `process`.
+    /// Their amplitudes are relative to the reference exposure like the
+    /// output of `copy_hdr_output`.
+    pub fn spherical_gaussians(&self) -> &[SphericalGaussian] {
+        &self.sg_lobes
+    }
+
+    /// Returns the `StampOptions` used by `stamp` and `stamp_batch`.
+    fn stamp_options(&self) -> StampOptions {
+        StampOptions {
+            response: self.response,
+            weighting: if self.hdr_merge {
+                PixelWeighting::Hat
+            } else {
+                PixelWeighting::Uniform
+            },
+            ..StampOptions::default()
+        }
+    }
+
     pub fn image_size(&self) -> usize {
         SIZE
     }
 
+    /// Returns the fraction of the environment observed so far.
+    pub fn coverage(&self) -> Coverage {
+        let mut faces = [0.0; 6];
+        for (face, coverage) in self.raw_cube.iter().zip(faces.iter_mut()) {
+            let observed: f32 = face.data
+                .iter()
+                .zip(TEXEL_SOLID_ANGLES.iter())
+                .filter(|&(x, _)| x.w > 0.0)
+                .map(|(_, &solid_angle)| solid_angle)
+                .sum();
+            *coverage = observed / *FACE_SOLID_ANGLE;
+        }
+
+        // All faces have the same solid angle
+        Coverage {
+            total: faces.iter().sum::<f32>() / 6.0,
+            faces,
+        }
+    }
+
+    /// Suggests the view direction that would add the most unobserved solid
+    /// angle within a cone of `params.half_fov`. Returns `None` if every
+    /// allowed direction has already been observed.
+    pub fn suggest_view_direction(&self, params: &ViewSuggestionParams) -> Option<ViewSuggestion> {
+        // Compute the unobserved solid angle of each texel of a low mip level.
+        // Only the texels (cells) with an unobserved part affect the result
+        let size = SIZE >> VIEW_SUGGESTION_LEVEL;
+        let block = 1 << VIEW_SUGGESTION_LEVEL;
+        let mut cells = Vec::new();
+        for (face_i, face) in self.raw_cube.iter().enumerate() {
+            for y in 0..size {
+                for x in 0..size {
+                    let mut unobserved = 0.0;
+                    for by in y * block..(y + 1) * block {
+                        for bx in x * block..(x + 1) * block {
+                            if face.data[bx + by * SIZE].w <= 0.0 {
+                                unobserved += TEXEL_SOLID_ANGLES[bx + by * SIZE];
+                            }
+                        }
+                    }
+                    if unobserved > 0.0 {
+                        let direction = VIEW_SUGGESTION_DIRECTIONS[x + (y + face_i * size) * size];
+                        cells.push((direction, unobserved));
+                    }
+                }
+            }
+        }
+
+        let reachable = params.reachable_from.map(|(d, angle)| (d.normalize(), angle.0.cos()));
+        let is_allowed = |d: Vector3<f32>| {
+            (!params.upper_hemisphere_only || d.y >= 0.0) &&
+                reachable.map(|(r, cos_angle)| d.dot(r) >= cos_angle).unwrap_or(true)
+        };
+
+        let cos_half_fov = params.half_fov.0.cos();
+        let captured_within = |d: Vector3<f32>, cos_angle: f32| {
+            cells
+                .iter()
+                .filter(|&&(dir, _)| dir.dot(d) >= cos_angle)
+                .map(|&(_, unobserved)| unobserved)
+                .sum::<f32>()
+        };
+        let captured = |d: Vector3<f32>| captured_within(d, cos_half_fov);
+
+        // The candidates are the texels of `VIEW_SUGGESTION_LEVEL`. Group them
+        // by their parent texels at `VIEW_CANDIDATE_LEVEL`; a cone of
+        // `half_fov` around a child is contained by a cone around the parent
+        // widened by the distance to the farthest child, which bounds the
+        // solid angle captured from the children. Visit the parents in the
+        // descending order of the bound and stop when no remaining parent can
+        // beat the best child found so far
+        let coarse_size = size / 2;
+        let children = |i: usize| {
+            let face_i = i / (coarse_size * coarse_size);
+            let (x, y) = (i % coarse_size * 2, i / coarse_size % coarse_size * 2);
+            (0..4).map(move |k| {
+                VIEW_SUGGESTION_DIRECTIONS[x + k % 2 + (y + k / 2 + face_i * size) * size]
+            })
+        };
+        let mut parents: Vec<(usize, f32)> = VIEW_CANDIDATE_DIRECTIONS
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| children(i).any(|d| is_allowed(d)))
+            .map(|(i, &parent)| {
+                let radius = children(i)
+                    .map(|d| d.dot(parent).min(1.0).acos())
+                    .fold(0.0, f32::max);
+                let angle = (params.half_fov.0 + radius).min(PI);
+                (i, captured_within(parent, angle.cos()))
+            })
+            .filter(|&(_, bound)| bound > 0.0)
+            .collect();
+        parents.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+        let mut best: Option<(Vector3<f32>, f32)> = None;
+        for &(i, bound) in parents.iter() {
+            if bound <= best.map(|(_, x)| x).unwrap_or(0.0) {
+                break;
+            }
+            for candidate in children(i).filter(|&d| is_allowed(d)) {
+                let solid_angle = captured(candidate);
+                if solid_angle > best.map(|(_, x)| x).unwrap_or(0.0) {
+                    best = Some((candidate, solid_angle));
+                }
+            }
+        }
+        let candidate = match best {
+            Some((candidate, _)) => candidate,
+            None => return None,
+        };
+
+        // Center the cone on the unobserved region if possible
+        let centroid = cells
+            .iter()
+            .filter(|&&(dir, _)| dir.dot(candidate) >= cos_half_fov)
+            .fold(Vector3::zero(), |sum, &(dir, unobserved)| sum + dir * unobserved);
+        let centroid = centroid.normalize();
+        let direction = if is_allowed(centroid) && captured(centroid) >= captured(candidate) {
+            centroid
+        } else {
+            candidate
+        };
+
+        let cone_angle = cells
+            .iter()
+            .filter(|&&(dir, _)| dir.dot(direction) >= cos_half_fov)
+            .map(|&(dir, _)| dir.dot(direction).min(1.0).acos())
+            .fold(0.0, f32::max);
+
+        Some(ViewSuggestion {
+            direction,
+            cone_angle: Rad(cone_angle),
+            solid_angle: captured(direction),
+        })
+    }
+
+    /// Returns the number of `f32` values written by `copy_coverage_map`.
+    pub fn coverage_map_len(&self, mip_level: usize) -> Result<usize> {
+        if mip_level > LOG_SIZE {
+            return Err(Error::InvalidMipLevel(mip_level));
+        }
+        let size = SIZE >> mip_level;
+        Ok(size * size * 6 * 2)
+    }
+
+    /// Copies the per-texel coverage and confidence of the specified mip
+    /// level into `out`, which can be used to guide the user to the
+    /// directions that still need scanning.
+    ///
+    /// The texels are stored in the same order as `copy_output`, each
+    /// consisting of two `f32` values in the range `[0, 1]`:
+    ///
+    ///  - The coverage, the fraction of the corresponding base level texels
+    ///    that have been observed.
+    ///  - The confidence, which is derived from the accumulated weight of the
+    ///    observations. Texels only observed through clipped or very dark
+    ///    pixels (when the HDR merging is enabled) have a low confidence.
+    ///
+    pub fn copy_coverage_map(&self, mip_level: usize, out: &mut [f32]) -> Result<()> {
+        let len = self.coverage_map_len(mip_level)?;
+        if out.len() < len {
+            return Err(Error::DimensionMismatch);
+        }
+
+        let size = SIZE >> mip_level;
+        let block = 1 << mip_level;
+        let scale = 1.0 / (block * block) as f32;
+        let faces = self.raw_cube.iter().zip(self.raw_weights.iter());
+        for ((face, weights), out_face) in faces.zip(out.chunks_mut(size * size * 2)) {
+            for y in 0..size {
+                for x in 0..size {
+                    let (mut coverage, mut confidence) = (0.0, 0.0);
+                    for by in y * block..(y + 1) * block {
+                        for bx in x * block..(x + 1) * block {
+                            let i = bx + by * SIZE;
+                            if face.data[i].w > 0.0 {
+                                coverage += 1.0;
+                                confidence += (weights[i] / CONFIDENCE_FULL_WEIGHT).min(1.0);
+                            }
+                        }
+                    }
+                    let dst = &mut out_face[(x + y * size) * 2..];
+                    dst[0] = coverage * scale;
+                    dst[1] = confidence * scale;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts recording the stamped frames into a capture file (see the
+    /// `capture` module), discarding the current recording if any. The
+    /// images are downscaled by the factor `downscale`, which must be
+    /// non-zero.
+    ///
+    /// While recording, stamping an image larger than the camera image
+    /// specified by `intrinsics` fails with `Error::DimensionMismatch`.
+    /// Frames stamped by `try_stamp_with_options` are not recorded.
+    pub fn start_recording(&mut self, intrinsics: &Intrinsics, downscale: usize) -> Result<()> {
+        if downscale == 0 {
+            return Err(Error::DimensionMismatch);
+        }
+        // Writing to a `Vec` never fails
+        let writer = CaptureWriter::new(Vec::new(), intrinsics).unwrap();
+        self.recording = Some(Recording { writer, downscale });
+        Ok(())
+    }
+
+    /// Returns the capture file recorded so far, or `None` if not recording.
+    pub fn recording(&self) -> Option<&[u8]> {
+        self.recording.as_ref().map(|r| &r.writer.get_ref()[..])
+    }
+
+    /// Stops recording and returns the recorded capture file.
+    pub fn stop_recording(&mut self) -> Option<Vec<u8>> {
+        self.recording.take().map(|r| r.writer.into_inner())
+    }
+
+    /// Checks if the frames can be recorded by `record`.
+    fn check_recordable(&self, frames: &[StampFrame]) -> Result<()> {
+        if let Some(ref recording) = self.recording {
+            let intrinsics = recording.writer.intrinsics();
+            for frame in frames.iter() {
+                if frame.image.width > intrinsics.width as usize ||
+                    frame.image.height > intrinsics.height as usize
+                {
+                    return Err(Error::DimensionMismatch);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records frames that were successfully stamped by a single call.
+    fn record(&mut self, frames: &[StampFrame]) {
+        let settings = self.stamp_settings();
+        if let Some(ref mut recording) = self.recording {
+            // The images were validated by `check_recordable` and by stamping
+            // them, and writing to a `Vec` never fails
+            recording
+                .writer
+                .write_batch(clock::now(), frames, &settings, recording.downscale)
+                .unwrap();
+        }
+    }
+
+    /// Returns the number of mip levels of the output image.
+    pub fn num_levels(&self) -> usize {
+        self.converted_cube_levels.len()
+    }
+
     pub fn stamp(&mut self, image: &Image<&[Vector4<u8>]>, camera_matrix: Matrix4<f32>) {
-        for (i, env_image) in self.raw_cube.iter_mut().enumerate() {
-            let face_inv_view_proj_mat = CUBE_FACES[i].info().inv_view_proj_mat;
-            stamp::stamp_camera_image(
-                &mut env_image.as_mut(),
+        self.try_stamp(image, camera_matrix).unwrap()
+    }
+
+    /// Fallible version of `stamp`.
+    pub fn try_stamp(
+        &mut self,
+        image: &Image<&[Vector4<u8>]>,
+        camera_matrix: Matrix4<f32>,
+    ) -> Result<()> {
+        let frame = StampFrame::new(*image, camera_matrix);
+        self.check_recordable(&[frame])?;
+        let options = self.stamp_options();
+        self.try_stamp_with_options(image, camera_matrix, &options)?;
+        self.record(&[frame]);
+        Ok(())
+    }
+
+    pub fn stamp_camera(&mut self, image: &Image<&[Vector4<u8>]>, camera: &Camera) {
+        self.try_stamp_camera(image, camera).unwrap()
+    }
+
+    /// Fallible version of `stamp_camera`. The camera position is ignored.
+    pub fn try_stamp_camera(&mut self, image: &Image<&[Vector4<u8>]>, camera: &Camera) -> Result<()> {
+        camera.validate()?;
+        self.try_stamp(image, camera.environment_view_proj_matrix())
+    }
+
+    /// `try_stamp` with a camera matrix defined in a different convention or
+    /// an image stored bottom-up. The output is always in the canonical
+    /// (OpenGL) convention; use `arcane_gfx::convention::remap_cube_map` to
+    /// convert it.
+    pub fn try_stamp_with_options(
+        &mut self,
+        image: &Image<&[Vector4<u8>]>,
+        camera_matrix: Matrix4<f32>,
+        options: &StampOptions,
+    ) -> Result<()> {
+        let start = clock::now();
+        let num_texels = self.stamp_faces(|face_image, face_inv_view_proj_mat| {
+            stamp::try_stamp_camera_image_with_options(
+                face_image,
                 face_inv_view_proj_mat,
                 image,
                 camera_matrix,
-            );
+                options,
+            )
+        })?;
+
+        self.profile.num_stamps += 1;
+        self.profile.num_stamped_texels += num_texels as u64;
+        self.profile.stamp_time += clock::now() - start;
+        Ok(())
+    }
+
+    pub fn stamp_frame(&mut self, frame: &StampFrame) {
+        self.try_stamp_frame(frame).unwrap()
+    }
+
+    /// Fallible version of `stamp_frame`.
+    ///
+    /// Unlike `stamp`, this takes the weight and the exposure of the frame
+    /// into account.
+    pub fn try_stamp_frame(&mut self, frame: &StampFrame) -> Result<()> {
+        self.try_stamp_batch(&[*frame])
+    }
+
+    pub fn stamp_batch(&mut self, frames: &[StampFrame]) {
+        self.try_stamp_batch(frames).unwrap()
+    }
+
+    /// Fallible version of `stamp_batch`.
+    ///
+    /// Stamps multiple frames in a single pass over the cube faces. Where
+    /// frames overlap, the weighted average of them is stored (whereas
+    /// successive calls to `stamp` would keep only the last one). The
+    /// automatic gain estimation treats the batch as a single frame.
+    pub fn try_stamp_batch(&mut self, frames: &[StampFrame]) -> Result<()> {
+        for frame in frames.iter() {
+            if !(frame.weight >= 0.0 && frame.weight.is_finite()) ||
+                !(frame.exposure.abs() <= MAX_EXPOSURE)
+            {
+                return Err(Error::InvalidFrameParams);
+            }
         }
+        self.check_recordable(frames)?;
+
+        let start = clock::now();
+        let base_options = self.stamp_options();
+        let num_texels = self.stamp_faces(|face_image, face_inv_view_proj_mat| {
+            let mut num_texels = 0;
+            for frame in frames.iter() {
+                let options = StampOptions {
+                    scale: (-frame.exposure).exp2(),
+                    weight: frame.weight,
+                    blend: BlendMode::Add,
+                    ..base_options
+                };
+                num_texels += stamp::try_stamp_camera_image_with_options(
+                    face_image,
+                    face_inv_view_proj_mat,
+                    &frame.image,
+                    frame.camera_matrix,
+                    &options,
+                )?;
+            }
+            Ok(num_texels)
+        })?;
+
+        self.profile.num_stamps += frames.len() as u64;
+        self.profile.num_stamped_texels += num_texels as u64;
+        self.profile.stamp_time += clock::now() - start;
+        self.record(frames);
+        Ok(())
+    }
+
+    /// Calls `f` for each cube face to stamp new texels onto a cleared
+    /// temporary image, and then applies the automatic gain to them and
+    /// copies (or merges) them to `raw_cube`. Nothing is copied if `f` fails.
+    fn stamp_faces<F>(&mut self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&mut Image<&mut [Vector4<f32>]>, Matrix4<f32>) -> Result<usize>,
+    {
+        let mut num_texels = 0;
+        for (i, new_face) in self.temp1.iter_mut().enumerate() {
+            for x in new_face.iter_mut() {
+                *x = Vector4::zero();
+            }
+            num_texels += f(
+                &mut Image {
+                    data: &mut new_face[..],
+                    width: SIZE,
+                    height: SIZE,
+                },
+                CUBE_FACES[i].info().inv_view_proj_mat,
+            )?;
+        }
+
+        self.last_gain = estimate_gain(&self.temp1, &self.raw_cube, self.auto_gain);
+        let gain = self.last_gain.gain;
+        let hdr_merge = self.hdr_merge;
+
+        let faces = self.temp1.iter().zip(self.raw_cube.iter_mut()).zip(
+            self.raw_weights.iter_mut(),
+        );
+        for ((new_face, env_image), weights) in faces {
+            for ((src, dst), weight) in new_face.iter().zip(env_image.data.iter_mut()).zip(
+                weights.iter_mut(),
+            )
+            {
+                if src.w <= 0.0 {
+                    continue;
+                }
+
+                // `src` is a weighted sum of the new values
+                let new = src.truncate().mul_element_wise(gain);
+                let color = if hdr_merge && dst.w > 0.0 {
+                    let history = weight.min(HDR_MAX_HISTORY_WEIGHT);
+                    *weight = history + src.w;
+                    (dst.truncate() * history + new) / *weight
+                } else {
+                    *weight = src.w;
+                    new / src.w
+                };
+                *dst = color.extend(1.0);
+            }
+        }
+
+        Ok(num_texels)
     }
 
     pub fn process(&mut self) {
@@ -112,6 +959,8 @@ impl Context {
         let ref mut raw_cube = self.raw_cube;
         let ref mut temp1 = self.temp1;
         let ref mut temp2 = self.temp2;
+        let ref mut profile = self.profile;
+        profile.num_processes += 1;
 
         // Fill the base mip level
         for (src_face, dst_face) in raw_cube.iter().zip(env_cube_levels[0].iter_mut()) {
@@ -120,19 +969,78 @@ impl Context {
             }
         }
 
+        // Extract lights (and optionally remove them) before blurring
+        if let Some(ref params) = self.light_params {
+            self.lights = lights::extract_lights(
+                env_cube_levels[0]
+                    .iter_mut()
+                    .map(|face| &mut face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_mut_slice(),
+                SIZE,
+                params,
+            );
+            for light in self.lights.iter_mut() {
+                light.color /= LINEAR_WHITE;
+            }
+        }
+
+        // Blend in the priors. This is done after the light extraction so that
+        // lights are only extracted from the actual observations
+        if let Some(ref params) = self.prior_params {
+            let prior = EnvironmentPrior::estimate(
+                env_cube_levels[0]
+                    .iter()
+                    .map(|face| &face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_slice(),
+                SIZE,
+                params,
+            );
+            prior.apply(
+                env_cube_levels[0]
+                    .iter_mut()
+                    .map(|face| &mut face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_mut_slice(),
+                SIZE,
+            );
+            self.prior = Some(prior);
+        }
+
+        // Blend in the fallback environment map. This leaves no holes to fill
+        if let Some(ref fallback) = self.fallback {
+            fallback.blend(
+                env_cube_levels[0]
+                    .iter_mut()
+                    .map(|face| &mut face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_mut_slice(),
+                self.raw_weights
+                    .iter()
+                    .map(Vec::as_slice)
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_slice(),
+                &self.confidence_curve,
+            );
+        }
+
         // Generate mip levels
         let kernel = &BLUR_SETUP.kernel[..];
         for (i, &(kernel_scale, num_passes)) in BLUR_SETUP.levels.iter().enumerate() {
             let size = SIZE >> i;
             if i > 0 {
+                let start = clock::now();
                 let (prev, cur) = env_cube_levels[i - 1..i + 1].split_first_mut().unwrap();
                 for (src_face, dst_face) in prev.iter().zip(cur[0].iter_mut()) {
                     cubemaputils::downsample_2x(&mut dst_face.data, &src_face.data, size);
                 }
+                profile.downsample_time += clock::now() - start;
             }
 
             let ref mut cur = env_cube_levels[i];
-            for _ in 0..num_passes {
+            for pass in 0..num_passes {
+                let start = clock::now();
                 ltasgblur::ltasg_single(
                     temp1
                         .iter_mut()
@@ -184,41 +1092,318 @@ impl Context {
                     2,
                     ltasgblur::StandardCubeMapTrait,
                 );
+                profile.blur_times[i][pass] += clock::now() - start;
             }
         }
 
-        // Fill in the holes
-        for i in (1..BLUR_SETUP.levels.len()).rev() {
+        // Fill in the holes by push-pull. The levels below the blurred ones
+        // are pulled across the cube face boundaries down to 1x1, and then
+        // each level is filled from the next coarser one
+        let start = clock::now();
+        for i in BLUR_SETUP.levels.len()..LOG_SIZE + 1 {
+            let size = SIZE >> i;
+            let (prev, cur) = env_cube_levels[i - 1..i + 1].split_first_mut().unwrap();
+            cubemaputils::pull_2x(
+                cur[0]
+                    .iter_mut()
+                    .map(|face| &mut face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_mut_slice(),
+                prev.iter()
+                    .map(|face| &face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_slice(),
+                size,
+            );
+        }
+
+        cubemaputils::fill_hole_1x1(
+            env_cube_levels[LOG_SIZE]
+                .iter_mut()
+                .map(|face| &mut face.data[..])
+                .collect::<SmallVec<[_; 6]>>()
+                .as_mut_slice(),
+        );
+
+        for i in (1..LOG_SIZE + 1).rev() {
             let size = SIZE >> i;
             let (prev, cur) = env_cube_levels[i - 1..i + 1].split_first_mut().unwrap();
-            for (src_face, dst_face) in cur[0].iter_mut().zip(prev.iter_mut()) {
-                cubemaputils::upsample_fill_hole_2x(&mut dst_face.data, &src_face.data, size);
+            cubemaputils::push_fill_hole_2x(
+                prev.iter_mut()
+                    .map(|face| &mut face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_mut_slice(),
+                cur[0]
+                    .iter()
+                    .map(|face| &face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_slice(),
+                size,
+            );
+        }
+        profile.fill_hole_time += clock::now() - start;
+
+        // Fit spherical Gaussians to a low mip level
+        if self.num_sg_lobes > 0 {
+            let ref level = env_cube_levels[SG_FIT_LEVEL];
+            self.sg_lobes = sg::fit_spherical_gaussians(
+                level
+                    .iter()
+                    .map(|face| &face.data[..])
+                    .collect::<SmallVec<[_; 6]>>()
+                    .as_slice(),
+                SIZE >> SG_FIT_LEVEL,
+                self.num_sg_lobes,
+            );
+            for lobe in self.sg_lobes.iter_mut() {
+                lobe.amplitude /= LINEAR_WHITE;
             }
         }
 
-        // Convert to the target image format
+        // Convert to the target image format. Only the levels whose converted
+        // texels actually differ from the previous ones are marked as changed
+        let start = clock::now();
         let table = &cubemaputils::DELINEARIZE_TABLE[0..0x10000];
-        for (src_level, dst_level) in
-            env_cube_levels[0..BLUR_SETUP.levels.len()].iter().zip(
-                self.converted_cube_levels.iter_mut(),
-            )
+        for (i, (src_level, dst_level)) in
+            env_cube_levels
+                .iter()
+                .zip(self.converted_cube_levels.iter_mut())
+                .enumerate()
         {
+            let mut changed = false;
             for (src_face, dst_face) in src_level.iter().zip(dst_level.iter_mut()) {
                 for (src, dst) in src_face.data.iter().zip(dst_face.data.iter_mut()) {
                     let mut src = *src;
                     src *= 1.0 / (src.w + 1.0e-10);
-                    *dst = Vector4::new(
-                        table[(src.x as usize) & 0xffff],
-                        table[(src.y as usize) & 0xffff],
-                        table[(src.z as usize) & 0xffff],
+                    let new = Vector4::new(
+                        table[(src.x as usize).min(0xffff)],
+                        table[(src.y as usize).min(0xffff)],
+                        table[(src.z as usize).min(0xffff)],
                         255,
                     );
+                    changed |= new != *dst;
+                    *dst = new;
                 }
             }
+            if changed {
+                self.changed_levels |= 1 << i;
+            }
         }
+        profile.conversion_time += clock::now() - start;
+    }
+
+    /// Retrieves the timings and counters accumulated so far.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn reset_profile(&mut self) {
+        self.profile.reset();
     }
 
     pub fn output_image(&self, mip_level: usize, cube_face: usize) -> Image<&[Vector4<u8>]> {
-        self.converted_cube_levels[mip_level][cube_face].as_ref()
+        self.try_output_image(mip_level, cube_face).unwrap()
+    }
+
+    /// Fallible version of `output_image`.
+    pub fn try_output_image(
+        &self,
+        mip_level: usize,
+        cube_face: usize,
+    ) -> Result<Image<&[Vector4<u8>]>> {
+        let level = self.converted_cube_levels
+            .get(mip_level)
+            .ok_or(Error::InvalidMipLevel(mip_level))?;
+        let face = level.get(cube_face).ok_or(
+            Error::InvalidCubeFace(cube_face),
+        )?;
+        Ok(face.as_ref())
+    }
+
+    /// Returns the number of bytes required to store the packed output
+    /// generated by `copy_output`.
+    pub fn output_size(&self) -> usize {
+        self.converted_cube_levels
+            .iter()
+            .map(|level| level.iter().map(|face| face.data.len() * 4).sum::<usize>())
+            .sum()
+    }
+
+    /// Copies all mip levels of the processed cube map into `out`.
+    ///
+    /// The output is packed in the following layout:
+    ///
+    ///  - Mip levels are stored in an increasing order (i.e., starting from
+    ///    the base level whose size is `image_size()`), down to `1x1`.
+    ///  - Each mip level contains six faces in the order of `CUBE_FACES`
+    ///    (`+X`, `-X`, `+Y`, `-Y`, `+Z`, `-Z`).
+    ///  - Each face of the mip level `i` contains `(image_size() >> i)²`
+    ///    texels in the row-major order.
+    ///  - Each texel is four bytes in the RGBA order, encoded in sRGB.
+    ///
+    /// There is no padding between faces and mip levels. The total size is
+    /// given by `output_size()`.
+    ///
+    /// If `only_changed` is `true`, only the mip levels modified since the last
+    /// call to this method are written and the rest of `out` is left intact.
+    /// Returns a bit mask of the mip levels that were written.
+    pub fn copy_output(&mut self, out: &mut [u8], only_changed: bool) -> Result<u32> {
+        if out.len() < self.output_size() {
+            return Err(Error::DimensionMismatch);
+        }
+
+        let mask = if only_changed {
+            self.changed_levels
+        } else {
+            (1 << self.converted_cube_levels.len()) - 1
+        };
+
+        let mut offset = 0;
+        for (i, level) in self.converted_cube_levels.iter().enumerate() {
+            for face in level.iter() {
+                let len = face.data.len() * 4;
+                if mask & (1 << i) != 0 {
+                    for (src, dst) in face.data.iter().zip(out[offset..offset + len].chunks_mut(4)) {
+                        dst[0] = src.x;
+                        dst[1] = src.y;
+                        dst[2] = src.z;
+                        dst[3] = src.w;
+                    }
+                }
+                offset += len;
+            }
+        }
+
+        self.changed_levels &= !mask;
+        Ok(mask)
     }
+
+    /// Returns the number of `f32` values required to store the output of
+    /// `copy_spherical_gaussians`.
+    pub fn sg_len(&self) -> usize {
+        self.sg_lobes.len() * 7
+    }
+
+    /// Copies the spherical Gaussians fitted by the last call to `process`
+    /// into `out` as a flat array. Each lobe consists of seven values: the
+    /// normalized axis (XYZ), the sharpness, and the RGB amplitude.
+    pub fn copy_spherical_gaussians(&self, out: &mut [f32]) -> Result<()> {
+        if out.len() < self.sg_len() {
+            return Err(Error::DimensionMismatch);
+        }
+
+        for (lobe, dst) in self.sg_lobes.iter().zip(out.chunks_mut(7)) {
+            dst[0] = lobe.axis.x;
+            dst[1] = lobe.axis.y;
+            dst[2] = lobe.axis.z;
+            dst[3] = lobe.sharpness;
+            dst[4] = lobe.amplitude.x;
+            dst[5] = lobe.amplitude.y;
+            dst[6] = lobe.amplitude.z;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of `f32` values required to store the packed
+    /// output generated by `copy_hdr_output`.
+    pub fn hdr_output_len(&self) -> usize {
+        self.env_cube_levels
+            .iter()
+            .map(|level| level.iter().map(|face| face.data.len() * 4).sum::<usize>())
+            .sum()
+    }
+
+    /// Copies all mip levels of the processed cube map into `out` as linear
+    /// radiance values, which are not clipped.
+    ///
+    /// The layout is the same as `copy_output` except that each texel
+    /// consists of four `f32` values in the RGBA order. The radiance is
+    /// relative to the reference exposure; the pixel value `255` at the
+    /// reference exposure is mapped to `1.0`. The alpha channel is always
+    /// `1.0`.
+    pub fn copy_hdr_output(&self, out: &mut [f32]) -> Result<()> {
+        if out.len() < self.hdr_output_len() {
+            return Err(Error::DimensionMismatch);
+        }
+
+        let mut offset = 0;
+        for level in self.env_cube_levels.iter() {
+            for face in level.iter() {
+                let len = face.data.len() * 4;
+                for (src, dst) in face.data.iter().zip(out[offset..offset + len].chunks_mut(4)) {
+                    let scale = 1.0 / ((src.w + 1.0e-10) * LINEAR_WHITE);
+                    dst[0] = src.x * scale;
+                    dst[1] = src.y * scale;
+                    dst[2] = src.z * scale;
+                    dst[3] = 1.0;
+                }
+                offset += len;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Estimates the gain that should be applied to the newly stamped texels
+/// `new_faces` to match the previously observed texels `old_faces` (both in
+/// the RGB + weight format).
+fn estimate_gain(
+    new_faces: &[Vec<Vector4<f32>>],
+    old_faces: &[Image<Vec<Vector4<f32>>>],
+    auto_gain: AutoGain,
+) -> GainEstimate {
+    if !auto_gain.exposure && !auto_gain.white_balance {
+        return GainEstimate::default();
+    }
+
+    let in_range = |x: Vector3<f32>| {
+        x.x < GAIN_SAMPLE_MAX && x.y < GAIN_SAMPLE_MAX && x.z < GAIN_SAMPLE_MAX &&
+            x.x + x.y + x.z > GAIN_SAMPLE_MIN
+    };
+
+    let mut new_sum = Vector3::<f64>::zero();
+    let mut old_sum = Vector3::<f64>::zero();
+    let mut num_samples = 0;
+    for (new_face, old_face) in new_faces.iter().zip(old_faces.iter()) {
+        for (new, old) in new_face.iter().zip(old_face.data.iter()) {
+            if new.w <= 0.0 || old.w <= 0.0 {
+                continue;
+            }
+            let new = new.truncate() / new.w;
+            let old = old.truncate() / old.w;
+            if in_range(new) && in_range(old) {
+                new_sum += new.cast();
+                old_sum += old.cast();
+                num_samples += 1;
+            }
+        }
+    }
+
+    if num_samples < GAIN_MIN_SAMPLES {
+        return GainEstimate::default();
+    }
+
+    let clamp = |x: f64| (x as f32).max(1.0 / GAIN_MAX).min(GAIN_MAX);
+    let luminance_gain = clamp(old_sum.sum() / new_sum.sum());
+    let channel_gain = Vector3::new(
+        clamp(old_sum.x / new_sum.x),
+        clamp(old_sum.y / new_sum.y),
+        clamp(old_sum.z / new_sum.z),
+    );
+
+    let gain = match (auto_gain.exposure, auto_gain.white_balance) {
+        (true, false) => Vector3::new(luminance_gain, luminance_gain, luminance_gain),
+        (true, true) => channel_gain,
+        // Preserve the overall brightness
+        (false, true) => channel_gain * (1.0 / luminance_gain),
+        (false, false) => unreachable!(),
+    };
+
+    if !gain.x.is_finite() || !gain.y.is_finite() || !gain.z.is_finite() {
+        return GainEstimate::default();
+    }
+
+    GainEstimate { gain, num_samples }
 }
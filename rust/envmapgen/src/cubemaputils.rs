@@ -4,9 +4,12 @@
  * This file is a part of ARcane. Please read the license text that
  * comes with the source code for use conditions.
  */
-use cgmath::Vector4;
+use cgmath::{Vector2, Vector4};
+use cgmath::prelude::*;
 use hyperenvmap::ltasgblur;
 
+use arcane_gfx::cubemap::{CUBE_FACES, CubeFace, clip_to_texel};
+
 lazy_static! {
     pub static ref DELINEARIZE_TABLE: Vec<u8> = (0..65536).map(|i| {
         let norm = (i as f32) * (1.0 / 255.0 / 255.0);
@@ -29,26 +32,98 @@ pub fn downsample_2x(dst: &mut [Vector4<f32>], src: &[Vector4<f32>], size: usize
     }
 }
 
-pub fn upsample_fill_hole_2x(dst: &mut [Vector4<f32>], src: &[Vector4<f32>], size: usize) {
-    for y in 0..size {
-        let dst12 = &mut dst[(y * 2) * (size * 2)..][0..size * 4];
-        let (dst1, dst2) = dst12.split_at_mut(size * 2);
-        for (x, &s) in src[y * size..][0..size].iter().enumerate() {
-            {
-                let w = dst1[x * 2].w;
-                dst1[x * 2] += s * (1.0 - w);
-            }
-            {
-                let w = dst1[x * 2 + 1].w;
-                dst1[x * 2 + 1] += s * (1.0 - w);
-            }
-            {
-                let w = dst2[x * 2].w;
-                dst2[x * 2] += s * (1.0 - w);
+/// Returns the texel `(x, y)` of the face `face` of a cube map whose size is
+/// `size` texels. The coordinates may lie outside the face, in which case
+/// the texel is fetched from the adjacent face.
+fn fetch_texel(
+    faces: &[&[Vector4<f32>]],
+    face: usize,
+    x: isize,
+    y: isize,
+    size: usize,
+) -> Vector4<f32> {
+    let s = size as isize;
+    if x >= 0 && y >= 0 && x < s && y < s {
+        return faces[face][(x + y * s) as usize];
+    }
+
+    let scale = 2.0 / size as f32;
+    let d = CUBE_FACES[face].unproject(Vector2::new(
+        (x as f32 + 0.5) * scale - 1.0,
+        (y as f32 + 0.5) * scale - 1.0,
+    ));
+    let new_face = CubeFace::from_direction(d);
+    let p = new_face.project(d);
+    faces[new_face as usize][clip_to_texel(p.x, size) + clip_to_texel(p.y, size) * size]
+}
+
+/// Downsamples a cube map in the RGB + weight format (with the weight
+/// premultiplied) by the factor of two using a 4x4 tent filter.
+///
+/// Unlike `downsample_2x`, this crosses the cube face boundaries, so it can
+/// be used to propagate the observed texels to the adjacent faces.
+pub fn pull_2x(dst: &mut [&mut [Vector4<f32>]], src: &[&[Vector4<f32>]], size: usize) {
+    const WEIGHTS: [f32; 4] = [0.125, 0.375, 0.375, 0.125];
+    for (face, dst_face) in dst.iter_mut().enumerate() {
+        for y in 0..size {
+            for x in 0..size {
+                let mut sum = Vector4::zero();
+                for (dy, &wy) in WEIGHTS.iter().enumerate() {
+                    let sy = (y * 2 + dy) as isize - 1;
+                    for (dx, &wx) in WEIGHTS.iter().enumerate() {
+                        let sx = (x * 2 + dx) as isize - 1;
+                        sum += fetch_texel(src, face, sx, sy, size * 2) * (wx * wy);
+                    }
+                }
+                dst_face[x + y * size] = sum;
             }
-            {
-                let w = dst2[x * 2 + 1].w;
-                dst2[x * 2 + 1] += s * (1.0 - w);
+        }
+    }
+}
+
+/// Fills the holes of a 1x1 cube map in the RGB + weight format with the
+/// weighted average of all faces. Does nothing if no texels are observed.
+pub fn fill_hole_1x1(faces: &mut [&mut [Vector4<f32>]]) {
+    let sum = faces.iter().fold(Vector4::zero(), |sum, face| sum + face[0]);
+    if !(sum.w > 0.0) {
+        return;
+    }
+    let average = sum / sum.w;
+    for face in faces.iter_mut() {
+        let w = face[0].w;
+        face[0] += average * (1.0 - w);
+    }
+}
+
+/// Fills the holes of `dst` (whose size is `size * 2` texels) with `src`
+/// (whose size is `size` texels), bilinearly upsampled across the cube face
+/// boundaries. Both are in the RGB + weight format, and `src` is expected to
+/// have no holes.
+pub fn push_fill_hole_2x(dst: &mut [&mut [Vector4<f32>]], src: &[&[Vector4<f32>]], size: usize) {
+    // The first of the two nearest `src` texels and its weight
+    let nearest = |i: usize| if i % 2 == 0 {
+        ((i / 2) as isize - 1, 0.25)
+    } else {
+        ((i / 2) as isize, 0.75)
+    };
+
+    let dst_size = size * 2;
+    for (face, dst_face) in dst.iter_mut().enumerate() {
+        for y in 0..dst_size {
+            let (y0, wy) = nearest(y);
+            for x in 0..dst_size {
+                let ref mut d = dst_face[x + y * dst_size];
+                let w = d.w;
+                if w >= 1.0 {
+                    continue;
+                }
+
+                let (x0, wx) = nearest(x);
+                let s = fetch_texel(src, face, x0, y0, size) * (wx * wy) +
+                    fetch_texel(src, face, x0 + 1, y0, size) * ((1.0 - wx) * wy) +
+                    fetch_texel(src, face, x0, y0 + 1, size) * (wx * (1.0 - wy)) +
+                    fetch_texel(src, face, x0 + 1, y0 + 1, size) * ((1.0 - wx) * (1.0 - wy));
+                *d += s * (1.0 - w);
             }
         }
     }
@@ -79,6 +154,12 @@ pub struct MipPyramidGenSetup {
 }
 
 impl MipPyramidGenParams {
+    /// Returns the total σ of the blur applied up to the mip level `level`,
+    /// in units of the face width.
+    pub fn sigma(&self, level: usize) -> f32 {
+        (level as f32 + self.log2_min_sigma).exp2()
+    }
+
     pub fn setup(&self) -> MipPyramidGenSetup {
         let kernel = ltasgblur::gaussian_kernel(
             (self.kernel_resolution * self.kernel_width) as usize,
@@ -88,7 +169,7 @@ impl MipPyramidGenParams {
         let levels = (0..self.num_levels)
             .map(|i| {
                 let size = self.size >> i;
-                let sigma = (i as f32 + self.log2_min_sigma).exp2();
+                let sigma = self.sigma(i);
 
                 // The amount of blur applied on this stage
                 let res_sigma = (sigma * sigma - last_variance).sqrt();
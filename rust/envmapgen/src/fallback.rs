@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Fallback environment maps for the directions that have not been observed
+//! (yet).
+//!
+//! Before the user has scanned anything, the output would be entirely black,
+//! and afterwards the unobserved regions are only filled by extrapolating
+//! the observed ones. A fallback environment map (e.g., a studio HDR image
+//! bundled with the application) is blended with the captured one per texel
+//! according to how confidently each texel has been observed.
+use cgmath::Vector4;
+use cgmath::prelude::*;
+
+use arcane_gfx::{Image, Error, Result};
+use arcane_gfx::response::LINEAR_WHITE;
+
+/// Maps the accumulated weight of a captured texel to the fraction of the
+/// captured value in the blended result. The remainder is taken from the
+/// fallback environment map.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ConfidenceCurve {
+    /// The accumulated weight at which the fallback is no longer visible.
+    /// A single well-exposed pixel has the weight `1`.
+    pub full_weight: f32,
+
+    /// The exponent applied to the normalized weight. Values greater than
+    /// `1` keep more of the fallback in sparsely observed texels.
+    pub exponent: f32,
+}
+
+impl Default for ConfidenceCurve {
+    fn default() -> Self {
+        Self {
+            full_weight: 1.0,
+            exponent: 1.0,
+        }
+    }
+}
+
+impl ConfidenceCurve {
+    /// Returns `true` if both parameters are finite and positive.
+    pub fn is_valid(&self) -> bool {
+        self.full_weight > 0.0 && self.full_weight.is_finite() && self.exponent > 0.0 &&
+            self.exponent.is_finite()
+    }
+
+    /// Evaluates the curve. Returns a value in `[0, 1]` if the curve is
+    /// valid.
+    pub fn evaluate(&self, weight: f32) -> f32 {
+        if !(weight > 0.0) {
+            return 0.0;
+        }
+        (weight / self.full_weight).min(1.0).powf(self.exponent)
+    }
+}
+
+/// A fallback environment map resampled to the resolution of the captured
+/// one.
+#[derive(Debug, Clone)]
+pub struct FallbackEnvironment {
+    size: usize,
+
+    /// Linear values where `LINEAR_WHITE` corresponds to the pixel value
+    /// `255` at the reference exposure. The weight channel is always `1`.
+    faces: Vec<Vec<Vector4<f32>>>,
+}
+
+impl FallbackEnvironment {
+    /// Constructs a `FallbackEnvironment` of the resolution `size` from six
+    /// square cube faces in the `CUBE_FACES` order. The faces contain linear
+    /// RGB values relative to the reference exposure (`1.0` corresponds to
+    /// the pixel value `255`, like the output of `Context::copy_hdr_output`).
+    /// The alpha channel is ignored.
+    ///
+    /// The face size must be either a multiple or a divisor of `size`.
+    pub fn new(images: &[Image<&[Vector4<f32>]>], size: usize) -> Result<Self> {
+        if images.len() != 6 {
+            return Err(Error::DimensionMismatch);
+        }
+        let src_size = images[0].width;
+        for image in images.iter() {
+            if image.width != src_size || image.height != src_size ||
+                image.data.len() < src_size * src_size
+            {
+                return Err(Error::DimensionMismatch);
+            }
+        }
+        if src_size == 0 || (src_size % size != 0 && size % src_size != 0) {
+            return Err(Error::DimensionMismatch);
+        }
+
+        let faces = images
+            .iter()
+            .map(|image| {
+                let mut face = resample(image.data, src_size, size);
+                for x in face.iter_mut() {
+                    // Reject negative and non-finite values since they would
+                    // spread across the whole map during the blur
+                    *x = Vector4::new(
+                        sanitize(x.x) * LINEAR_WHITE,
+                        sanitize(x.y) * LINEAR_WHITE,
+                        sanitize(x.z) * LINEAR_WHITE,
+                        1.0,
+                    );
+                }
+                face
+            })
+            .collect();
+
+        Ok(Self { size, faces })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the resampled faces in the RGB + weight format.
+    pub fn faces(&self) -> &[Vec<Vector4<f32>>] {
+        &self.faces
+    }
+
+    /// Blends the fallback into a cube map in the RGB + weight format.
+    /// `weights` specifies the accumulated weight of each texel, which is
+    /// mapped to the fraction of the captured value by `curve`. All texels
+    /// have the weight `1` afterwards.
+    pub fn blend(
+        &self,
+        faces: &mut [&mut [Vector4<f32>]],
+        weights: &[&[f32]],
+        curve: &ConfidenceCurve,
+    ) {
+        let len = self.size * self.size;
+        for ((dst_face, weights), src_face) in
+            faces.iter_mut().zip(weights.iter()).zip(self.faces.iter())
+        {
+            for ((dst, &weight), src) in dst_face[0..len].iter_mut().zip(weights.iter()).zip(
+                src_face.iter(),
+            )
+            {
+                let t = if dst.w > 0.0 { curve.evaluate(weight) } else { 0.0 };
+                let captured = if t > 0.0 { *dst / dst.w } else { Vector4::zero() };
+                *dst = captured * t + *src * (1.0 - t);
+            }
+        }
+    }
+}
+
+/// Resamples a square image by box filtering (`src_size > size`) or pixel
+/// replication (`src_size < size`).
+fn resample(src: &[Vector4<f32>], src_size: usize, size: usize) -> Vec<Vector4<f32>> {
+    let mut dst = vec![Vector4::zero(); size * size];
+    if src_size >= size {
+        let ratio = src_size / size;
+        let scale = 1.0 / (ratio * ratio) as f32;
+        for y in 0..src_size {
+            for x in 0..src_size {
+                dst[x / ratio + y / ratio * size] += src[x + y * src_size] * scale;
+            }
+        }
+    } else {
+        let ratio = size / src_size;
+        for y in 0..size {
+            for x in 0..size {
+                dst[x + y * size] = src[x / ratio + y / ratio * src_size];
+            }
+        }
+    }
+    dst
+}
+
+fn sanitize(x: f32) -> f32 {
+    if x > 0.0 && x.is_finite() { x } else { 0.0 }
+}
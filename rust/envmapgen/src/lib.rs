@@ -4,6 +4,12 @@
  * This file is a part of ARcane. Please read the license text that
  * comes with the source code for use conditions.
  */
+//! Generates environment maps from camera images.
+//!
+//! This crate is built as a WebAssembly module for the web application, and
+//! as a native shared library and a Rust library for other tools. The
+//! `emg_*` functions make up the C API, which is declared in
+//! `include/envmapgen.h`.
 #![feature(allocator_api)]
 
 extern crate cgmath;
@@ -14,21 +20,108 @@ extern crate smallvec;
 extern crate hyperenvmap;
 
 mod blur;
-mod context;
-mod cubemaputils;
+pub mod capture;
+mod clock;
+pub mod context;
+pub mod cubemaputils;
+pub mod fallback;
+pub mod prior;
+pub mod profile;
+mod status;
 
 use std::heap::{Heap, Alloc, Layout};
-use std::{ptr, mem};
-use cgmath::{Vector4, Matrix4};
+use std::{ptr, mem, slice};
+use cgmath::{Vector4, Matrix4, Quaternion, Rad};
 
-use arcane_gfx::Image;
+use arcane_gfx::{Image, Error, Result};
+use arcane_gfx::camera::Camera;
+use arcane_gfx::response::ResponseCurve;
+use arcane_gfx::lights::LightExtractionParams;
 
-use context::Context;
+use capture::Intrinsics;
+use context::{Context, StampFrame, AutoGain, ViewSuggestionParams};
+use fallback::ConfidenceCurve;
+use prior::PriorParams;
+use status::handle_result;
 
+pub use status::Status;
+
+/// Constructs a slice from a pointer passed to a C API function, which may be
+/// null only if `len` is zero.
+unsafe fn slice_from_raw<'a, T>(p: *const T, len: usize) -> Result<&'a [T]> {
+    if len == 0 {
+        Ok(&[])
+    } else if p.is_null() {
+        Err(Error::InvalidArgument)
+    } else {
+        Ok(slice::from_raw_parts(p, len))
+    }
+}
+
+/// The mutable version of `slice_from_raw`.
+unsafe fn slice_from_raw_mut<'a, T>(p: *mut T, len: usize) -> Result<&'a mut [T]> {
+    if len == 0 {
+        Ok(&mut [])
+    } else if p.is_null() {
+        Err(Error::InvalidArgument)
+    } else {
+        Ok(slice::from_raw_parts_mut(p, len))
+    }
+}
+
+/// Dereferences a pointer passed to a C API function, which must not be
+/// null.
+unsafe fn ref_from_raw<'a, T>(p: *const T) -> Result<&'a T> {
+    p.as_ref().ok_or(Error::InvalidArgument)
+}
+
+/// The mutable version of `ref_from_raw`.
+unsafe fn mut_from_raw<'a, T>(p: *mut T) -> Result<&'a mut T> {
+    p.as_mut().ok_or(Error::InvalidArgument)
+}
+
+/// Returns the length (in bytes) of the UTF-8 message describing the last
+/// error, or zero if no error has occured so far.
 #[no_mangle]
-pub unsafe fn emg_malloc(size: usize) -> *mut u8 {
-    let layout = Layout::from_size_align(size + mem::size_of::<Layout>(), 4).unwrap();
-    let p = Heap.alloc(layout.clone()).unwrap();
+pub unsafe extern "C" fn emg_last_error_len() -> usize {
+    status::with_last_error(str::len)
+}
+
+/// Copies the message describing the last error into the specified buffer.
+/// Returns the number of bytes written, which is at most `len`. The message
+/// is not null-terminated. Nothing is written if `buffer` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_last_error_copy(buffer: *mut u8, len: usize) -> usize {
+    if len == 0 || buffer.is_null() {
+        return 0;
+    }
+    status::with_last_error(|message| {
+        let len = len.min(message.len());
+        ptr::copy_nonoverlapping(message.as_ptr(), buffer, len);
+        len
+    })
+}
+
+/// Returns a null pointer if the allocation has failed.
+#[no_mangle]
+pub unsafe extern "C" fn emg_malloc(size: usize) -> *mut u8 {
+    match try_malloc(size) {
+        Ok(p) => p,
+        Err(e) => {
+            status::set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe fn try_malloc(size: usize) -> Result<*mut u8> {
+    let total_size = size.checked_add(mem::size_of::<Layout>()).ok_or(
+        Error::AllocationFailed,
+    )?;
+    let layout = Layout::from_size_align(total_size, 4).ok_or(Error::AllocationFailed)?;
+    let p = Heap.alloc(layout.clone()).map_err(
+        |_| Error::AllocationFailed,
+    )?;
     ptr::write(p as *mut Layout, layout);
     for i in 0..size / 4 {
         ptr::write(
@@ -36,73 +129,643 @@ pub unsafe fn emg_malloc(size: usize) -> *mut u8 {
             0xdeadbeef,
         );
     }
-    p.offset(mem::size_of::<Layout>() as isize)
+    Ok(p.offset(mem::size_of::<Layout>() as isize))
 }
 
+/// Does nothing if `p` is a null pointer.
 #[no_mangle]
-pub unsafe fn emg_free(p: *mut u8) {
+pub unsafe extern "C" fn emg_free(p: *mut u8) {
+    if p.is_null() {
+        return;
+    }
     let p = p.offset(-(mem::size_of::<Layout>() as isize));
     let layout = ptr::read(p as *mut _);
     Heap.dealloc(p, layout);
 }
 
 #[no_mangle]
-pub unsafe fn emg_context_new() -> *mut Context {
+pub unsafe extern "C" fn emg_context_new() -> *mut Context {
     Box::into_raw(Box::new(Context::new()))
 }
 
 #[no_mangle]
-pub unsafe fn emg_context_destroy(this: *mut Context) {
-    Box::from_raw(this);
+pub unsafe extern "C" fn emg_context_destroy(this: *mut Context) {
+    drop(Box::from_raw(this));
 }
 
 #[no_mangle]
-pub unsafe fn emg_context_stamp(
+pub unsafe extern "C" fn emg_context_stamp(
     this: *mut Context,
-    image: *const Vector4<u8>,
+    image: *const u8,
     width: usize,
     height: usize,
-    camera_matrix: *const Matrix4<f32>,
-) {
-    use std::slice::from_raw_parts;
+    camera_matrix: *const f32,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(width.checked_mul(height).ok_or(Error::DimensionMismatch).and_then(|len| {
+        context.try_stamp(
+            &Image {
+                data: slice_from_raw(image as *const Vector4<u8>, len)?,
+                width,
+                height,
+            },
+            *ref_from_raw(camera_matrix as *const Matrix4<f32>)?,
+        )
+    }))
+}
 
+/// The camera parameters passed to `emg_context_stamp_camera`.
+#[repr(C)]
+pub struct CameraParams {
+    /// The camera orientation as a unit quaternion `(x, y, z, w)`, which
+    /// rotates a vector from the camera's local space (`-Z` forward, `+Y`
+    /// up) into the world space.
+    pub orientation: [f32; 4],
+
+    /// The vertical field of view in radians.
+    pub fov_y: f32,
+
+    /// The ratio of the image width to its height.
+    pub aspect: f32,
+}
+
+/// Stamps a camera image using a camera orientation instead of a matrix.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_stamp_camera(
+    this: *mut Context,
+    image: *const u8,
+    width: usize,
+    height: usize,
+    camera: *const CameraParams,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(width.checked_mul(height).ok_or(Error::DimensionMismatch).and_then(|len| {
+        let params = ref_from_raw(camera)?;
+        let q = params.orientation;
+        let camera = Camera::new(
+            Quaternion::new(q[3], q[0], q[1], q[2]),
+            Rad(params.fov_y),
+            params.aspect,
+        );
+        context.try_stamp_camera(
+            &Image {
+                data: slice_from_raw(image as *const Vector4<u8>, len)?,
+                width,
+                height,
+            },
+            &camera,
+        )
+    }))
+}
+
+/// Describes a camera image passed to `emg_context_stamp_batch`.
+#[repr(C)]
+pub struct StampFrameDesc {
+    /// The RGBA image data.
+    pub image: *const u8,
+    pub width: usize,
+    pub height: usize,
+
+    /// The view-projection matrix in the column-major order.
+    pub camera_matrix: [f32; 16],
+
+    /// The weight of the frame relative to other frames in the batch.
+    pub weight: f32,
+
+    /// The exposure value of the image in stops relative to the reference
+    /// exposure, in the range `[-32, 32]`.
+    pub exposure: f32,
+}
+
+/// Stamps `num_frames` camera images described by the array `frames`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_stamp_batch(
+    this: *mut Context,
+    frames: *const StampFrameDesc,
+    num_frames: usize,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(slice_from_raw(frames, num_frames).and_then(|descs| {
+        descs
+            .iter()
+            .map(|desc| {
+                let len = desc.width.checked_mul(desc.height).ok_or(Error::DimensionMismatch)?;
+                Ok(StampFrame {
+                    image: Image {
+                        data: slice_from_raw(desc.image as *const Vector4<u8>, len)?,
+                        width: desc.width,
+                        height: desc.height,
+                    },
+                    camera_matrix: *(&desc.camera_matrix as *const [f32; 16] as *const Matrix4<f32>),
+                    weight: desc.weight,
+                    exposure: desc.exposure,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .and_then(|frames| context.try_stamp_batch(&frames))
+    }))
+}
+
+/// Enables or disables the automatic exposure and white balance
+/// compensation.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_auto_gain(this: *mut Context, exposure: bool, white_balance: bool) {
     let context: &mut Context = &mut *this;
-    context.stamp(
-        &Image {
-            data: from_raw_parts(image, width * height),
-            width,
-            height,
-        },
-        *camera_matrix,
-    );
+    context.set_auto_gain(AutoGain {
+        exposure,
+        white_balance,
+    });
 }
 
+/// Stores the gain applied to the last stamped frame (R, G, and B) to
+/// `out_gain[0..3]` unless it is `NULL`. Returns the number of texels the
+/// estimation was based on.
 #[no_mangle]
-pub unsafe fn emg_context_get_image_size(this: *mut Context) -> usize {
+pub unsafe extern "C" fn emg_context_get_last_gain(this: *mut Context, out_gain: *mut f32) -> usize {
+    let context: &Context = &*this;
+    let estimate = context.last_gain();
+    if let Ok(out_gain) = mut_from_raw(out_gain as *mut [f32; 3]) {
+        *out_gain = estimate.gain.into();
+    }
+    estimate.num_samples
+}
+
+/// Sets the camera response curve to `x^gamma`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_response_gamma(this: *mut Context, gamma: f32) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(if gamma > 0.0 && gamma.is_finite() {
+        context.set_response_curve(ResponseCurve::gamma(gamma));
+        Ok(())
+    } else {
+        Err(Error::InvalidResponseCurve)
+    })
+}
+
+/// Sets the camera response curve to a calibrated table of 256 linear values
+/// in the range `[0, 1]`, indexed by the pixel value.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_response_table(this: *mut Context, table: *const f32) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(
+        slice_from_raw(table, 256)
+            .and_then(ResponseCurve::from_table)
+            .map(|curve| context.set_response_curve(curve)),
+    )
+}
+
+/// Enables or disables the HDR merging of stamped frames.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_hdr_merge(this: *mut Context, enabled: bool) {
+    let context: &mut Context = &mut *this;
+    context.set_hdr_merge(enabled);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_get_image_size(this: *mut Context) -> usize {
     let context: &Context = &*this;
     context.image_size()
 }
 
+/// Updates the output from the stamped images. The status is reserved for
+/// errors reported by future versions; currently it is always
+/// `EMG_STATUS_OK`.
 #[no_mangle]
-pub unsafe fn emg_context_process(this: *mut Context) {
+pub unsafe extern "C" fn emg_context_process(this: *mut Context) -> Status {
     let context: &mut Context = &mut *this;
-    context.process()
+    context.process();
+    handle_result(Ok(()))
 }
 
+/// Stores the pointer to the specified output image to `*out_data`.
 #[no_mangle]
-pub unsafe fn emg_context_get_output_image_data(
+pub unsafe extern "C" fn emg_context_get_output_image_data(
     this: *mut Context,
     mip_level: usize,
     cube_face: usize,
-) -> *const Vector4<u8> {
+    out_data: *mut *const u8,
+) -> Status {
     let context: &Context = &*this;
-    context.output_image(mip_level, cube_face).data.as_ptr()
+    handle_result(mut_from_raw(out_data).and_then(|out_data| {
+        let image = context.try_output_image(mip_level, cube_face)?;
+        *out_data = image.data.as_ptr() as *const u8;
+        Ok(())
+    }))
 }
 
+/// Returns the size (in bytes) of the buffer required by
+/// `emg_context_copy_output`.
 #[no_mangle]
-pub unsafe fn emg_spherical_blur(
-    size: usize,
-    images: *mut Vector4<u8>,
+pub unsafe extern "C" fn emg_context_output_size(this: *mut Context) -> usize {
+    let context: &Context = &*this;
+    context.output_size()
+}
+
+/// Copies all mip levels and faces of the output image into the specified
+/// buffer. See `Context::copy_output` for the layout.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_output(this: *mut Context, buffer: *mut u8, len: usize) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(
+        slice_from_raw_mut(buffer, len)
+            .and_then(|buffer| context.copy_output(buffer, false))
+            .map(|_| ()),
+    )
+}
+
+/// Copies the mip levels modified since the last copy into the specified
+/// buffer, which must have the same layout as the one used by
+/// `emg_context_copy_output`. A bit mask of the mip levels that were written
+/// is stored to `*out_levels`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_changed_output(
+    this: *mut Context,
+    buffer: *mut u8,
+    len: usize,
+    out_levels: *mut u32,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(mut_from_raw(out_levels).and_then(|out_levels| {
+        *out_levels = context.copy_output(slice_from_raw_mut(buffer, len)?, true)?;
+        Ok(())
+    }))
+}
+
+/// Stores the observed fraction of each cube face to `out_faces[0..6]` unless
+/// it is `NULL`. Returns the observed fraction of the whole environment.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_get_coverage(this: *mut Context, out_faces: *mut f32) -> f32 {
+    let context: &Context = &*this;
+    let coverage = context.coverage();
+    if let Ok(out_faces) = mut_from_raw(out_faces as *mut [f32; 6]) {
+        *out_faces = coverage.faces;
+    }
+    coverage.total
+}
+
+/// Returns the number of `f32` values written by
+/// `emg_context_copy_coverage_map` for the specified mip level, or zero if
+/// the mip level is out of range.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_coverage_map_len(this: *mut Context, mip_level: usize) -> usize {
+    let context: &Context = &*this;
+    context.coverage_map_len(mip_level).unwrap_or(0)
+}
+
+/// Copies the per-texel coverage and confidence of the specified mip level
+/// into the specified buffer. See `Context::copy_coverage_map` for the
+/// layout.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_coverage_map(
+    this: *mut Context,
+    mip_level: usize,
+    buffer: *mut f32,
+    len: usize,
+) -> Status {
+    let context: &Context = &*this;
+    handle_result(slice_from_raw_mut(buffer, len).and_then(
+        |buffer| context.copy_coverage_map(mip_level, buffer),
+    ))
+}
+
+/// A view direction returned by `emg_context_suggest_view_direction`.
+#[repr(C)]
+pub struct ViewSuggestionDesc {
+    /// The normalized direction.
+    pub direction: [f32; 3],
+
+    /// The angular radius (in radians) of the unobserved region that would
+    /// be captured.
+    pub cone_angle: f32,
+
+    /// The unobserved solid angle (in steradians) that would be captured.
+    pub solid_angle: f32,
+}
+
+/// Suggests the view direction that would add the most unobserved solid
+/// angle to the captured environment, assuming a camera whose field of view
+/// is approximated by a cone with the half angle `half_fov` (in radians).
+///
+/// If `upper_hemisphere_only` is `true`, only directions with a non-negative
+/// Y coordinate are considered. If `reference_direction` is not null, only
+/// directions within `max_angle` (in radians) from the direction
+/// `reference_direction[0..3]` are considered.
+///
+/// Returns `false` if every allowed direction has already been observed.
+/// Otherwise, the suggestion is stored to `*out_suggestion` unless it is
+/// `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_suggest_view_direction(
+    this: *mut Context,
+    half_fov: f32,
+    upper_hemisphere_only: bool,
+    reference_direction: *const f32,
+    max_angle: f32,
+    out_suggestion: *mut ViewSuggestionDesc,
+) -> bool {
+    let context: &Context = &*this;
+    let reachable_from = if reference_direction.is_null() {
+        None
+    } else {
+        let d = *(reference_direction as *const [f32; 3]);
+        Some((d.into(), Rad(max_angle)))
+    };
+    let params = ViewSuggestionParams {
+        half_fov: Rad(half_fov),
+        upper_hemisphere_only,
+        reachable_from,
+    };
+    match context.suggest_view_direction(&params) {
+        Some(suggestion) => {
+            if let Ok(out_suggestion) = mut_from_raw(out_suggestion) {
+                *out_suggestion = ViewSuggestionDesc {
+                    direction: suggestion.direction.into(),
+                    cone_angle: suggestion.cone_angle.0,
+                    solid_angle: suggestion.solid_angle,
+                };
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Enables the extraction of up to `max_lights` dominant light sources by
+/// `emg_context_process`, or disables it if `max_lights` is zero. If
+/// `remove` is `true`, the extracted lights are removed from the output.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_light_extraction(
+    this: *mut Context,
+    max_lights: usize,
+    remove: bool,
+) {
+    let context: &mut Context = &mut *this;
+    context.set_light_extraction(if max_lights > 0 {
+        Some(LightExtractionParams {
+            max_lights,
+            remove,
+            ..Default::default()
+        })
+    } else {
+        None
+    });
+}
+
+/// A light source returned by `emg_context_get_lights`.
+#[repr(C)]
+pub struct LightDesc {
+    /// The normalized direction toward the light source.
+    pub direction: [f32; 3],
+
+    /// The irradiance contributed by the light source, relative to the
+    /// reference exposure.
+    pub color: [f32; 3],
+
+    /// The angular spread of the light source in radians.
+    pub spread: f32,
+}
+
+/// Copies up to `max_lights` lights extracted by the last call to
+/// `emg_context_process` into `out_lights`. Returns the total number of
+/// extracted lights. `out_lights` may be `NULL` if `max_lights` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_get_lights(
+    this: *mut Context,
+    out_lights: *mut LightDesc,
+    max_lights: usize,
+) -> usize {
+    let context: &Context = &*this;
+    let lights = context.lights();
+    let out_lights = slice_from_raw_mut(out_lights, max_lights).unwrap_or(&mut []);
+    for (light, out_light) in lights.iter().zip(out_lights.iter_mut()) {
+        *out_light = LightDesc {
+            direction: light.direction.into(),
+            color: light.color.into(),
+            spread: light.spread.0,
+        };
+    }
+    lights.len()
+}
+
+/// Enables or disables the priors for the unobserved directions: a floor
+/// color projected onto a ground plane `ground_height` below the origin
+/// (`ground`), and a sky gradient (`sky`). Both are disabled by default.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_prior(
+    this: *mut Context,
+    ground: bool,
+    ground_height: f32,
+    sky: bool,
 ) {
-    blur::apply_spherical_blur(size, images)
+    let context: &mut Context = &mut *this;
+    context.set_prior(if ground || sky {
+        Some(PriorParams {
+            ground,
+            ground_height,
+            sky,
+            ..Default::default()
+        })
+    } else {
+        None
+    });
+}
+
+/// Sets the fallback environment map shown in the unobserved directions.
+/// `data` points to six `size`×`size` cube faces in the order `+X`, `-X`,
+/// `+Y`, `-Y`, `+Z`, and `-Z`, each texel consisting of four `f32` values in
+/// the RGBA order, in the same scale as the output of
+/// `emg_context_copy_hdr_output`. The alpha channel is ignored. `size` must
+/// be a multiple or a divisor of the image size. Pass `NULL` to remove the
+/// fallback.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_fallback(
+    this: *mut Context,
+    data: *const f32,
+    size: usize,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    if data.is_null() {
+        context.clear_fallback();
+        return Status::Ok;
+    }
+
+    // The buffer consists of `size * size * 6 * 4` `f32` values
+    let len = size.checked_mul(size).and_then(|len| {
+        len.checked_mul(6 * mem::size_of::<Vector4<f32>>()).map(|_| len)
+    });
+    handle_result(len.ok_or(Error::DimensionMismatch).and_then(|len| {
+        let data = slice_from_raw(data as *const Vector4<f32>, len * 6)?;
+        let images: Vec<_> = data.chunks(len.max(1))
+            .map(|face| {
+                Image {
+                    data: face,
+                    width: size,
+                    height: size,
+                }
+            })
+            .collect();
+        context.set_fallback(&images)
+    }))
+}
+
+/// Sets the curve that maps the accumulated weight of each captured texel to
+/// the fraction of the captured value blended with the fallback environment
+/// map: `min(weight / full_weight, 1)^exponent`. Both parameters must be
+/// finite and positive.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_confidence_curve(
+    this: *mut Context,
+    full_weight: f32,
+    exponent: f32,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(context.set_confidence_curve(ConfidenceCurve {
+        full_weight,
+        exponent,
+    }))
+}
+
+/// Sets the number of spherical Gaussians fitted to the environment by
+/// `emg_context_process`. Zero (the default) disables the fitting.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_set_num_sg_lobes(this: *mut Context, num_lobes: usize) {
+    let context: &mut Context = &mut *this;
+    context.set_num_sg_lobes(num_lobes);
+}
+
+/// Returns the number of `f32` values written by
+/// `emg_context_copy_spherical_gaussians`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_sg_len(this: *mut Context) -> usize {
+    let context: &Context = &*this;
+    context.sg_len()
+}
+
+/// Copies the spherical Gaussians fitted by the last call to
+/// `emg_context_process` into the specified buffer. See
+/// `Context::copy_spherical_gaussians` for the layout.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_spherical_gaussians(
+    this: *mut Context,
+    buffer: *mut f32,
+    len: usize,
+) -> Status {
+    let context: &Context = &*this;
+    handle_result(slice_from_raw_mut(buffer, len).and_then(
+        |buffer| context.copy_spherical_gaussians(buffer),
+    ))
+}
+
+/// Returns the number of `f32` values written by
+/// `emg_context_copy_hdr_output`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_hdr_output_len(this: *mut Context) -> usize {
+    let context: &Context = &*this;
+    context.hdr_output_len()
+}
+
+/// Copies all mip levels and faces of the output image into the specified
+/// buffer as linear radiance values. See `Context::copy_hdr_output` for the
+/// layout.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_hdr_output(
+    this: *mut Context,
+    buffer: *mut f32,
+    len: usize,
+) -> Status {
+    let context: &Context = &*this;
+    handle_result(slice_from_raw_mut(buffer, len).and_then(
+        |buffer| context.copy_hdr_output(buffer),
+    ))
+}
+
+/// Returns the number of `f64` values written by `emg_context_copy_profile`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_profile_len(this: *mut Context) -> usize {
+    let context: &Context = &*this;
+    context.profile().flat_len()
+}
+
+/// Copies the profiling result into the specified buffer. See
+/// `Profile::write_flat` for the layout. If `reset` is `true`, the counters
+/// are reset afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_profile(
+    this: *mut Context,
+    buffer: *mut f64,
+    len: usize,
+    reset: bool,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    if len < context.profile().flat_len() {
+        return handle_result(Err(Error::DimensionMismatch));
+    }
+    match slice_from_raw_mut(buffer, len) {
+        Ok(buffer) => context.profile().write_flat(buffer),
+        Err(e) => return handle_result(Err(e)),
+    }
+    if reset {
+        context.reset_profile();
+    }
+    Status::Ok
+}
+
+/// Starts recording the frames stamped by `emg_context_stamp`,
+/// `emg_context_stamp_camera` and `emg_context_stamp_batch` into a capture
+/// file, discarding the current recording if any. See
+/// `Context::start_recording`.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_start_recording(
+    this: *mut Context,
+    intrinsics: *const Intrinsics,
+    downscale: usize,
+) -> Status {
+    let context: &mut Context = &mut *this;
+    handle_result(ref_from_raw(intrinsics).and_then(
+        |intrinsics| context.start_recording(intrinsics, downscale),
+    ))
+}
+
+/// Returns the size (in bytes) of the capture file recorded so far, or zero
+/// if not recording.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_recording_len(this: *mut Context) -> usize {
+    let context: &Context = &*this;
+    context.recording().map(|x| x.len()).unwrap_or(0)
+}
+
+/// Copies the capture file recorded so far into the specified buffer, which
+/// must be at least `emg_context_recording_len` bytes long.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_copy_recording(
+    this: *mut Context,
+    buffer: *mut u8,
+    len: usize,
+) -> Status {
+    let context: &Context = &*this;
+    let recording = context.recording().unwrap_or(&[]);
+    if len < recording.len() {
+        return handle_result(Err(Error::DimensionMismatch));
+    }
+    handle_result(slice_from_raw_mut(buffer, len).map(|buffer| {
+        buffer[..recording.len()].copy_from_slice(recording);
+    }))
+}
+
+/// Stops recording and discards the recorded capture file.
+#[no_mangle]
+pub unsafe extern "C" fn emg_context_stop_recording(this: *mut Context) {
+    let context: &mut Context = &mut *this;
+    context.stop_recording();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn emg_spherical_blur(
+    size: usize,
+    images: *mut u8,
+) -> Status {
+    if images.is_null() {
+        return handle_result(Err(Error::InvalidArgument));
+    }
+    handle_result(blur::apply_spherical_blur(size, images as *mut Vector4<u8>))
 }
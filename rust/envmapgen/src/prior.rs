@@ -0,0 +1,200 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Priors for the directions that have never been observed.
+//!
+//! The lower hemisphere and the area behind the user are rarely scanned, and
+//! the hole filling alone turns them into a washed-out average of the whole
+//! environment. The priors estimated here provide plausible values for them:
+//!
+//!  - The ground prior assumes a floor at a fixed height below the origin
+//!    (the marker), whose color is estimated from the observed texels that
+//!    look down at the floor near the origin.
+//!  - The sky prior is a gradient over the elevation, estimated from the
+//!    observed texels in the upper hemisphere.
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+
+use arcane_gfx::cubemap::{CUBE_FACES, texel_solid_angle};
+
+/// The number of elevation bands of the sky gradient.
+pub const NUM_SKY_BANDS: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PriorParams {
+    /// Enables the ground prior for the directions below the horizon.
+    pub ground: bool,
+
+    /// The distance from the origin down to the ground plane. Must be
+    /// positive for the ground prior to be used.
+    pub ground_height: f32,
+
+    /// The floor color is estimated from the observed texels whose
+    /// intersection with the ground plane is within this horizontal distance
+    /// from the point right below the origin.
+    pub ground_radius: f32,
+
+    /// Enables the sky (or ceiling) gradient prior for the directions above
+    /// the horizon.
+    pub sky: bool,
+
+    /// The weight of the prior relative to an observed texel. Small values
+    /// make the prior only noticeable in large unobserved regions.
+    pub weight: f32,
+}
+
+impl Default for PriorParams {
+    fn default() -> Self {
+        Self {
+            ground: true,
+            ground_height: 1.5,
+            ground_radius: 3.0,
+            sky: true,
+            weight: 0.25,
+        }
+    }
+}
+
+/// Priors estimated from a cube map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvironmentPrior {
+    pub params: PriorParams,
+
+    /// The estimated floor color, or `None` if the floor near the origin has
+    /// not been observed (or the ground prior is disabled).
+    pub floor: Option<Vector3<f32>>,
+
+    /// The average color of each elevation band of the upper hemisphere,
+    /// starting from the horizon. `None` for unobserved bands.
+    pub sky_bands: [Option<Vector3<f32>>; NUM_SKY_BANDS],
+}
+
+impl EnvironmentPrior {
+    /// Estimates the priors from a cube map in the RGB + weight format.
+    /// Texels with a zero weight are ignored.
+    pub fn estimate(faces: &[&[Vector4<f32>]], size: usize, params: &PriorParams) -> Self {
+        let use_ground = params.ground && params.ground_height > 0.0;
+
+        // `-d.y` must be at least this for the intersection with the ground
+        // plane to be within `ground_radius`
+        let h = params.ground_height;
+        let r = params.ground_radius.max(0.0);
+        let min_ground_y = h / (h * h + r * r).sqrt();
+
+        let mut floor_sum = Vector4::zero();
+        let mut sky_sums = [Vector4::zero(); NUM_SKY_BANDS];
+        for (face_i, face) in faces.iter().enumerate() {
+            for y in 0..size {
+                for x in 0..size {
+                    let value = face[x + y * size];
+                    if !(value.w > 0.0) {
+                        continue;
+                    }
+                    let color = value.truncate() / value.w;
+                    let d = CUBE_FACES[face_i].texel_direction(x, y, size).normalize();
+                    let solid_angle = texel_solid_angle(x, y, size);
+                    let sample = (color * solid_angle).extend(solid_angle);
+                    if d.y >= 0.0 {
+                        if params.sky {
+                            sky_sums[sky_band(d.y)] += sample;
+                        }
+                    } else if use_ground && -d.y >= min_ground_y {
+                        floor_sum += sample;
+                    }
+                }
+            }
+        }
+
+        let average = |sum: Vector4<f32>| if sum.w > 0.0 {
+            Some(sum.truncate() / sum.w)
+        } else {
+            None
+        };
+
+        let mut sky_bands = [None; NUM_SKY_BANDS];
+        for (band, sum) in sky_bands.iter_mut().zip(sky_sums.iter()) {
+            *band = average(*sum);
+        }
+
+        Self {
+            params: *params,
+            floor: average(floor_sum),
+            sky_bands,
+        }
+    }
+
+    /// Evaluates the sky gradient at the elevation `y` (the Y coordinate of
+    /// a normalized direction) by interpolating between the observed bands.
+    pub fn sky(&self, y: f32) -> Option<Vector3<f32>> {
+        let t = y.max(0.0).min(1.0) * NUM_SKY_BANDS as f32 - 0.5;
+        let lower = (0..NUM_SKY_BANDS)
+            .rev()
+            .find(|&i| i as f32 <= t && self.sky_bands[i].is_some());
+        let upper = (0..NUM_SKY_BANDS).find(|&i| i as f32 >= t && self.sky_bands[i].is_some());
+        match (lower, upper) {
+            (Some(i), Some(k)) if i != k => {
+                let f = (t - i as f32) / (k - i) as f32;
+                Some(self.sky_bands[i].unwrap() * (1.0 - f) + self.sky_bands[k].unwrap() * f)
+            }
+            (Some(i), _) => self.sky_bands[i],
+            (None, Some(k)) => self.sky_bands[k],
+            (None, None) => None,
+        }
+    }
+
+    /// Evaluates the priors in the normalized direction `d`. Returns `None`
+    /// if no prior is available for `d`.
+    pub fn evaluate(&self, d: Vector3<f32>) -> Option<Vector3<f32>> {
+        if d.y >= 0.0 {
+            return self.sky(d.y);
+        }
+
+        let floor = match self.floor {
+            Some(floor) => floor,
+            None => return None,
+        };
+
+        // Toward the horizon, the floor is seen at a grazing angle from
+        // farther away, so blend it with the horizon color
+        let horizontal = (1.0 - d.y * d.y).max(0.0).sqrt();
+        let distance = self.params.ground_height * horizontal / -d.y;
+        let t = if distance > self.params.ground_radius {
+            self.params.ground_radius / distance
+        } else {
+            1.0
+        };
+        let horizon = self.sky(0.0).unwrap_or(floor);
+        Some(floor * t + horizon * (1.0 - t))
+    }
+
+    /// Blends the priors into the unobserved (or partially observed) texels
+    /// of a cube map in the RGB + weight format, weighted by
+    /// `params.weight`.
+    pub fn apply(&self, faces: &mut [&mut [Vector4<f32>]], size: usize) {
+        let weight = self.params.weight;
+        if !(weight > 0.0) {
+            return;
+        }
+        for (face_i, face) in faces.iter_mut().enumerate() {
+            for y in 0..size {
+                for x in 0..size {
+                    let ref mut texel = face[x + y * size];
+                    if texel.w >= 1.0 {
+                        continue;
+                    }
+                    let d = CUBE_FACES[face_i].texel_direction(x, y, size).normalize();
+                    if let Some(color) = self.evaluate(d) {
+                        *texel += (color * weight).extend(weight) * (1.0 - texel.w);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn sky_band(y: f32) -> usize {
+    ((y * NUM_SKY_BANDS as f32) as usize).min(NUM_SKY_BANDS - 1)
+}
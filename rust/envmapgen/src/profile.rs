@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Per-stage timings and counters collected by `Context`.
+use std::fmt;
+
+/// Timings and counters accumulated since the creation of a `Context` or the
+/// last call to `Context::reset_profile`. All times are in seconds.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// The number of frames stamped by `Context::stamp` and
+    /// `Context::stamp_batch`.
+    pub num_stamps: u64,
+
+    /// The number of cube map texels written by `Context::stamp`.
+    pub num_stamped_texels: u64,
+
+    pub stamp_time: f64,
+
+    /// The number of calls to `Context::process`.
+    pub num_processes: u64,
+
+    pub downsample_time: f64,
+
+    /// The time spent on each blur pass, indexed by the mip level and then
+    /// by the pass.
+    pub blur_times: Vec<Vec<f64>>,
+
+    pub fill_hole_time: f64,
+
+    pub conversion_time: f64,
+}
+
+impl Profile {
+    pub(crate) fn new(num_passes: &[usize]) -> Self {
+        Self {
+            blur_times: num_passes.iter().map(|&n| vec![0.0; n]).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        let blur_times = ::std::mem::replace(&mut self.blur_times, Vec::new());
+        *self = Self {
+            blur_times: blur_times.iter().map(|x| vec![0.0; x.len()]).collect(),
+            ..Default::default()
+        };
+    }
+
+    /// Returns the number of elements written by `write_flat`.
+    pub fn flat_len(&self) -> usize {
+        8 + self.blur_times.iter().map(|x| 1 + x.len()).sum::<usize>()
+    }
+
+    /// Writes the contents as a flat array of `f64` in the following order:
+    ///
+    ///  - `num_stamps`
+    ///  - `num_stamped_texels`
+    ///  - `stamp_time`
+    ///  - `num_processes`
+    ///  - `downsample_time`
+    ///  - `fill_hole_time`
+    ///  - `conversion_time`
+    ///  - The number of blurred mip levels, followed by, for each of them,
+    ///    the number of passes and the time spent on each pass.
+    ///
+    /// `out` must be at least `flat_len()` long.
+    pub fn write_flat(&self, out: &mut [f64]) {
+        let header = [
+            self.num_stamps as f64,
+            self.num_stamped_texels as f64,
+            self.stamp_time,
+            self.num_processes as f64,
+            self.downsample_time,
+            self.fill_hole_time,
+            self.conversion_time,
+            self.blur_times.len() as f64,
+        ];
+        out[0..8].copy_from_slice(&header);
+
+        let mut i = 8;
+        for passes in self.blur_times.iter() {
+            out[i] = passes.len() as f64;
+            out[i + 1..i + 1 + passes.len()].copy_from_slice(passes);
+            i += 1 + passes.len();
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "| Stage              |      Total (μs) |")?;
+        writeln!(f, "| ------------------ | --------------- |")?;
+        let row = |f: &mut fmt::Formatter, name: &str, time: f64| {
+            writeln!(f, "| {:<18} | {:>15.0} |", name, time * 1.0e6)
+        };
+        row(f, "Stamp", self.stamp_time)?;
+        row(f, "Downsample", self.downsample_time)?;
+        for (level, passes) in self.blur_times.iter().enumerate() {
+            for (pass, &time) in passes.iter().enumerate() {
+                row(f, &format!("Blur L{} P{}", level, pass), time)?;
+            }
+        }
+        row(f, "Fill Holes", self.fill_hole_time)?;
+        row(f, "Conversion", self.conversion_time)?;
+        writeln!(f)?;
+        writeln!(f, "Stamps: {}", self.num_stamps)?;
+        writeln!(f, "Stamped texels: {}", self.num_stamped_texels)?;
+        write!(f, "Processes: {}", self.num_processes)
+    }
+}
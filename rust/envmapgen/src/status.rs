@@ -0,0 +1,78 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Status codes returned by the FFI functions, and the storage for the
+//! message describing the last error.
+use std::cell::RefCell;
+
+use arcane_gfx::{Error, Result};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(i32)]
+pub enum Status {
+    Ok = 0,
+    DimensionMismatch = 1,
+    InvalidMipLevel = 2,
+    InvalidCubeFace = 3,
+    AllocationFailed = 4,
+    NonFiniteMatrix = 5,
+    InvalidCamera = 6,
+    SingularMatrix = 7,
+    InvalidFrameParams = 8,
+    InvalidResponseCurve = 9,
+    InvalidConfidenceCurve = 10,
+    InvalidArgument = 11,
+}
+
+impl From<Error> for Status {
+    fn from(x: Error) -> Self {
+        match x {
+            Error::DimensionMismatch => Status::DimensionMismatch,
+            Error::InvalidMipLevel(_) => Status::InvalidMipLevel,
+            Error::InvalidCubeFace(_) => Status::InvalidCubeFace,
+            Error::AllocationFailed => Status::AllocationFailed,
+            Error::NonFiniteMatrix => Status::NonFiniteMatrix,
+            Error::InvalidCamera => Status::InvalidCamera,
+            Error::SingularMatrix => Status::SingularMatrix,
+            Error::InvalidFrameParams => Status::InvalidFrameParams,
+            Error::InvalidResponseCurve => Status::InvalidResponseCurve,
+            Error::InvalidConfidenceCurve => Status::InvalidConfidenceCurve,
+            Error::InvalidArgument => Status::InvalidArgument,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Records the error (if any) as the last error and converts the result to
+/// a status code.
+pub fn handle_result(x: Result<()>) -> Status {
+    match x {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            set_last_error(e);
+            e.into()
+        }
+    }
+}
+
+pub fn set_last_error(e: Error) {
+    LAST_ERROR.with(|s| {
+        let mut s = s.borrow_mut();
+        s.clear();
+        use std::fmt::Write;
+        write!(s, "{}", e).unwrap();
+    });
+}
+
+pub fn with_last_error<F, R>(f: F) -> R
+where
+    F: FnOnce(&str) -> R,
+{
+    LAST_ERROR.with(|s| f(&s.borrow()))
+}
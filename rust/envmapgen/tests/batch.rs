@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Tests for `Context::stamp_batch`.
+extern crate cgmath;
+extern crate arcane_gfx;
+extern crate envmapgen;
+
+use cgmath::{Vector4, Matrix4, Quaternion, Deg};
+use cgmath::prelude::*;
+
+use arcane_gfx::{Image, Error};
+use arcane_gfx::camera::Camera;
+use envmapgen::context::{Context, StampFrame, MAX_EXPOSURE};
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 16;
+
+fn grey_image(value: u8) -> Vec<Vector4<u8>> {
+    vec![Vector4::new(value, value, value, 255); WIDTH * HEIGHT]
+}
+
+fn image(data: &[Vector4<u8>]) -> Image<&[Vector4<u8>]> {
+    Image { data, width: WIDTH, height: HEIGHT }
+}
+
+fn camera_matrix() -> Matrix4<f32> {
+    Camera::new(Quaternion::one(), Deg(90.0).into(), 1.0).environment_view_proj_matrix()
+}
+
+/// Returns the center texel of the `-Z` face, which is observed by the camera.
+fn center(context: &mut Context) -> Vector4<u8> {
+    context.process();
+    let size = context.image_size();
+    context.output_image(0, 5).data[size / 2 * size + size / 2]
+}
+
+fn assert_near(a: Vector4<u8>, b: Vector4<u8>) {
+    let d = a.cast::<i32>() - b.cast::<i32>();
+    assert!(d.x.abs() <= 1 && d.y.abs() <= 1 && d.z.abs() <= 1, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn single_frame_matches_stamp() {
+    let data = grey_image(150);
+
+    let mut context1 = Context::new();
+    context1.stamp(&image(&data), camera_matrix());
+
+    let mut context2 = Context::new();
+    context2.stamp_batch(&[StampFrame::new(image(&data), camera_matrix())]);
+
+    for face in 0..6 {
+        assert!(context1.output_image(0, face).data == context2.output_image(0, face).data);
+    }
+    assert_eq!(context1.profile().num_stamped_texels, context2.profile().num_stamped_texels);
+}
+
+#[test]
+fn weighted_average() {
+    let dark = grey_image(100);
+    let bright = grey_image(200);
+    let mixed = grey_image(180); // ≈ sqrt((100² + 200² × 3) / 4)
+
+    let mut context = Context::new();
+    context.stamp_batch(&[
+        StampFrame::new(image(&dark), camera_matrix()),
+        StampFrame { weight: 3.0, ..StampFrame::new(image(&bright), camera_matrix()) },
+    ]);
+    let actual = center(&mut context);
+
+    let mut context = Context::new();
+    context.stamp(&image(&mixed), camera_matrix());
+    assert_near(actual, center(&mut context));
+
+    // Zero-weight frames do not affect the result
+    let mut context = Context::new();
+    context.stamp_batch(&[
+        StampFrame::new(image(&mixed), camera_matrix()),
+        StampFrame { weight: 0.0, ..StampFrame::new(image(&dark), camera_matrix()) },
+    ]);
+    assert_near(actual, center(&mut context));
+}
+
+#[test]
+fn exposure() {
+    let data = grey_image(200);
+    let expected = grey_image(141); // ≈ 200 / sqrt(2)
+
+    let mut context = Context::new();
+    context.stamp_batch(&[StampFrame { exposure: 1.0, ..StampFrame::new(image(&data), camera_matrix()) }]);
+    let actual = center(&mut context);
+
+    let mut context = Context::new();
+    context.stamp(&image(&expected), camera_matrix());
+    assert_near(actual, center(&mut context));
+}
+
+#[test]
+fn invalid_frames() {
+    let data = grey_image(200);
+    let frame = StampFrame::new(image(&data), camera_matrix());
+    let mut context = Context::new();
+
+    let invalid = [
+        StampFrame { weight: -1.0, ..frame },
+        StampFrame { weight: ::std::f32::INFINITY, ..frame },
+        StampFrame { exposure: ::std::f32::NAN, ..frame },
+        StampFrame { exposure: 200.0, ..frame },
+        StampFrame { exposure: -200.0, ..frame },
+        StampFrame { exposure: MAX_EXPOSURE + 1.0, ..frame },
+        StampFrame { exposure: -MAX_EXPOSURE - 1.0, ..frame },
+    ];
+    for f in invalid.iter() {
+        assert_eq!(context.try_stamp_batch(&[frame, *f]), Err(Error::InvalidFrameParams));
+    }
+
+    let singular = StampFrame { camera_matrix: Matrix4::zero(), ..frame };
+    assert_eq!(context.try_stamp_batch(&[frame, singular]), Err(Error::SingularMatrix));
+
+    // Nothing is stamped if any of the frames is invalid
+    assert_eq!(context.profile().num_stamps, 0);
+    assert_eq!(center(&mut context).x, 0);
+}
+
+#[test]
+fn extreme_exposures() {
+    let data = grey_image(200);
+    let frame = StampFrame::new(image(&data), camera_matrix());
+    let mut context = Context::new();
+    context.set_hdr_merge(true);
+    context.stamp_batch(&[StampFrame { exposure: MAX_EXPOSURE, ..frame }]);
+    context.stamp_batch(&[StampFrame { exposure: -MAX_EXPOSURE, ..frame }]);
+    context.process();
+
+    let mut out = vec![0.0; context.hdr_output_len()];
+    context.copy_hdr_output(&mut out).unwrap();
+    assert!(out.iter().all(|x| x.is_finite()));
+}
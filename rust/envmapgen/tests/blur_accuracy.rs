@@ -0,0 +1,241 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Compares the mip pyramid generated by `ltasgblur` against a brute-force
+//! spherical convolution. Run with `--nocapture` to see the per-level report.
+extern crate cgmath;
+extern crate image;
+extern crate rand;
+extern crate smallvec;
+extern crate hyperenvmap;
+extern crate arcane_gfx;
+extern crate envmapgen;
+
+mod common;
+
+use std::fmt;
+use std::mem::swap;
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+use smallvec::SmallVec;
+use hyperenvmap::ltasgblur;
+
+use arcane_gfx::convolve::{convolve_cube_map, SphericalKernel};
+use arcane_gfx::cubemap::texel_solid_angle;
+use envmapgen::cubemaputils::MipPyramidGenParams;
+
+use common::sim::{self, CubeMap};
+
+const SIZE: usize = 32;
+const NUM_LEVELS: usize = 5;
+
+/// The ground truth used by `sim`, plus a few small bright spots so the
+/// shape of the blur kernel actually matters.
+fn test_input() -> CubeMap {
+    let base = sim::standard_ground_truth(SIZE);
+    let spots = [
+        Vector3::new(0.3f32, 0.8, -0.5).normalize(),
+        Vector3::new(-0.7f32, 0.1, 0.7).normalize(),
+        // Near a cube corner, where the texel density is the lowest
+        Vector3::new(1.0f32, -1.0, 1.0).normalize(),
+    ];
+    CubeMap::from_fn(SIZE, |d| {
+        let mut value = base.sample(d);
+        for spot in spots.iter() {
+            if d.dot(*spot) > 0.995 {
+                value += Vector3::new(1.0, 1.0, 1.0);
+            }
+        }
+        value
+    })
+}
+
+/// The parameters used by `Context` (`high_quality == false`) and
+/// `emg_spherical_blur` (`high_quality == true`), except for `size`.
+fn pyramid_params(high_quality: bool) -> MipPyramidGenParams {
+    MipPyramidGenParams {
+        kernel_resolution: 4.0,
+        kernel_width: 2.0,
+        log2_min_sigma: -5.0,
+        num_levels: NUM_LEVELS,
+        size: SIZE,
+        high_quality,
+    }
+}
+
+/// Generates the mip pyramid in the same way as `Context::process` and
+/// `emg_spherical_blur`, but without quantization or hole filling.
+fn fast_pyramid(input: &CubeMap, high_quality: bool) -> Vec<CubeMap> {
+    let setup = pyramid_params(high_quality).setup();
+    let kernel = &setup.kernel[..];
+
+    let mut levels: Vec<CubeMap> = Vec::new();
+    for (i, &(kernel_scale, num_passes)) in setup.levels.iter().enumerate() {
+        let size = SIZE >> i;
+        let mut cur = if i == 0 {
+            input.clone()
+        } else {
+            levels[i - 1].downsample()
+        };
+        let mut tmp = vec![vec![Vector4::zero(); size * size]; 6];
+
+        for _ in 0..num_passes {
+            for phase in 0..3 {
+                ltasgblur::ltasg_single(
+                    tmp.iter_mut()
+                        .map(Vec::as_mut_slice)
+                        .collect::<SmallVec<[_; 6]>>()
+                        .as_mut_slice(),
+                    cur.faces
+                        .iter()
+                        .map(|face| &face.data[..])
+                        .collect::<SmallVec<[_; 6]>>()
+                        .as_slice(),
+                    size,
+                    kernel,
+                    kernel_scale,
+                    phase,
+                    ltasgblur::StandardCubeMapTrait,
+                );
+                for (face, tmp_face) in cur.faces.iter_mut().zip(tmp.iter_mut()) {
+                    swap(&mut face.data, tmp_face);
+                }
+            }
+        }
+
+        levels.push(cur);
+    }
+    levels
+}
+
+/// Convolves `input` with the Gaussian the pyramid generated with `params`
+/// is meant to approximate at `level`.
+fn reference_level(input: &CubeMap, params: &MipPyramidGenParams, level: usize) -> CubeMap {
+    // `MipPyramidGenParams` specifies σ in units of the face width. A face
+    // spans the range `[-1, 1]` (a width of `2`) in clip space, and the clip
+    // space coordinate is `tan θ ≈ θ` near the face center, so the angular σ
+    // is `2` times as large.
+    let sigma = params.sigma(level) * 2.0;
+    let size = SIZE >> level;
+    let mut out = CubeMap::from_fn(size, |_| Vector3::zero());
+    convolve_cube_map(
+        out.faces
+            .iter_mut()
+            .map(|face| &mut face.data[..])
+            .collect::<Vec<_>>()
+            .as_mut_slice(),
+        size,
+        input
+            .faces
+            .iter()
+            .map(|face| &face.data[..])
+            .collect::<Vec<_>>()
+            .as_slice(),
+        SIZE,
+        SphericalKernel::Gaussian { sigma },
+    );
+    out
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LevelError {
+    level: usize,
+    size: usize,
+    num_passes: usize,
+
+    /// The solid angle-weighted RMS error divided by the mean luminance of
+    /// the reference.
+    relative_rmse: f32,
+
+    /// The largest absolute error of a single channel.
+    max_error: f32,
+}
+
+struct Report(Vec<LevelError>);
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "level  size  passes  rel. RMSE  max error")?;
+        for e in self.0.iter() {
+            writeln!(
+                f,
+                "{:5}  {:4}  {:6}  {:9.4}  {:9.4}",
+                e.level,
+                e.size,
+                e.num_passes,
+                e.relative_rmse,
+                e.max_error
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn compare(input: &CubeMap, high_quality: bool) -> Report {
+    let fast = fast_pyramid(input, high_quality);
+    let params = pyramid_params(high_quality);
+    let setup = params.setup();
+
+    Report(
+        fast.iter()
+            .enumerate()
+            .map(|(level, actual)| {
+                let expected = reference_level(input, &params, level);
+                let size = actual.size;
+
+                let mut sum_sq = 0.0;
+                let mut sum_value = 0.0;
+                let mut total_weight = 0.0;
+                let mut max_error = 0.0f32;
+                for (e_face, a_face) in expected.faces.iter().zip(actual.faces.iter()) {
+                    for y in 0..size {
+                        for x in 0..size {
+                            let weight = texel_solid_angle(x, y, size);
+                            let e = e_face.data[x + y * size].truncate();
+                            let a = a_face.data[x + y * size].truncate();
+                            let diff = a - e;
+                            sum_sq += diff.dot(diff) / 3.0 * weight;
+                            sum_value += (e.x + e.y + e.z) / 3.0 * weight;
+                            total_weight += weight;
+                            max_error = max_error
+                                .max(diff.x.abs())
+                                .max(diff.y.abs())
+                                .max(diff.z.abs());
+                        }
+                    }
+                }
+
+                LevelError {
+                    level,
+                    size,
+                    num_passes: setup.levels[level].1,
+                    relative_rmse: (sum_sq / total_weight).sqrt() / (sum_value / total_weight),
+                    max_error,
+                }
+            })
+            .collect(),
+    )
+}
+
+fn check(report: &Report) {
+    for e in report.0.iter() {
+        assert!(e.relative_rmse < 0.1, "{:?}", e);
+    }
+}
+
+#[test]
+fn realtime_pyramid() {
+    let report = compare(&test_input(), false);
+    println!("Real-time pyramid vs. reference:\n{}", report);
+    check(&report);
+}
+
+#[test]
+fn high_quality_pyramid() {
+    let report = compare(&test_input(), true);
+    println!("High quality pyramid vs. reference:\n{}", report);
+    check(&report);
+}
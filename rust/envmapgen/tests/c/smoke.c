@@ -0,0 +1,333 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+/*
+ * Exercises the C API of envmapgen through the native shared library.
+ * See `Makefile` for how to build and run this program.
+ */
+#include <stdio.h>
+#include <string.h>
+
+#include "envmapgen.h"
+
+static int num_failures = 0;
+
+#define CHECK(cond) do { \
+        if (!(cond)) { \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+            ++num_failures; \
+        } \
+    } while (0)
+
+static void print_last_error(void)
+{
+    char message[256];
+    uintptr_t len = emg_last_error_copy((uint8_t *)message, sizeof(message) - 1);
+    message[len] = 0;
+    printf("  last error: %s\n", message);
+}
+
+int main(void)
+{
+    EmgContext *context = emg_context_new();
+    CHECK(context != NULL);
+
+    uintptr_t size = emg_context_get_image_size(context);
+    CHECK(size > 0);
+    printf("image size = %u\n", (unsigned)size);
+
+    /* A uniformly grey camera image looking at -Z */
+    enum { CAMERA_WIDTH = 32, CAMERA_HEIGHT = 24 };
+    static uint8_t camera_image[CAMERA_WIDTH * CAMERA_HEIGHT * 4];
+    memset(camera_image, 128, sizeof(camera_image));
+
+    /* Column-major; maps the view direction -Z to the center of the
+     * clip space with a 90° field of view */
+    float camera_matrix[16] = {
+        1, 0, 0, 0,
+        0, 1, 0, 0,
+        0, 0, 0, -1,
+        0, 0, 1, 0,
+    };
+
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_OK);
+
+    /* Non-finite matrices must be rejected */
+    float bad_matrix[16];
+    memcpy(bad_matrix, camera_matrix, sizeof(bad_matrix));
+    bad_matrix[0] = 0.0f / 0.0f;
+    EmgStatus status = emg_context_stamp(context, camera_image, CAMERA_WIDTH,
+        CAMERA_HEIGHT, bad_matrix);
+    CHECK(status == EMG_STATUS_NON_FINITE_MATRIX);
+    CHECK(emg_last_error_len() > 0);
+    print_last_error();
+    CHECK(emg_last_error_copy(NULL, 0) == 0);
+    CHECK(emg_last_error_copy(NULL, 16) == 0);
+
+    /* Stamp using a camera orientation (looking toward -Z) */
+    EmgCameraParams camera = {{0.0f, 0.0f, 0.0f, 1.0f}, 1.0f,
+        (float)CAMERA_WIDTH / CAMERA_HEIGHT};
+    CHECK(emg_context_stamp_camera(context, camera_image, CAMERA_WIDTH,
+        CAMERA_HEIGHT, &camera) == EMG_STATUS_OK);
+    camera.fov_y = 0.0f;
+    CHECK(emg_context_stamp_camera(context, camera_image, CAMERA_WIDTH,
+        CAMERA_HEIGHT, &camera) == EMG_STATUS_INVALID_CAMERA);
+
+    /* Stamp two frames at once, the second one underexposed by a stop */
+    EmgStampFrameDesc frames[2];
+    for (int i = 0; i < 2; ++i) {
+        frames[i].image = camera_image;
+        frames[i].width = CAMERA_WIDTH;
+        frames[i].height = CAMERA_HEIGHT;
+        memcpy(frames[i].camera_matrix, camera_matrix, sizeof(camera_matrix));
+        frames[i].weight = 1.0f;
+        frames[i].exposure = (float)-i;
+    }
+    CHECK(emg_context_stamp_batch(context, frames, 2) == EMG_STATUS_OK);
+    CHECK(emg_context_stamp_batch(context, NULL, 0) == EMG_STATUS_OK);
+    frames[1].weight = -1.0f;
+    CHECK(emg_context_stamp_batch(context, frames, 2) ==
+        EMG_STATUS_INVALID_FRAME_PARAMS);
+    frames[1].weight = 1.0f;
+    frames[1].exposure = -200.0f;
+    CHECK(emg_context_stamp_batch(context, frames, 2) ==
+        EMG_STATUS_INVALID_FRAME_PARAMS);
+
+    /* Null pointers are rejected unless the corresponding length is zero */
+    CHECK(emg_context_stamp(context, NULL, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        NULL) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_stamp_camera(context, camera_image, CAMERA_WIDTH,
+        CAMERA_HEIGHT, NULL) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_stamp_batch(context, NULL, 2) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_set_response_table(context, NULL) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_get_lights(context, NULL, 0) == 0);
+    CHECK(emg_context_copy_hdr_output(context, NULL, 0) == EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(emg_context_copy_hdr_output(context, NULL, 1u << 30) == EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_spherical_blur(16, NULL) == EMG_STATUS_INVALID_ARGUMENT);
+
+    /* The gain is estimated from the texels overlapping the previous frames */
+    float gain[3] = {0.0f, 0.0f, 0.0f};
+    emg_context_set_auto_gain(context, true, true);
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_OK);
+    CHECK(emg_context_get_last_gain(context, gain) > 0);
+    printf("gain = (%f, %f, %f)\n", gain[0], gain[1], gain[2]);
+    CHECK(gain[0] > 1.0f && gain[0] < 2.0f);
+
+    emg_context_set_light_extraction(context, 2, false);
+    emg_context_set_num_sg_lobes(context, 8);
+    emg_context_set_prior(context, true, 1.5f, true);
+    CHECK(emg_context_process(context) == EMG_STATUS_OK);
+
+    /* A uniformly grey image has no dominant light source */
+    EmgLightDesc lights[2];
+    CHECK(emg_context_get_lights(context, lights, 2) == 0);
+
+    /* Seven values per spherical Gaussian */
+    float lobes[8 * 7];
+    CHECK(emg_context_sg_len(context) == 8 * 7);
+    CHECK(emg_context_copy_spherical_gaussians(context, lobes, 8 * 7) == EMG_STATUS_OK);
+    CHECK(emg_context_copy_spherical_gaussians(context, lobes, 8 * 7 - 1) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(lobes[3] > 0.0f);
+
+    /* Only the -Z face has been observed */
+    float face_coverage[6];
+    float coverage = emg_context_get_coverage(context, face_coverage);
+    printf("coverage = %f\n", coverage);
+    CHECK(face_coverage[5] > 0.99f);
+    CHECK(face_coverage[0] < 0.5f);
+    CHECK(coverage > 0.15f && coverage < 0.5f);
+
+    /* The next view should point away from the observed -Z face */
+    EmgViewSuggestionDesc suggestion;
+    CHECK(emg_context_suggest_view_direction(context, 0.5f, false, NULL, 0.0f, &suggestion));
+    CHECK(suggestion.direction[2] > -0.5f);
+    CHECK(suggestion.solid_angle > 0.0f);
+
+    const float forward[3] = {0.0f, 0.0f, -1.0f};
+    CHECK(emg_context_suggest_view_direction(context, 0.5f, true, forward, 1.2f, &suggestion));
+    CHECK(suggestion.direction[1] >= 0.0f);
+    CHECK(-suggestion.direction[2] > 0.36f - 1.0e-4f);
+
+    /* Coverage and confidence of the 1x1 mip level */
+    uintptr_t log_size = 0;
+    while ((size >> log_size) > 1) {
+        ++log_size;
+    }
+    float coverage_map[6 * 2];
+    CHECK(emg_context_coverage_map_len(context, log_size) == 6 * 2);
+    CHECK(emg_context_copy_coverage_map(context, log_size, coverage_map, 6 * 2) ==
+        EMG_STATUS_OK);
+    CHECK(coverage_map[5 * 2] > 0.99f);
+    CHECK(coverage_map[5 * 2 + 1] > 0.0f && coverage_map[5 * 2 + 1] <= 1.0f);
+    CHECK(emg_context_coverage_map_len(context, log_size + 1) == 0);
+    CHECK(emg_context_copy_coverage_map(context, log_size + 1, coverage_map, 6 * 2) ==
+        EMG_STATUS_INVALID_MIP_LEVEL);
+
+    /* Out-of-range indices must be reported as errors */
+    const uint8_t *data = NULL;
+    CHECK(emg_context_get_output_image_data(context, 0, 0, &data) == EMG_STATUS_OK);
+    CHECK(data != NULL);
+    CHECK(emg_context_get_output_image_data(context, 100, 0, &data) ==
+        EMG_STATUS_INVALID_MIP_LEVEL);
+    CHECK(emg_context_get_output_image_data(context, 0, 6, &data) ==
+        EMG_STATUS_INVALID_CUBE_FACE);
+
+    /* Packed output */
+    uintptr_t output_size = emg_context_output_size(context);
+    uintptr_t expected_size = 0;
+    for (uintptr_t i = size; i; i >>= 1) {
+        expected_size += i * i * 4 * 6;
+    }
+    CHECK(output_size == expected_size);
+
+    uint8_t *output = emg_malloc(output_size);
+    CHECK(output != NULL);
+    CHECK(emg_context_copy_output(context, output, output_size) == EMG_STATUS_OK);
+    CHECK(emg_context_copy_output(context, output, output_size - 1) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+
+    /* The -Z face (index 5) was observed by the camera */
+    uintptr_t neg_z_offset = size * size * 4 * 5;
+    uintptr_t center = neg_z_offset + ((size / 2) * size + size / 2) * 4;
+    printf("-Z center = (%u, %u, %u, %u)\n", output[center], output[center + 1],
+        output[center + 2], output[center + 3]);
+    CHECK(output[center] > 0);
+
+    CHECK(emg_context_copy_output(context, NULL, output_size) ==
+        EMG_STATUS_INVALID_ARGUMENT);
+    CHECK(emg_context_copy_changed_output(context, output, output_size, NULL) ==
+        EMG_STATUS_INVALID_ARGUMENT);
+
+    uint32_t changed_levels = 0xffffffff;
+    CHECK(emg_context_copy_changed_output(context, output, output_size,
+        &changed_levels) == EMG_STATUS_OK);
+    CHECK(changed_levels == 0);
+
+    /* Processing again without new input changes nothing */
+    CHECK(emg_context_process(context) == EMG_STATUS_OK);
+    CHECK(emg_context_copy_changed_output(context, output, output_size,
+        &changed_levels) == EMG_STATUS_OK);
+    CHECK(changed_levels == 0);
+    emg_free(output);
+
+    /* Linear radiance output */
+    uintptr_t hdr_len = emg_context_hdr_output_len(context);
+    CHECK(hdr_len == output_size);
+    float *hdr_output = (float *)emg_malloc(hdr_len * sizeof(float));
+    CHECK(hdr_output != NULL);
+    CHECK(emg_context_copy_hdr_output(context, hdr_output, hdr_len) == EMG_STATUS_OK);
+    CHECK(emg_context_copy_hdr_output(context, hdr_output, hdr_len - 1) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(hdr_output[center] > 0.0f);
+    emg_free((uint8_t *)hdr_output);
+
+    /* Response curves */
+    CHECK(emg_context_set_response_gamma(context, 2.2f) == EMG_STATUS_OK);
+    CHECK(emg_context_set_response_gamma(context, -1.0f) ==
+        EMG_STATUS_INVALID_RESPONSE_CURVE);
+    float response[256];
+    for (int i = 0; i < 256; ++i) {
+        response[i] = (float)i / 255.0f;
+    }
+    CHECK(emg_context_set_response_table(context, response) == EMG_STATUS_OK);
+    response[10] = 1.0f;
+    CHECK(emg_context_set_response_table(context, response) ==
+        EMG_STATUS_INVALID_RESPONSE_CURVE);
+    emg_context_set_hdr_merge(context, true);
+
+    /* Profiling counters */
+    uintptr_t profile_len = emg_context_profile_len(context);
+    double profile[64];
+    CHECK(profile_len <= sizeof(profile) / sizeof(profile[0]));
+    CHECK(emg_context_copy_profile(context, profile, profile_len, true) == EMG_STATUS_OK);
+    CHECK(profile[0] == 5.0); /* num_stamps */
+    CHECK(profile[1] > 0.0);  /* num_stamped_texels */
+    CHECK(profile[3] == 2.0); /* num_processes */
+
+    emg_context_destroy(context);
+
+    /* A fallback environment map fills everything before anything is scanned */
+    context = emg_context_new();
+    float fallback[6 * 4];
+    for (int i = 0; i < 6 * 4; ++i) {
+        fallback[i] = 0.5f;
+    }
+    CHECK(emg_context_set_fallback(context, fallback, 3) == EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(emg_context_set_fallback(context, fallback, (uintptr_t)-1 / 2) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(emg_context_set_fallback(context, fallback, 1) == EMG_STATUS_OK);
+    CHECK(emg_context_set_confidence_curve(context, 4.0f, 2.0f) == EMG_STATUS_OK);
+    CHECK(emg_context_set_confidence_curve(context, 0.0f, 2.0f) ==
+        EMG_STATUS_INVALID_CONFIDENCE_CURVE);
+    CHECK(emg_context_set_confidence_curve(context, 1.0f, -1.0f) ==
+        EMG_STATUS_INVALID_CONFIDENCE_CURVE);
+    CHECK(emg_context_process(context) == EMG_STATUS_OK);
+    hdr_len = emg_context_hdr_output_len(context);
+    hdr_output = (float *)emg_malloc(hdr_len * sizeof(float));
+    CHECK(hdr_output != NULL);
+    CHECK(emg_context_copy_hdr_output(context, hdr_output, hdr_len) == EMG_STATUS_OK);
+    CHECK(hdr_output[0] > 0.49f && hdr_output[0] < 0.51f);
+    CHECK(hdr_output[hdr_len - 4] > 0.49f && hdr_output[hdr_len - 4] < 0.51f);
+    emg_free((uint8_t *)hdr_output);
+    CHECK(emg_context_set_fallback(context, NULL, 0) == EMG_STATUS_OK);
+    emg_context_destroy(context);
+
+    /* Record the stamped frames into a capture file */
+    context = emg_context_new();
+    EmgIntrinsics intrinsics = {CAMERA_WIDTH, CAMERA_HEIGHT, {16.0f, 16.0f}, {16.0f, 12.0f}};
+    CHECK(emg_context_recording_len(context) == 0);
+    CHECK(emg_context_start_recording(context, &intrinsics, 0) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(emg_context_start_recording(context, &intrinsics, 2) == EMG_STATUS_OK);
+    uintptr_t header_len = emg_context_recording_len(context);
+    CHECK(header_len == 36);
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_OK);
+    frames[1].exposure = -1.0f;
+    CHECK(emg_context_stamp_batch(context, frames, 2) == EMG_STATUS_OK);
+
+    /* Three 16x12 frames, only the first of which has a response curve */
+    uintptr_t recording_len = emg_context_recording_len(context);
+    CHECK(recording_len == header_len + 256 * 4 +
+        3 * (8 + 4 + 4 + 4 + 16 * 4 + 8 + 16 * 12 * 4));
+    uint8_t *recording = emg_malloc(recording_len);
+    CHECK(recording != NULL);
+    CHECK(emg_context_copy_recording(context, recording, recording_len) == EMG_STATUS_OK);
+    CHECK(emg_context_copy_recording(context, recording, recording_len - 1) ==
+        EMG_STATUS_DIMENSION_MISMATCH);
+    CHECK(memcmp(recording, "EMGCAP", 6) == 0);
+    emg_free(recording);
+
+    /* Images larger than the recorded camera image are rejected */
+    intrinsics.width = CAMERA_WIDTH - 1;
+    CHECK(emg_context_start_recording(context, &intrinsics, 1) == EMG_STATUS_OK);
+    CHECK(emg_context_recording_len(context) == header_len);
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_DIMENSION_MISMATCH);
+    emg_context_stop_recording(context);
+    CHECK(emg_context_recording_len(context) == 0);
+    CHECK(emg_context_stamp(context, camera_image, CAMERA_WIDTH, CAMERA_HEIGHT,
+        camera_matrix) == EMG_STATUS_OK);
+    emg_context_destroy(context);
+
+    /* emg_spherical_blur rejects sizes that can't be halved four times */
+    uint8_t *images = emg_malloc(6 * 6 * 4 * 2);
+    CHECK(emg_spherical_blur(6, images) == EMG_STATUS_DIMENSION_MISMATCH);
+    emg_free(images);
+    emg_free(NULL);
+
+    if (num_failures) {
+        fprintf(stderr, "%d check(s) failed\n", num_failures);
+        return 1;
+    }
+    printf("OK\n");
+    return 0;
+}
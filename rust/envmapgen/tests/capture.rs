@@ -0,0 +1,245 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+extern crate cgmath;
+extern crate arcane_gfx;
+extern crate envmapgen;
+
+use std::io::{self, Cursor};
+use cgmath::{Vector4, Matrix4};
+use cgmath::prelude::*;
+
+use arcane_gfx::Image;
+use arcane_gfx::response::ResponseCurve;
+use envmapgen::capture::{CaptureReader, CaptureWriter, Intrinsics};
+use envmapgen::context::{AutoGain, Context, StampFrame, StampSettings};
+
+const WIDTH: usize = 8;
+const HEIGHT: usize = 6;
+
+fn intrinsics() -> Intrinsics {
+    Intrinsics {
+        width: WIDTH as u32,
+        height: HEIGHT as u32,
+        focal_length: [6.0, 6.0],
+        principal_point: [4.0, 3.0],
+    }
+}
+
+fn image_data() -> Vec<Vector4<u8>> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| Vector4::new(i as u8, (i * 2) as u8, (i * 3) as u8, 255))
+        .collect()
+}
+
+fn hdr_settings() -> StampSettings {
+    StampSettings {
+        response: ResponseCurve::srgb(),
+        hdr_merge: true,
+        auto_gain: AutoGain {
+            exposure: true,
+            white_balance: false,
+        },
+    }
+}
+
+fn write_capture() -> Vec<u8> {
+    let data = image_data();
+    let image = Image {
+        data: &data[..],
+        width: WIDTH,
+        height: HEIGHT,
+    };
+    let frame = StampFrame::new(image, Matrix4::identity());
+    let mut writer = CaptureWriter::new(Vec::new(), &intrinsics()).unwrap();
+    writer.write_frame(0.5, &frame, &StampSettings::default(), 1).unwrap();
+    writer
+        .write_batch(
+            1.0,
+            &[
+                StampFrame {
+                    exposure: 1.5,
+                    ..frame
+                },
+                StampFrame {
+                    weight: 0.25,
+                    ..frame
+                },
+            ],
+            &hdr_settings(),
+            2,
+        )
+        .unwrap();
+    writer.into_inner()
+}
+
+/// The offset of the image width field of the first frame (after the header,
+/// and the timestamp, the flags, the response curve, the weight, the exposure
+/// and the camera matrix of the frame).
+const FRAME_WIDTH_OFFSET: usize = 36 + 8 + 4 + 1024 + 8 + 64;
+
+#[test]
+fn round_trip() {
+    let bytes = write_capture();
+    let reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(*reader.intrinsics(), intrinsics());
+
+    let frames: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[0].timestamp, 0.5);
+    assert_eq!(frames[0].camera_matrix, Matrix4::identity());
+    assert_eq!((frames[0].weight, frames[0].exposure), (1.0, 0.0));
+    assert!(!frames[0].continues_batch);
+    assert_eq!(frames[0].settings, StampSettings::default());
+    assert_eq!((frames[0].image.width, frames[0].image.height), (WIDTH, HEIGHT));
+    assert_eq!(frames[0].image.data, image_data());
+
+    assert_eq!((frames[1].weight, frames[1].exposure), (1.0, 1.5));
+    assert_eq!((frames[2].weight, frames[2].exposure), (0.25, 0.0));
+    assert!(!frames[1].continues_batch);
+    assert!(frames[2].continues_batch);
+    for frame in frames[1..].iter() {
+        assert_eq!(frame.timestamp, 1.0);
+        assert_eq!(frame.settings, hdr_settings());
+        assert_eq!((frame.image.width, frame.image.height), (WIDTH / 2, HEIGHT / 2));
+    }
+}
+
+/// Version 1 files (without the stamp settings) must still be readable.
+#[test]
+fn version1() {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"EMGCAP\0\0");
+    for &x in [1, WIDTH as u32, HEIGHT as u32].iter() {
+        bytes.extend_from_slice(&le_u32(x));
+    }
+    for &x in [6.0f32, 6.0, 4.0, 3.0, 0.0, 0.0].iter() {
+        bytes.extend_from_slice(&le_u32(x.to_bits()));
+    }
+    for &x in <Matrix4<f32> as AsRef<[f32; 16]>>::as_ref(&Matrix4::identity()).iter() {
+        bytes.extend_from_slice(&le_u32(x.to_bits()));
+    }
+    bytes.extend_from_slice(&le_u32(1));
+    bytes.extend_from_slice(&le_u32(1));
+    bytes.extend_from_slice(&[1, 2, 3, 4]);
+
+    let reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(*reader.intrinsics(), intrinsics());
+    let frames: Vec<_> = reader.map(Result::unwrap).collect();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].timestamp, 0.0);
+    assert_eq!(frames[0].camera_matrix, Matrix4::identity());
+    assert_eq!((frames[0].weight, frames[0].exposure), (1.0, 0.0));
+    assert_eq!(frames[0].settings, StampSettings::default());
+    assert_eq!(frames[0].image.data, vec![Vector4::new(1, 2, 3, 4)]);
+}
+
+fn le_u32(x: u32) -> [u8; 4] {
+    [x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8]
+}
+
+#[test]
+fn corrupt_frames() {
+    // A frame larger than the camera image must be rejected before its data
+    // is allocated
+    let mut bytes = write_capture();
+    for &(offset, value) in [(FRAME_WIDTH_OFFSET, 0xffffu32), (FRAME_WIDTH_OFFSET + 4, 0xffff)].iter() {
+        let mut bytes = bytes.clone();
+        bytes[offset..offset + 4].copy_from_slice(&le_u32(value));
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let e = reader.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    // Truncated
+    let len = bytes.len();
+    bytes.truncate(len - 1);
+    let frames: Vec<_> = CaptureReader::new(Cursor::new(bytes)).unwrap().collect();
+    assert_eq!(frames.len(), 3);
+    assert!(frames[1].is_ok());
+    assert_eq!(frames[2].as_ref().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+/// Replaying a capture recorded by `Context` must reproduce the recorded
+/// session.
+#[test]
+fn record_and_replay() {
+    let data = image_data();
+    let image = Image {
+        data: &data[..],
+        width: WIDTH,
+        height: HEIGHT,
+    };
+    let camera_matrix = Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 0.0, -1.0,
+        0.0, 0.0, 1.0, 0.0,
+    );
+    let frame = StampFrame::new(image, camera_matrix);
+
+    let mut context = Context::new();
+    context.start_recording(&intrinsics(), 1).unwrap();
+    context.stamp(&image, camera_matrix);
+    context.set_stamp_settings(&hdr_settings());
+    context.stamp_batch(&[
+        StampFrame {
+            exposure: 1.0,
+            ..frame
+        },
+        StampFrame {
+            weight: 0.5,
+            ..frame
+        },
+    ]);
+    let bytes = context.stop_recording().unwrap();
+    assert_eq!(context.recording(), None);
+    assert!(context.coverage().total > 0.0);
+    context.process();
+
+    let mut replayed = Context::new();
+    let mut batch: Vec<_> = Vec::new();
+    let mut frames = CaptureReader::new(Cursor::new(bytes)).unwrap().map(Result::unwrap).peekable();
+    let mut num_batches = 0;
+    while let Some(frame) = frames.next() {
+        batch.push(frame);
+        if frames.peek().map(|f| f.continues_batch).unwrap_or(false) {
+            continue;
+        }
+        replayed.set_stamp_settings(&batch[0].settings);
+        {
+            let stamp_frames: Vec<_> = batch.iter().map(|f| f.stamp_frame()).collect();
+            replayed.stamp_batch(&stamp_frames);
+        }
+        batch.clear();
+        num_batches += 1;
+    }
+    assert_eq!(num_batches, 2);
+    replayed.process();
+
+    let mut expected = vec![0.0; context.hdr_output_len()];
+    let mut actual = vec![0.0; replayed.hdr_output_len()];
+    context.copy_hdr_output(&mut expected).unwrap();
+    replayed.copy_hdr_output(&mut actual).unwrap();
+    assert!(expected == actual);
+}
+
+#[test]
+fn record_oversized_image() {
+    let data = image_data();
+    let image = Image {
+        data: &data[..],
+        width: WIDTH,
+        height: HEIGHT,
+    };
+    let mut context = Context::new();
+    context
+        .start_recording(&Intrinsics { width: WIDTH as u32 - 1, ..intrinsics() }, 1)
+        .unwrap();
+    assert!(context.try_stamp(&image, Matrix4::identity()).is_err());
+    assert_eq!(context.recording().map(|x| x.len()), Some(36));
+    assert!(context.start_recording(&intrinsics(), 0).is_err());
+}
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Golden image comparison.
+//!
+//! Expected images are stored in `tests/data/golden`. When an image does
+//! not match, the actual image and a difference image (amplified by 16x) are
+//! written to `target/golden-diff`.
+//!
+//! Setting the environment variable `EMG_BLESS` makes the tests overwrite
+//! the expected images with the actual ones. A missing expected image is
+//! reported as a failure; record it explicitly with `EMG_BLESS`.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use cgmath::Vector4;
+use image;
+
+use arcane_gfx::Image;
+use arcane_gfx::cubemap::CubeFace;
+
+pub fn data_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../target/golden-diff")
+}
+
+pub fn face_name(face: CubeFace) -> &'static str {
+    match face {
+        CubeFace::PositiveX => "posx",
+        CubeFace::NegativeX => "negx",
+        CubeFace::PositiveY => "posy",
+        CubeFace::NegativeY => "negy",
+        CubeFace::PositiveZ => "posz",
+        CubeFace::NegativeZ => "negz",
+    }
+}
+
+pub fn load_image(name: &str) -> Image<Vec<Vector4<u8>>> {
+    let path = data_dir().join(name);
+    let img = image::open(&path)
+        .unwrap_or_else(|e| panic!("could not load {}: {}", path.display(), e))
+        .to_rgba();
+    Image {
+        data: img.pixels()
+            .map(|p| Vector4::new(p.data[0], p.data[1], p.data[2], p.data[3]))
+            .collect(),
+        width: img.width() as usize,
+        height: img.height() as usize,
+    }
+}
+
+/// Loads a cube map stored as six images named `PREFIX_FACE.png`.
+pub fn load_cube_map(prefix: &str) -> Vec<Image<Vec<Vector4<u8>>>> {
+    (0..6)
+        .map(|i| {
+            let face = CubeFace::from_ordinal(i).unwrap();
+            load_image(&format!("{}_{}.png", prefix, face_name(face)))
+        })
+        .collect()
+}
+
+fn save_image(path: &PathBuf, image: &Image<&[Vector4<u8>]>) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut img = image::RgbaImage::new(image.width as u32, image.height as u32);
+    for (y, x) in img.pixels_mut().zip(image.data.iter()) {
+        y.data = [x.x, x.y, x.z, x.w];
+    }
+    img.save(path).unwrap();
+}
+
+/// Collects the results of golden image comparisons, and reports all
+/// mismatches at once when `finish` is called.
+pub struct Golden {
+    failures: Vec<String>,
+}
+
+impl Golden {
+    pub fn new() -> Self {
+        Self { failures: Vec::new() }
+    }
+
+    /// Compares `actual` against the expected image `NAME.png`. Each channel
+    /// may differ by up to `tolerance`.
+    pub fn check(&mut self, name: &str, actual: &Image<&[Vector4<u8>]>, tolerance: u8) {
+        let file_name = format!("{}.png", name);
+        let expected_path = data_dir().join(&file_name);
+
+        if env::var_os("EMG_BLESS").is_some() {
+            save_image(&expected_path, actual);
+            return;
+        }
+
+        if !expected_path.exists() {
+            self.failures.push(format!(
+                "{}: the expected image is missing (run with EMG_BLESS=1 to record it)",
+                name
+            ));
+            return;
+        }
+
+        let expected = load_image(&file_name);
+        if expected.width != actual.width || expected.height != actual.height {
+            self.failures.push(format!(
+                "{}: size mismatch (expected {}x{}, got {}x{})",
+                name,
+                expected.width,
+                expected.height,
+                actual.width,
+                actual.height
+            ));
+            return;
+        }
+
+        let mut num_mismatches = 0;
+        let mut max_diff = 0;
+        let diff: Vec<_> = expected
+            .data
+            .iter()
+            .zip(actual.data.iter())
+            .map(|(e, a)| {
+                let d = Vector4::new(
+                    (e.x as i32 - a.x as i32).abs(),
+                    (e.y as i32 - a.y as i32).abs(),
+                    (e.z as i32 - a.z as i32).abs(),
+                    (e.w as i32 - a.w as i32).abs(),
+                );
+                let m = d.x.max(d.y).max(d.z).max(d.w);
+                max_diff = max_diff.max(m);
+                if m > tolerance as i32 {
+                    num_mismatches += 1;
+                }
+                let amp = |x: i32| (x * 16).min(255) as u8;
+                Vector4::new(amp(d.x), amp(d.y), amp(d.z), 255)
+            })
+            .collect();
+
+        if num_mismatches > 0 {
+            let actual_path = diff_dir().join(format!("{}.actual.png", name));
+            let diff_path = diff_dir().join(format!("{}.diff.png", name));
+            save_image(&actual_path, actual);
+            save_image(
+                &diff_path,
+                &Image {
+                    data: &diff[..],
+                    width: actual.width,
+                    height: actual.height,
+                },
+            );
+            self.failures.push(format!(
+                "{}: {} texel(s) differ by more than {} (max. {}); see {} and {}",
+                name,
+                num_mismatches,
+                tolerance,
+                max_diff,
+                actual_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+
+    pub fn finish(self) {
+        if !self.failures.is_empty() {
+            panic!(
+                "{} golden image(s) did not match:\n{}",
+                self.failures.len(),
+                self.failures.join("\n")
+            );
+        }
+    }
+}
@@ -0,0 +1,12 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Utilities shared by the integration tests. Not every test uses all of
+//! them.
+#![allow(dead_code)]
+
+pub mod golden;
+pub mod sim;
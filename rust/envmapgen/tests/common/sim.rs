@@ -0,0 +1,341 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Synthetic AR session simulator.
+//!
+//! Renders camera frames from a known ground-truth cube map, feeds them
+//! through `Context`, and measures how well the ground truth is
+//! reconstructed.
+use std::fmt;
+use cgmath::{Vector3, Vector4, Matrix3, Matrix4, Rad, Deg, PerspectiveFov};
+use cgmath::prelude::*;
+use rand::Rng;
+
+use arcane_gfx::Image;
+use arcane_gfx::cubemap::{CUBE_FACES, CubeFace, texel_solid_angle, clip_to_texel};
+use envmapgen::context::Context;
+use envmapgen::cubemaputils;
+
+/// A cube map storing linear RGB values in the range `[0, 1]`. The alpha
+/// channel is unused and set to one.
+#[derive(Debug, Clone)]
+pub struct CubeMap {
+    pub size: usize,
+    pub faces: Vec<Image<Vec<Vector4<f32>>>>,
+}
+
+impl CubeMap {
+    /// Constructs a cube map by evaluating `f` for the (normalized) direction
+    /// of each texel's center.
+    pub fn from_fn<F>(size: usize, mut f: F) -> Self
+    where
+        F: FnMut(Vector3<f32>) -> Vector3<f32>,
+    {
+        Self {
+            size,
+            faces: CUBE_FACES
+                .iter()
+                .map(|face| {
+                    let mut data = Vec::with_capacity(size * size);
+                    for y in 0..size {
+                        for x in 0..size {
+                            let d = face.texel_direction(x, y, size).normalize();
+                            data.push(f(d).extend(1.0));
+                        }
+                    }
+                    Image {
+                        data,
+                        width: size,
+                        height: size,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Samples the texel containing the direction `d`.
+    pub fn sample(&self, d: Vector3<f32>) -> Vector3<f32> {
+        let face = CubeFace::from_direction(d);
+        let p = face.project(d);
+        let x = clip_to_texel(p.x, self.size);
+        let y = clip_to_texel(p.y, self.size);
+        self.faces[face as usize].data[x + y * self.size].truncate()
+    }
+
+    /// Box-filters this cube map by the factor of two.
+    pub fn downsample(&self) -> Self {
+        let size = self.size / 2;
+        Self {
+            size,
+            faces: self.faces
+                .iter()
+                .map(|face| {
+                    let mut data = vec![Vector4::zero(); size * size];
+                    cubemaputils::downsample_2x(&mut data, &face.data, size);
+                    Image {
+                        data,
+                        width: size,
+                        height: size,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The ground truth used by the tests: a smooth gradient with a bright
+/// "sky" above the horizon and a darker, tinted "floor" below it.
+pub fn standard_ground_truth(size: usize) -> CubeMap {
+    CubeMap::from_fn(size, |d| {
+        let sky = Vector3::new(0.55, 0.7, 0.9) * (0.6 + 0.4 * d.y.max(0.0));
+        let floor = Vector3::new(0.35, 0.25, 0.2) * (0.8 - 0.3 * d.x);
+        let t = (d.y * 4.0).max(-1.0).min(1.0) * 0.5 + 0.5;
+        floor * (1.0 - t) + sky * t
+    })
+}
+
+/// A simulated camera located at the origin.
+#[derive(Debug, Copy, Clone)]
+pub struct SimCamera {
+    /// Rotation from the camera space to the world space. The camera looks
+    /// toward `-Z` in the camera space, and `+Y` is up.
+    pub rotation: Matrix3<f32>,
+    pub fov_y: Rad<f32>,
+    pub width: usize,
+    pub height: usize,
+
+    /// Multiplies the linear values of the rendered image, simulating
+    /// changes in the exposure and the white balance.
+    pub gain: Vector3<f32>,
+}
+
+impl SimCamera {
+    /// Constructs a camera by applying the roll, pitch, and yaw rotations
+    /// (in this order) to a camera looking toward `-Z`.
+    pub fn from_angles(yaw: Deg<f32>, pitch: Deg<f32>, roll: Deg<f32>, fov_y: Deg<f32>) -> Self {
+        Self {
+            rotation: Matrix3::from_angle_y(yaw) * Matrix3::from_angle_x(pitch) *
+                Matrix3::from_angle_z(roll),
+            fov_y: fov_y.into(),
+            width: 160,
+            height: 120,
+            gain: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// The matrix passed to `Context::stamp`.
+    pub fn view_proj_matrix(&self) -> Matrix4<f32> {
+        let proj: Matrix4<f32> = PerspectiveFov {
+            fovy: self.fov_y,
+            aspect: self.aspect(),
+            near: 0.1,
+            far: 100.0,
+        }.into();
+        proj * Matrix4::from(self.rotation.transpose())
+    }
+
+    /// Returns whether the direction `d` (in the world space) is inside the
+    /// camera's field of view.
+    pub fn contains(&self, d: Vector3<f32>) -> bool {
+        let v = self.rotation.transpose() * d;
+        if v.z >= 0.0 {
+            return false;
+        }
+        let tan_y = (self.fov_y.0 * 0.5).tan();
+        let tan_x = tan_y * self.aspect();
+        (v.x / -v.z).abs() < tan_x && (v.y / -v.z).abs() < tan_y
+    }
+
+    /// Renders the ground truth as seen from this camera. Linear values are
+    /// encoded with the gamma 2 curve assumed by `Context::stamp`.
+    pub fn render(&self, ground_truth: &CubeMap) -> Image<Vec<Vector4<u8>>> {
+        let tan_y = (self.fov_y.0 * 0.5).tan();
+        let tan_x = tan_y * self.aspect();
+        let mut data = Vec::with_capacity(self.width * self.height);
+        for py in 0..self.height {
+            let ndc_y = 1.0 - (py as f32 + 0.5) * (2.0 / self.height as f32);
+            for px in 0..self.width {
+                let ndc_x = (px as f32 + 0.5) * (2.0 / self.width as f32) - 1.0;
+                let d = self.rotation * Vector3::new(ndc_x * tan_x, ndc_y * tan_y, -1.0);
+                let c = ground_truth.sample(d).mul_element_wise(self.gain);
+                let encode = |x: f32| (x.max(0.0).min(1.0).sqrt() * 255.0).round() as u8;
+                data.push(Vector4::new(encode(c.x), encode(c.y), encode(c.z), 255));
+            }
+        }
+        Image {
+            data,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// A scripted scan: a full turn at several pitches, plus the zenith and
+/// the nadir.
+pub fn scripted_cameras() -> Vec<SimCamera> {
+    let mut cameras = Vec::new();
+    for &pitch in [-60.0, -30.0, 0.0, 30.0, 60.0].iter() {
+        for i in 0..12 {
+            cameras.push(SimCamera::from_angles(
+                Deg(i as f32 * 30.0),
+                Deg(pitch),
+                Deg(0.0),
+                Deg(60.0),
+            ));
+        }
+    }
+    cameras.push(SimCamera::from_angles(Deg(0.0), Deg(90.0), Deg(0.0), Deg(60.0)));
+    cameras.push(SimCamera::from_angles(Deg(0.0), Deg(-90.0), Deg(0.0), Deg(60.0)));
+    cameras
+}
+
+/// Generates cameras with random orientations and fields of view.
+pub fn random_cameras<R: Rng>(rng: &mut R, count: usize) -> Vec<SimCamera> {
+    (0..count)
+        .map(|_| {
+            SimCamera::from_angles(
+                Deg(rng.gen_range(0.0, 360.0)),
+                Deg(rng.gen_range(-90.0, 90.0)),
+                Deg(rng.gen_range(-20.0, 20.0)),
+                Deg(rng.gen_range(40.0, 75.0)),
+            )
+        })
+        .collect()
+}
+
+/// Reconstruction quality of a single mip level.
+#[derive(Debug, Copy, Clone)]
+pub struct LevelReport {
+    pub level: usize,
+    pub size: usize,
+    /// The fraction of the solid angle observed by at least one camera.
+    pub coverage: f32,
+    /// Solid angle-weighted RMS error (in the linear space) over all texels.
+    pub rmse: f32,
+    /// Solid angle-weighted RMS error over the observed texels.
+    pub observed_rmse: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub levels: Vec<LevelReport>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "| Level | Size | Coverage |   RMSE   | Observed RMSE |")?;
+        writeln!(f, "| ----- | ---- | -------- | -------- | ------------- |")?;
+        for l in self.levels.iter() {
+            writeln!(
+                f,
+                "| {:>5} | {:>4} | {:>7.2}% | {:>8.5} | {:>13.5} |",
+                l.level,
+                l.size,
+                l.coverage * 100.0,
+                l.rmse,
+                l.observed_rmse
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes an output texel of `Context` to the linear space.
+pub fn decode_srgb(x: u8) -> f32 {
+    let x = x as f32 / 255.0;
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Stamps the frames rendered from `cameras` onto a new `Context`,
+/// processes it, and compares the result against `ground_truth`, which must
+/// be at least as large as the context's image size.
+///
+/// The reference for each mip level is obtained by box-filtering the
+/// ground truth. The blur applied by the pyramid is intentional but counts
+/// as an error, so higher mip levels naturally show larger errors.
+pub fn simulate(ground_truth: &CubeMap, cameras: &[SimCamera]) -> (Context, Report) {
+    simulate_with_context(Context::new(), ground_truth, cameras)
+}
+
+/// `simulate` with a preconfigured `Context`.
+pub fn simulate_with_context(
+    mut context: Context,
+    ground_truth: &CubeMap,
+    cameras: &[SimCamera],
+) -> (Context, Report) {
+    for camera in cameras.iter() {
+        let frame = camera.render(ground_truth);
+        context
+            .try_stamp(&frame.as_ref(), camera.view_proj_matrix())
+            .unwrap();
+    }
+    context.process();
+
+    let mut reference = ground_truth.clone();
+    while reference.size > context.image_size() {
+        reference = reference.downsample();
+    }
+    assert_eq!(reference.size, context.image_size());
+
+    let mut levels = Vec::new();
+    for level in 0..context.num_levels() {
+        let size = context.image_size() >> level;
+        let mut total_area = 0.0;
+        let mut observed_area = 0.0;
+        let mut sq_error = 0.0;
+        let mut observed_sq_error = 0.0;
+
+        for (i, face) in CUBE_FACES.iter().enumerate() {
+            let output = context.try_output_image(level, i).unwrap();
+            for y in 0..size {
+                for x in 0..size {
+                    let area = texel_solid_angle(x, y, size);
+                    let expected = reference.faces[i].data[x + y * size];
+                    let actual = output.data[x + y * size];
+                    let e = Vector3::new(
+                        decode_srgb(actual.x) - expected.x,
+                        decode_srgb(actual.y) - expected.y,
+                        decode_srgb(actual.z) - expected.z,
+                    );
+                    let e = e.dot(e) / 3.0;
+
+                    total_area += area;
+                    sq_error += e * area;
+
+                    let d = face.texel_direction(x, y, size);
+                    if cameras.iter().any(|c| c.contains(d)) {
+                        observed_area += area;
+                        observed_sq_error += e * area;
+                    }
+                }
+            }
+        }
+
+        levels.push(LevelReport {
+            level,
+            size,
+            coverage: observed_area / total_area,
+            rmse: (sq_error / total_area).sqrt(),
+            observed_rmse: if observed_area > 0.0 {
+                (observed_sq_error / observed_area).sqrt()
+            } else {
+                0.0
+            },
+        });
+
+        reference = reference.downsample();
+    }
+
+    (context, Report { levels })
+}
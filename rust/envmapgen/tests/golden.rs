@@ -0,0 +1,309 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! Golden image regression tests. See `common/golden.rs` for how to update
+//! the expected images.
+//!
+//! Inputs (in `tests/data/golden`):
+//!
+//!  - `session.cap`: a capture file with a few camera frames.
+//!  - `cube16_*.png`: a 16x16 cube map.
+//!  - `holes16_*.png`: a 16x16 cube map with holes (texels with zero
+//!    alpha), including an entirely missing face (`-Y`).
+extern crate cgmath;
+extern crate image;
+extern crate rand;
+extern crate arcane_gfx;
+extern crate envmapgen;
+
+mod common;
+
+use std::fs::File;
+use std::io::BufReader;
+use cgmath::Vector4;
+use cgmath::prelude::*;
+
+use arcane_gfx::Image;
+use arcane_gfx::stamp::{self, BlendMode, StampOptions};
+use arcane_gfx::response::{PixelWeighting, ResponseCurve};
+use arcane_gfx::cubemap::{CUBE_FACES, CubeFace};
+use envmapgen::capture::{CaptureReader, Frame};
+use envmapgen::context::Context;
+use envmapgen::cubemaputils;
+
+use common::golden::{self, Golden};
+
+fn load_session() -> Vec<Frame> {
+    let file = File::open(golden::data_dir().join("session.cap")).unwrap();
+    CaptureReader::new(BufReader::new(file))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+}
+
+/// Converts an RGBA image to the linear, premultiplied format used by
+/// `Context` (with the gamma 2 curve assumed by `Context::stamp`).
+fn linearize(image: &Image<Vec<Vector4<u8>>>) -> Vec<Vector4<f32>> {
+    image
+        .data
+        .iter()
+        .map(|x| {
+            let x = x.cast::<f32>();
+            let a = x.w * (1.0 / 255.0);
+            Vector4::new(x.x * x.x * a, x.y * x.y * a, x.z * x.z * a, a)
+        })
+        .collect()
+}
+
+/// The inverse of `linearize`.
+fn delinearize(data: &[Vector4<f32>], size: usize) -> Image<Vec<Vector4<u8>>> {
+    Image {
+        data: data.iter()
+            .map(|x| if x.w > 0.0 {
+                let c = x.truncate() / x.w;
+                let enc = |x: f32| x.sqrt().round().min(255.0) as u8;
+                Vector4::new(enc(c.x), enc(c.y), enc(c.z), (x.w * 255.0).round().min(255.0) as u8)
+            } else {
+                Vector4::zero()
+            })
+            .collect(),
+        width: size,
+        height: size,
+    }
+}
+
+fn face_name(i: usize) -> &'static str {
+    golden::face_name(CubeFace::from_ordinal(i).unwrap())
+}
+
+/// Stamps the frames of `session.cap` onto a 64x64 cube map.
+fn stamp_session(options: &StampOptions) -> Vec<Vec<Vector4<f32>>> {
+    const SIZE: usize = 64;
+    let frames = load_session();
+
+    let mut faces = vec![vec![Vector4::<f32>::zero(); SIZE * SIZE]; 6];
+    for frame in frames.iter() {
+        for (face, data) in CUBE_FACES.iter().zip(faces.iter_mut()) {
+            stamp::try_stamp_camera_image_with_options(
+                &mut Image {
+                    data: &mut data[..],
+                    width: SIZE,
+                    height: SIZE,
+                },
+                face.info().inv_view_proj_mat,
+                &frame.image.as_ref(),
+                frame.camera_matrix,
+                options,
+            ).unwrap();
+        }
+    }
+    faces
+}
+
+/// Stamps the frames of `session.cap` with the default options. None of
+/// the frames covers a whole face, so this exercises the clipping of the
+/// scanlines against the source view frustum. The output must be
+/// bit-identical to the expected images.
+#[test]
+fn stamp() {
+    let mut golden = Golden::new();
+    for (i, data) in stamp_session(&StampOptions::default()).iter().enumerate() {
+        let image = delinearize(data, 64);
+        golden.check(&format!("stamp_{}", face_name(i)), &image.as_ref(), 0);
+    }
+    golden.finish();
+}
+
+/// Culling the tiles outside the source view frustum must not change the
+/// output of `stamp`.
+#[test]
+fn stamp_tile_sizes() {
+    let mut golden = Golden::new();
+    for &tile_size in [0, 1, 7, 64].iter() {
+        let options = StampOptions {
+            tile_size,
+            ..StampOptions::default()
+        };
+        for (i, data) in stamp_session(&options).iter().enumerate() {
+            let image = delinearize(data, 64);
+            golden.check(&format!("stamp_{}", face_name(i)), &image.as_ref(), 0);
+        }
+    }
+    golden.finish();
+}
+
+/// Merges the frames of `session.cap` with a non-default response curve and
+/// pixel weighting, as `Context` does when HDR merging is enabled. Every
+/// other frame is treated as being exposed one stop brighter.
+#[test]
+fn stamp_hdr() {
+    const SIZE: usize = 64;
+    let frames = load_session();
+    let mut golden = Golden::new();
+
+    let mut faces = vec![vec![Vector4::<f32>::zero(); SIZE * SIZE]; 6];
+    for (i, frame) in frames.iter().enumerate() {
+        let options = StampOptions {
+            scale: if i % 2 == 0 { 1.0 } else { 0.5 },
+            blend: BlendMode::Add,
+            response: ResponseCurve::srgb(),
+            weighting: PixelWeighting::Hat,
+            ..StampOptions::default()
+        };
+        for (face, data) in CUBE_FACES.iter().zip(faces.iter_mut()) {
+            stamp::try_stamp_camera_image_with_options(
+                &mut Image {
+                    data: &mut data[..],
+                    width: SIZE,
+                    height: SIZE,
+                },
+                face.info().inv_view_proj_mat,
+                &frame.image.as_ref(),
+                frame.camera_matrix,
+                &options,
+            ).unwrap();
+        }
+    }
+
+    for (i, data) in faces.iter().enumerate() {
+        let image = delinearize(data, SIZE);
+        golden.check(&format!("stamp_hdr_{}", face_name(i)), &image.as_ref(), 0);
+    }
+    golden.finish();
+}
+
+#[test]
+fn srgb_table() {
+    let mut golden = Golden::new();
+    let table = &cubemaputils::DELINEARIZE_TABLE;
+    let image = Image {
+        data: table.iter().map(|&x| Vector4::new(x, x, x, 255)).collect::<Vec<_>>(),
+        width: 256,
+        height: 256,
+    };
+    golden.check("srgb_table", &image.as_ref(), 0);
+    golden.finish();
+}
+
+#[test]
+fn downsample() {
+    let mut golden = Golden::new();
+    for (i, face) in golden::load_cube_map("cube16").iter().enumerate() {
+        let src = linearize(face);
+        let mut dst = vec![Vector4::zero(); 8 * 8];
+        cubemaputils::downsample_2x(&mut dst, &src, 8);
+        let image = delinearize(&dst, 8);
+        golden.check(&format!("downsample_{}", face_name(i)), &image.as_ref(), 1);
+    }
+    golden.finish();
+}
+
+#[test]
+fn fill_holes() {
+    let mut golden = Golden::new();
+    let faces: Vec<_> = golden::load_cube_map("holes16")
+        .iter()
+        .map(linearize)
+        .collect();
+
+    // Pull down to 1x1 and push the result back, as `Context::process` does
+    // for the levels below the blurred ones
+    let mut levels = vec![faces];
+    for size in [8, 4, 2, 1].iter().cloned() {
+        let mut next = vec![vec![Vector4::zero(); size * size]; 6];
+        cubemaputils::pull_2x(
+            next.iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+            levels.last().unwrap().iter().map(|f| &f[..]).collect::<Vec<_>>().as_slice(),
+            size,
+        );
+        levels.push(next);
+    }
+    cubemaputils::fill_hole_1x1(
+        levels[4].iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+    );
+    for i in (1..levels.len()).rev() {
+        let size = 16 >> i;
+        let (prev, cur) = levels[i - 1..i + 1].split_first_mut().unwrap();
+        cubemaputils::push_fill_hole_2x(
+            prev.iter_mut().map(|f| &mut f[..]).collect::<Vec<_>>().as_mut_slice(),
+            cur[0].iter().map(|f| &f[..]).collect::<Vec<_>>().as_slice(),
+            size,
+        );
+    }
+
+    for (level, faces) in levels.iter().enumerate() {
+        for (i, data) in faces.iter().enumerate() {
+            let image = delinearize(data, 16 >> level);
+            golden.check(
+                &format!("fill_holes_L{}_{}", level, face_name(i)),
+                &image.as_ref(),
+                1,
+            );
+        }
+    }
+    golden.finish();
+}
+
+#[test]
+fn process() {
+    let mut golden = Golden::new();
+    let mut context = Context::new();
+    for frame in load_session().iter() {
+        context
+            .try_stamp(&frame.image.as_ref(), frame.camera_matrix)
+            .unwrap();
+    }
+    context.process();
+
+    for level in 0..context.num_levels() {
+        for i in 0..6 {
+            let image = context.try_output_image(level, i).unwrap();
+            golden.check(&format!("process_L{}_{}", level, face_name(i)), &image, 2);
+        }
+    }
+    golden.finish();
+}
+
+#[test]
+fn spherical_blur() {
+    const SIZE: usize = 16;
+    const NUM_LEVELS: usize = 5;
+    let mut golden = Golden::new();
+
+    let mut buffer = Vec::new();
+    for face in golden::load_cube_map("cube16").iter() {
+        for x in face.data.iter() {
+            buffer.extend_from_slice(&[x.x, x.y, x.z, x.w]);
+        }
+    }
+    let total: usize = (0..NUM_LEVELS).map(|i| (SIZE >> i) * (SIZE >> i) * 4 * 6).sum();
+    buffer.resize(total, 0);
+
+    let status = unsafe { envmapgen::emg_spherical_blur(SIZE, buffer.as_mut_ptr()) };
+    assert_eq!(status, envmapgen::Status::Ok);
+
+    let mut offset = 0;
+    for level in 0..NUM_LEVELS {
+        let size = SIZE >> level;
+        for i in 0..6 {
+            let image = Image {
+                data: buffer[offset..offset + size * size * 4]
+                    .chunks(4)
+                    .map(|x| Vector4::new(x[0], x[1], x[2], x[3]))
+                    .collect::<Vec<_>>(),
+                width: size,
+                height: size,
+            };
+            golden.check(
+                &format!("spherical_blur_L{}_{}", level, face_name(i)),
+                &image.as_ref(),
+                2,
+            );
+            offset += size * size * 4;
+        }
+    }
+    golden.finish();
+}
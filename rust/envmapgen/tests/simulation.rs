@@ -0,0 +1,572 @@
+/*
+ * Copyright (c) 2017 ARcane Developers
+ *
+ * This file is a part of ARcane. Please read the license text that
+ * comes with the source code for use conditions.
+ */
+//! End-to-end accuracy tests using synthetic AR sessions. Run with
+//! `--nocapture` to see the per-level reports.
+extern crate cgmath;
+extern crate image;
+extern crate rand;
+extern crate arcane_gfx;
+extern crate envmapgen;
+
+mod common;
+
+use cgmath::{Vector3, Vector4};
+use cgmath::prelude::*;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use arcane_gfx::{Image, Error};
+use arcane_gfx::cubemap::CUBE_FACES;
+use arcane_gfx::lights::LightExtractionParams;
+use arcane_gfx::sg;
+use envmapgen::context::{Context, AutoGain, StampFrame, ViewSuggestionParams};
+use envmapgen::fallback::ConfidenceCurve;
+use envmapgen::prior::PriorParams;
+use common::sim;
+
+#[test]
+fn scripted_scan() {
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = sim::scripted_cameras();
+    let (_, report) = sim::simulate(&ground_truth, &cameras);
+    println!("Scripted scan:\n{}", report);
+
+    let base = &report.levels[0];
+    assert!(base.coverage > 0.99, "{:?}", base);
+    assert!(base.rmse < 0.03, "{:?}", base);
+    assert!(report.levels[1].rmse < 0.05, "{:?}", report.levels[1]);
+}
+
+#[test]
+fn random_scan() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = sim::random_cameras(&mut rng, 16);
+    let (_, report) = sim::simulate(&ground_truth, &cameras);
+    println!("Random scan:\n{}", report);
+
+    let base = &report.levels[0];
+    assert!(base.coverage > 0.0 && base.coverage <= 1.0, "{:?}", base);
+    assert!(base.observed_rmse < 0.03, "{:?}", base);
+}
+
+#[test]
+fn single_frame() {
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = [
+        sim::SimCamera::from_angles(
+            cgmath::Deg(0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(60.0),
+        ),
+    ];
+    let (_, report) = sim::simulate(&ground_truth, &cameras);
+    println!("Single frame:\n{}", report);
+
+    // A 60°x77° frustum covers roughly 1/9 of the sphere
+    let base = &report.levels[0];
+    assert!(base.coverage > 0.08 && base.coverage < 0.15, "{:?}", base);
+    assert!(base.observed_rmse < 0.03, "{:?}", base);
+}
+
+#[test]
+fn varying_exposure() {
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    let ground_truth = sim::standard_ground_truth(128);
+    let mut cameras = sim::scripted_cameras();
+    for camera in cameras[1..].iter_mut() {
+        let exposure = rng.gen_range(0.5, 1.5);
+        camera.gain = Vector3::new(
+            exposure * rng.gen_range(0.9, 1.1),
+            exposure,
+            exposure * rng.gen_range(0.9, 1.1),
+        );
+    }
+
+    let (_, uncompensated) = sim::simulate(&ground_truth, &cameras);
+    println!("Without auto gain:\n{}", uncompensated);
+
+    let mut context = Context::new();
+    context.set_auto_gain(AutoGain { exposure: true, white_balance: true });
+    let (context, report) = sim::simulate_with_context(context, &ground_truth, &cameras);
+    println!("With auto gain:\n{}", report);
+    println!("Last gain: {:?}", context.last_gain());
+
+    let base = &report.levels[0];
+    assert!(base.rmse < 0.06, "{:?}", base);
+    assert!(base.rmse < uncompensated.levels[0].rmse * 0.5, "{:?}", base);
+    assert!(context.last_gain().num_samples > 0);
+}
+
+/// Stamps each of `cameras` at the specified exposures (in stops) with
+/// HDR merging enabled, and processes the result.
+fn stamp_bracketed(
+    context: &mut Context,
+    ground_truth: &sim::CubeMap,
+    cameras: &[sim::SimCamera],
+    exposures: &[f32],
+) {
+    context.set_hdr_merge(true);
+    for camera in cameras.iter() {
+        let images: Vec<_> = exposures
+            .iter()
+            .map(|&ev| {
+                let mut camera = *camera;
+                let gain = ev.exp2();
+                camera.gain = Vector3::new(gain, gain, gain);
+                camera.render(ground_truth)
+            })
+            .collect();
+        let frames: Vec<_> = images
+            .iter()
+            .zip(exposures.iter())
+            .map(|(image, &exposure)| {
+                StampFrame { exposure, ..StampFrame::new(image.as_ref(), camera.view_proj_matrix()) }
+            })
+            .collect();
+        context.stamp_batch(&frames);
+    }
+    context.process();
+}
+
+/// Returns the relative RMS error of the base mip level of
+/// `copy_hdr_output` after `stamp_bracketed`.
+fn hdr_error(ground_truth: &sim::CubeMap, cameras: &[sim::SimCamera], exposures: &[f32]) -> f32 {
+    let mut context = Context::new();
+    stamp_bracketed(&mut context, ground_truth, cameras, exposures);
+
+    let mut output = vec![0.0; context.hdr_output_len()];
+    context.copy_hdr_output(&mut output).unwrap();
+
+    let size = context.image_size();
+    let (mut sum_sq_error, mut sum_sq) = (0.0, 0.0);
+    for (i, face) in CUBE_FACES.iter().enumerate() {
+        for y in 0..size {
+            for x in 0..size {
+                let d = face.texel_direction(x, y, size);
+                let expected = ground_truth.sample(d);
+                let texel = &output[((i * size + y) * size + x) * 4..][0..3];
+                let actual = Vector3::new(texel[0], texel[1], texel[2]);
+                sum_sq_error += (actual - expected).magnitude2();
+                sum_sq += expected.magnitude2();
+            }
+        }
+    }
+    (sum_sq_error / sum_sq).sqrt()
+}
+
+#[test]
+fn hdr_reconstruction() {
+    // Up to four times brighter than what fits in a single exposure
+    let ground_truth = sim::standard_ground_truth(128);
+    let ground_truth = sim::CubeMap::from_fn(128, |d| ground_truth.sample(d) * 4.0);
+    let cameras = sim::scripted_cameras();
+
+    let ldr_error = hdr_error(&ground_truth, &cameras, &[0.0]);
+    let hdr_error = hdr_error(&ground_truth, &cameras, &[0.0, -2.0]);
+    println!("Relative RMSE: {} (single exposure), {} (bracketed)", ldr_error, hdr_error);
+
+    assert!(hdr_error < 0.02, "{}", hdr_error);
+    assert!(hdr_error < ldr_error * 0.2, "{} vs {}", hdr_error, ldr_error);
+}
+
+#[test]
+fn light_extraction() {
+    let sun_direction = Vector3::new(0.4, 0.8, -0.3).normalize();
+    let sun_radius: f32 = 0.1;
+    let ground_truth = sim::standard_ground_truth(128);
+    let ground_truth = sim::CubeMap::from_fn(128, |d| if d.dot(sun_direction) > sun_radius.cos() {
+        Vector3::new(20.0, 19.0, 17.0)
+    } else {
+        ground_truth.sample(d)
+    });
+    let cameras = sim::scripted_cameras();
+
+    let mut context = Context::new();
+    context.set_light_extraction(Some(LightExtractionParams {
+        max_lights: 2,
+        remove: true,
+        ..LightExtractionParams::default()
+    }));
+    stamp_bracketed(&mut context, &ground_truth, &cameras, &[0.0, -5.0]);
+    println!("{:?}", context.lights());
+
+    // The sun is the only light source
+    assert_eq!(context.lights().len(), 1);
+    let sun = context.lights()[0];
+    assert!(sun.direction.dot(sun_direction) > 0.05f32.cos(), "{:?}", sun);
+
+    // ≈ 20 × 2π(1 - cos(0.1)). The brighter part of the sky around the sun
+    // is attributed to it as well
+    let expected_irradiance = 0.6;
+    assert!((sun.color.y - expected_irradiance).abs() < 0.15, "{:?}", sun);
+
+    // The sun was removed from the output
+    let mut output = vec![0.0; context.hdr_output_len()];
+    context.copy_hdr_output(&mut output).unwrap();
+    assert!(output.iter().all(|&x| x < 2.0));
+}
+
+#[test]
+fn spherical_gaussians() {
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = sim::scripted_cameras();
+
+    let mut context = Context::new();
+    context.set_num_sg_lobes(16);
+    stamp_bracketed(&mut context, &ground_truth, &cameras, &[0.0]);
+    assert_eq!(context.spherical_gaussians().len(), 16);
+
+    let mut flat = vec![0.0; context.sg_len()];
+    context.copy_spherical_gaussians(&mut flat).unwrap();
+    assert_eq!(flat.len(), 16 * 7);
+    assert_eq!(flat[3], context.spherical_gaussians()[0].sharpness);
+
+    // Reconstruct the environment from the lobes
+    let lobes = context.spherical_gaussians();
+    let size = 16;
+    let (mut sum_sq_error, mut sum_sq) = (0.0, 0.0);
+    for face in CUBE_FACES.iter() {
+        for y in 0..size {
+            for x in 0..size {
+                let d = face.texel_direction(x, y, size).normalize();
+                let expected = ground_truth.sample(d);
+                let actual = sg::evaluate_sum(lobes, d);
+                sum_sq_error += (actual - expected).magnitude2();
+                sum_sq += expected.magnitude2();
+            }
+        }
+    }
+    let error = (sum_sq_error / sum_sq).sqrt();
+    println!("Relative RMSE of 16 lobes: {}", error);
+    assert!(error < 0.25, "{}", error);
+}
+
+#[test]
+fn coverage() {
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = [
+        sim::SimCamera::from_angles(
+            cgmath::Deg(0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(0.0),
+            cgmath::Deg(60.0),
+        ),
+    ];
+    let (context, report) = sim::simulate(&ground_truth, &cameras);
+
+    // Agrees with the coverage measured by the simulator
+    let coverage = context.coverage();
+    println!("{:?}", coverage);
+    assert!((coverage.total - report.levels[0].coverage).abs() < 0.01, "{:?}", coverage);
+
+    // The camera is looking at `-Z`
+    assert!(coverage.faces[5] > 0.5, "{:?}", coverage);
+    assert_eq!(coverage.faces[4], 0.0);
+
+    // Each level of the coverage map preserves the total coverage
+    for level in 0..7 {
+        let size = context.image_size() >> level;
+        let mut map = vec![0.0; context.coverage_map_len(level).unwrap()];
+        assert_eq!(map.len(), size * size * 6 * 2);
+        context.copy_coverage_map(level, &mut map).unwrap();
+
+        let mean = map.chunks(2).map(|x| x[0]).sum::<f32>() / (size * size * 6) as f32;
+        assert!((mean - coverage.total).abs() < 0.05, "level {}: {}", level, mean);
+        assert!(map.chunks(2).all(|x| x[1] <= x[0] && x[1] >= 0.0 && x[0] <= 1.0));
+    }
+    assert!(context.coverage_map_len(7).is_err());
+
+    // A full scan covers everything with full confidence
+    let (context, _) = sim::simulate(&ground_truth, &sim::scripted_cameras());
+    assert!(context.coverage().total > 0.99);
+    let mut map = vec![0.0; context.coverage_map_len(6).unwrap()];
+    context.copy_coverage_map(6, &mut map).unwrap();
+    assert!(map.iter().all(|&x| x > 0.95), "{:?}", map);
+}
+
+/// Constructs a camera looking toward the direction `d`.
+fn camera_toward(d: Vector3<f32>) -> sim::SimCamera {
+    sim::SimCamera::from_angles(
+        cgmath::Rad((-d.x).atan2(-d.z)).into(),
+        cgmath::Rad(d.y.max(-1.0).min(1.0).asin()).into(),
+        cgmath::Deg(0.0),
+        cgmath::Deg(60.0),
+    )
+}
+
+#[test]
+fn view_suggestion() {
+    let ground_truth = sim::standard_ground_truth(128);
+    let forward = Vector3::new(0.0, 0.0, -1.0);
+    let mut cameras = vec![camera_toward(forward)];
+    let (context, _) = sim::simulate(&ground_truth, &cameras);
+
+    // Points away from the observed region
+    let params = ViewSuggestionParams::default();
+    let suggestion = context.suggest_view_direction(&params).unwrap();
+    println!("{:?}", suggestion);
+    assert!((suggestion.direction.magnitude() - 1.0).abs() < 1.0e-4);
+    assert!(suggestion.direction.dot(forward) < 0.5, "{:?}", suggestion);
+    assert!(suggestion.cone_angle.0 > 0.0 && suggestion.cone_angle.0 < 0.8, "{:?}", suggestion);
+    assert!(suggestion.solid_angle > 0.5, "{:?}", suggestion);
+
+    // Constraints
+    let suggestion = context
+        .suggest_view_direction(&ViewSuggestionParams {
+            upper_hemisphere_only: true,
+            ..params
+        })
+        .unwrap();
+    assert!(suggestion.direction.y >= 0.0, "{:?}", suggestion);
+
+    let max_angle = cgmath::Rad(1.2);
+    let suggestion = context
+        .suggest_view_direction(&ViewSuggestionParams {
+            reachable_from: Some((forward, max_angle)),
+            ..params
+        })
+        .unwrap();
+    assert!(suggestion.direction.dot(forward) >= max_angle.0.cos() - 1.0e-4, "{:?}", suggestion);
+
+    // Following the suggestions covers the environment quickly
+    let mut context = context;
+    let mut last_coverage = context.coverage().total;
+    for _ in 0..12 {
+        let suggestion = match context.suggest_view_direction(&params) {
+            Some(x) => x,
+            None => break,
+        };
+        cameras.push(camera_toward(suggestion.direction));
+        let result = sim::simulate(&ground_truth, &cameras);
+        context = result.0;
+        let coverage = context.coverage().total;
+        assert!(coverage > last_coverage, "{} → {}", last_coverage, coverage);
+        last_coverage = coverage;
+    }
+    println!("Coverage after {} frames: {}", cameras.len(), last_coverage);
+    assert!(last_coverage > 0.9, "{}", last_coverage);
+
+    // (Almost) nothing left to scan
+    let (context, _) = sim::simulate(&ground_truth, &sim::scripted_cameras());
+    if let Some(suggestion) = context.suggest_view_direction(&params) {
+        assert!(suggestion.solid_angle < 0.05, "{:?}", suggestion);
+    }
+}
+
+#[test]
+fn hole_filling() {
+    // Only a small part of the environment is observed
+    let ground_truth = sim::standard_ground_truth(128);
+    let cameras = [camera_toward(Vector3::new(0.0, 0.0, -1.0))];
+    let (context, _) = sim::simulate(&ground_truth, &cameras);
+
+    let mut output = vec![0.0; context.hdr_output_len()];
+    context.copy_hdr_output(&mut output).unwrap();
+
+    // Every level is filled, including the ones below the blurred levels
+    assert!(output.iter().all(|&x| x > 0.0 && x.is_finite()));
+
+    // Load the base level
+    let size = context.image_size();
+    let filled = sim::CubeMap {
+        size,
+        faces: (0..6)
+            .map(|i| {
+                let data = output[i * size * size * 4..(i + 1) * size * size * 4]
+                    .chunks(4)
+                    .map(|x| cgmath::Vector4::new(x[0], x[1], x[2], x[3]))
+                    .collect();
+                arcane_gfx::Image { data, width: size, height: size }
+            })
+            .collect(),
+    };
+
+    // Differences between adjacent texels across the face boundaries are
+    // comparable to those inside the faces
+    let (mut inner_diff, mut num_inner) = (0.0, 0);
+    let (mut edge_diff, mut num_edge) = (0.0, 0);
+    for face in CUBE_FACES.iter() {
+        for y in 0..size {
+            for x in 0..size {
+                let d = face.texel_direction(x, y, size);
+                let clip = |i: usize| (i as f32 + 0.5) * (2.0 / size as f32) - 1.0;
+                for &(nx, ny) in [(x + 1, y), (x, y + 1)].iter() {
+                    let neighbor = face.unproject(cgmath::Vector2::new(clip(nx), clip(ny)));
+                    let diff = (filled.sample(d) - filled.sample(neighbor)).magnitude();
+                    if nx < size && ny < size {
+                        inner_diff += diff;
+                        num_inner += 1;
+                    } else {
+                        edge_diff += diff;
+                        num_edge += 1;
+                    }
+                }
+            }
+        }
+    }
+    let inner_diff = inner_diff / num_inner as f32;
+    let edge_diff = edge_diff / num_edge as f32;
+    println!("Mean difference: {} (inside faces), {} (across faces)", inner_diff, edge_diff);
+    assert!(edge_diff < inner_diff * 3.0, "{} vs {}", edge_diff, inner_diff);
+}
+
+/// Returns the RMS error of the base level of `copy_hdr_output` over the
+/// texels that were not observed and satisfy `filter`.
+fn unobserved_error<F: Fn(Vector3<f32>) -> bool>(
+    context: &Context,
+    ground_truth: &sim::CubeMap,
+    filter: F,
+) -> f32 {
+    let size = context.image_size();
+    let mut output = vec![0.0; context.hdr_output_len()];
+    context.copy_hdr_output(&mut output).unwrap();
+    let mut coverage = vec![0.0; context.coverage_map_len(0).unwrap()];
+    context.copy_coverage_map(0, &mut coverage).unwrap();
+
+    let (mut sum_sq_error, mut count) = (0.0, 0);
+    for (i, face) in CUBE_FACES.iter().enumerate() {
+        for y in 0..size {
+            for x in 0..size {
+                let index = (i * size + y) * size + x;
+                let d = face.texel_direction(x, y, size).normalize();
+                if coverage[index * 2] > 0.0 || !filter(d) {
+                    continue;
+                }
+                let texel = &output[index * 4..][0..3];
+                let actual = Vector3::new(texel[0], texel[1], texel[2]);
+                sum_sq_error += (actual - ground_truth.sample(d)).magnitude2();
+                count += 1;
+            }
+        }
+    }
+    assert!(count > 0);
+    (sum_sq_error / count as f32).sqrt()
+}
+
+#[test]
+fn priors() {
+    let ground_truth = sim::standard_ground_truth(128);
+
+    // Look at the floor, the ceiling, and forward, leaving the sides and the
+    // back unobserved
+    let cameras = [
+        camera_toward(Vector3::new(0.0, -1.0, 0.0)),
+        camera_toward(Vector3::new(0.0, 1.0, 0.0)),
+        camera_toward(Vector3::new(0.0, 0.0, -1.0)),
+    ];
+    let (without_prior, base_report) = sim::simulate(&ground_truth, &cameras);
+
+    let mut context = Context::new();
+    context.set_prior(Some(PriorParams::default()));
+    let (with_prior, report) = sim::simulate_with_context(context, &ground_truth, &cameras);
+
+    let prior = with_prior.prior().unwrap();
+    println!("{:?}", prior);
+    assert!(prior.floor.is_some());
+    assert!(prior.sky_bands.iter().all(Option::is_some));
+
+    let below = |d: Vector3<f32>| d.y < -0.3;
+    let above = |d: Vector3<f32>| d.y > 0.3;
+    let below_errors = (
+        unobserved_error(&without_prior, &ground_truth, below),
+        unobserved_error(&with_prior, &ground_truth, below),
+    );
+    let above_errors = (
+        unobserved_error(&without_prior, &ground_truth, above),
+        unobserved_error(&with_prior, &ground_truth, above),
+    );
+    println!("RMSE below the horizon: {:?}", below_errors);
+    println!("RMSE above the horizon: {:?}", above_errors);
+
+    assert!(below_errors.1 < below_errors.0 * 0.5, "{:?}", below_errors);
+    assert!(above_errors.1 < above_errors.0 * 0.5, "{:?}", above_errors);
+
+    // The observed texels are not affected
+    let (base, level) = (&base_report.levels[0], &report.levels[0]);
+    assert!(level.observed_rmse < base.observed_rmse + 0.005, "{:?} vs {:?}", level, base);
+}
+
+fn cube_map_images(cube_map: &sim::CubeMap) -> Vec<Image<&[Vector4<f32>]>> {
+    cube_map.faces.iter().map(|face| face.as_ref()).collect()
+}
+
+#[test]
+fn fallback() {
+    let ground_truth = sim::standard_ground_truth(128);
+
+    // Before scanning anything, the output is the fallback itself (modulo
+    // the blur applied by the pyramid)
+    let mut context = Context::new();
+    context.set_fallback(&cube_map_images(&ground_truth)).unwrap();
+    let (_, report) = sim::simulate_with_context(context, &ground_truth, &[]);
+    println!("{}", report);
+    for level in report.levels.iter() {
+        assert!(level.rmse < 0.05, "{:?}", level);
+    }
+
+    // After a partial scan, the unobserved regions show the fallback while
+    // the observed ones are unaffected
+    let grey = sim::CubeMap::from_fn(16, |_| Vector3::new(0.3, 0.3, 0.3));
+    let cameras = [
+        camera_toward(Vector3::new(0.0, -1.0, 0.0)),
+        camera_toward(Vector3::new(0.0, 0.0, -1.0)),
+    ];
+    let (_, base_report) = sim::simulate(&ground_truth, &cameras);
+
+    let mut context = Context::new();
+    context.set_fallback(&cube_map_images(&grey)).unwrap();
+    let (with_fallback, report) = sim::simulate_with_context(context, &ground_truth, &cameras);
+    println!("{}", report);
+
+    let (base, level) = (&base_report.levels[0], &report.levels[0]);
+    assert!(level.observed_rmse < base.observed_rmse + 0.005, "{:?} vs {:?}", level, base);
+
+    let behind = |d: Vector3<f32>| d.z > 0.5;
+    let error = unobserved_error(&with_fallback, &grey, behind);
+    assert!(error < 0.02, "{}", error);
+
+    // A stricter confidence curve keeps more of the fallback in the observed
+    // texels
+    let mut context = Context::new();
+    context.set_fallback(&cube_map_images(&grey)).unwrap();
+    context
+        .set_confidence_curve(ConfidenceCurve {
+            full_weight: 4.0,
+            exponent: 1.0,
+        })
+        .unwrap();
+    let (_, strict_report) = sim::simulate_with_context(context, &ground_truth, &cameras);
+    assert!(strict_report.levels[0].observed_rmse > level.observed_rmse + 0.01);
+}
+
+#[test]
+fn fallback_size() {
+    let mut context = Context::new();
+    for &(size, ok) in [(1, true), (16, true), (256, true), (48, false), (0, false)].iter() {
+        let cube_map = sim::CubeMap::from_fn(size, |_| Vector3::new(0.5, 0.5, 0.5));
+        assert_eq!(context.set_fallback(&cube_map_images(&cube_map)).is_ok(), ok, "{}", size);
+    }
+    context.clear_fallback();
+    assert!(context.fallback().is_none());
+}
+
+#[test]
+fn confidence_curve() {
+    let mut context = Context::new();
+    let nan = ::std::f32::NAN;
+    let inf = ::std::f32::INFINITY;
+    for &(full_weight, exponent) in [(0.0, 1.0), (-1.0, 1.0), (nan, 1.0), (1.0, 0.0), (1.0, inf)]
+        .iter()
+    {
+        let curve = ConfidenceCurve {
+            full_weight,
+            exponent,
+        };
+        assert_eq!(context.set_confidence_curve(curve), Err(Error::InvalidConfidenceCurve));
+    }
+    assert_eq!(*context.confidence_curve(), ConfidenceCurve::default());
+}
@@ -26,11 +26,77 @@ export interface EnvironmentEstimatorInput
         image: ArrayBuffer;
         width: number;
         height: number;
-        /** The projection view matrix associated with the camera image. */
-        matrix: number[];
+        /**
+         * The projection view matrix associated with the camera image.
+         * Either this or `pose` must be specified.
+         */
+        matrix?: number[];
+        /**
+         * The camera pose, which can be used in place of `matrix` when only
+         * the device orientation is known.
+         */
+        pose?: EnvironmentEstimatorCameraPose;
     };
     resultBuffer?: ArrayBuffer;
     profilePerformance: boolean;
+    /**
+     * If specified, the per-texel coverage and confidence of this mip level
+     * are returned as `EnvironmentEstimatorOutput.coverageMap`.
+     */
+    coverageMapLevel?: number;
+    /**
+     * The priors used to fill the directions the user has not scanned yet,
+     * or `null` to leave them to the hole filling.
+     */
+    prior: EnvironmentEstimatorPriorOptions | null;
+    /**
+     * Compensates for the changes of the camera's exposure and white balance
+     * by matching each camera image to the previously scanned ones.
+     */
+    autoGain: boolean;
+    /**
+     * Records the camera images into a capture file while set. The capture
+     * file is returned as `EnvironmentEstimatorOutput.recording` once this is
+     * cleared (or the camera image size changes).
+     */
+    record: boolean;
+}
+
+/**
+ * The typical height of a handheld device above the floor, in meters (the
+ * unit of the camera poses reported by the AR frameworks).
+ */
+export const DEFAULT_GROUND_HEIGHT = 1.5;
+
+export interface EnvironmentEstimatorPriorOptions
+{
+    /**
+     * Fills the directions below the horizon with a floor color estimated
+     * from the scanned part of the floor.
+     */
+    ground: boolean;
+    /**
+     * The distance from the camera down to the floor, e.g.,
+     * `DEFAULT_GROUND_HEIGHT`.
+     */
+    groundHeight: number;
+    /**
+     * Fills the directions above the horizon with a sky (or ceiling)
+     * gradient estimated from the scanned ones.
+     */
+    sky: boolean;
+}
+
+export interface EnvironmentEstimatorCameraPose
+{
+    /**
+     * The camera orientation as a unit quaternion `[x, y, z, w]`, which
+     * rotates a vector from the camera's local space (`-Z` forward, `+Y` up)
+     * into the environment's space.
+     */
+    orientation: number[];
+    /** The vertical field of view in radians. */
+    fovY: number;
 }
 
 export interface EnvironmentEstimatorOutput
@@ -38,6 +104,36 @@ export interface EnvironmentEstimatorOutput
     cameraBuffer: ArrayBuffer;
     result: ArrayBuffer;
     performanceProfilingResult: string;
+    /** The observed fraction of the environment. */
+    coverage: number;
+    /** The observed fraction of each cube face. */
+    faceCoverage: number[];
+    /**
+     * Pairs of the coverage and confidence of each texel of the mip level
+     * specified by `EnvironmentEstimatorInput.coverageMapLevel`. See
+     * `Context::copy_coverage_map` for the layout.
+     */
+    coverageMap?: Float32Array;
+    /**
+     * The view direction that would capture the most unobserved part of the
+     * environment, or `null` if the whole environment has been observed.
+     */
+    viewSuggestion: EnvironmentEstimatorViewSuggestion | null;
+    /**
+     * A capture file recorded while `EnvironmentEstimatorInput.record` was
+     * set, which can be replayed by `envmapgen`'s `replay` example.
+     */
+    recording?: ArrayBuffer;
+}
+
+export interface EnvironmentEstimatorViewSuggestion
+{
+    /** The normalized direction in the environment's space. */
+    direction: number[];
+    /** The angular radius (in radians) of the unobserved region to capture. */
+    coneAngle: number;
+    /** The unobserved solid angle (in steradians) that would be captured. */
+    solidAngle: number;
 }
 
 export const enum EnvironmentEstimatorConstants
@@ -10,7 +10,7 @@ import { mat4 } from 'gl-matrix';
 import { Host, Channel } from '../../utils/workertransport';
 import {
     EnvironmentEstimatorParam, EnvironmentEstimatorInput, EnvironmentEstimatorOutput, EnvironmentEstimatorConstants,
-    BlurInputOutput,
+    EnvironmentEstimatorViewSuggestion, EnvironmentEstimatorPriorOptions, BlurInputOutput,
 } from './envestimator';
 
 import { GLContext } from '../globjs/context';
@@ -42,6 +42,42 @@ export class EnvironmentEstimatorClient
 
     onPerformanceProfile: ((text: string) => void) | null = null;
 
+    /**
+     * Called with the scan progress each time the environment map is
+     * updated. `coverageMap` is only provided if `coverageMapLevel` is set.
+     */
+    onCoverage: ((coverage: number, faceCoverage: number[], coverageMap?: Float32Array) => void) | null = null;
+
+    /** The mip level of the coverage map passed to `onCoverage`. */
+    coverageMapLevel: number | null = null;
+
+    /**
+     * Called with the direction the user should look toward next to scan the
+     * unobserved part of the environment, or `null` if there is none.
+     */
+    onViewSuggestion: ((suggestion: EnvironmentEstimatorViewSuggestion | null) => void) | null = null;
+
+    /**
+     * The priors used to fill the directions the user has not scanned yet.
+     * Disabled (`null`) by default.
+     */
+    prior: EnvironmentEstimatorPriorOptions | null = null;
+
+    /**
+     * Enables the compensation of the camera's automatic exposure and white
+     * balance. Disabled by default.
+     */
+    autoGain = false;
+
+    /**
+     * Records the camera images into a capture file while set. Disabled by
+     * default.
+     */
+    record = false;
+
+    /** Called with a capture file recorded while `record` was set. */
+    onRecording: ((recording: ArrayBuffer) => void) | null = null;
+
     constructor(private context: EnvironmentEstimatorContext)
     {
         this.input = context.host.open();
@@ -155,6 +191,11 @@ export class EnvironmentEstimatorClient
             },
             resultBuffer: void 0,
             profilePerformance: !!this.onPerformanceProfile,
+            coverageMapLevel: this.onCoverage && this.coverageMapLevel != null ?
+                this.coverageMapLevel : void 0,
+            prior: this.prior,
+            autoGain: this.autoGain,
+            record: this.record,
         };
         const transferList: any[] = [cameraImageBuffer];
 
@@ -175,6 +216,15 @@ export class EnvironmentEstimatorClient
         if (this.onPerformanceProfile) {
             this.onPerformanceProfile(data.performanceProfilingResult);
         }
+        if (this.onCoverage) {
+            this.onCoverage(data.coverage, data.faceCoverage, data.coverageMap);
+        }
+        if (this.onViewSuggestion) {
+            this.onViewSuggestion(data.viewSuggestion);
+        }
+        if (data.recording && this.onRecording) {
+            this.onRecording(data.recording);
+        }
     }
 
     updateStaticImage(images: CameraImageData[]): Promise<void>
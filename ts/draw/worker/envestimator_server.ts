@@ -16,18 +16,43 @@ import { table, assertEq } from '../../utils/utils';
 import { Host, Channel } from '../../utils/workertransport';
 import {
     EnvironmentEstimatorParam, EnvironmentEstimatorInput, EnvironmentEstimatorOutput, EnvironmentEstimatorConstants,
-    BlurInputOutput,
+    EnvironmentEstimatorViewSuggestion, EnvironmentEstimatorPriorOptions, BlurInputOutput,
 } from './envestimator';
 
 const LOG_SIZE = EnvironmentEstimatorConstants.LOG_SIZE;
 const SIZE = EnvironmentEstimatorConstants.SIZE;
 
+/** The half angle of the cone approximating a typical camera's field of view. */
+const VIEW_SUGGESTION_HALF_FOV = 0.5;
+
+/** The factor the camera images are downscaled by when recorded. */
+const RECORDING_DOWNSCALE = 2;
+
 type EnvmapgenContext = Ptr;
 
+const enum EnvmapgenStatus
+{
+    Ok = 0,
+    DimensionMismatch = 1,
+    InvalidMipLevel = 2,
+    InvalidCubeFace = 3,
+    AllocationFailed = 4,
+    NonFiniteMatrix = 5,
+    InvalidCamera = 6,
+    SingularMatrix = 7,
+    InvalidFrameParams = 8,
+    InvalidResponseCurve = 9,
+    InvalidConfidenceCurve = 10,
+    InvalidArgument = 11,
+}
+
 interface EnvmapgenExports
 {
     memory: WebAssembly.Memory;
 
+    emg_last_error_len(): number;
+    emg_last_error_copy(buffer: Ptr, len: number): number;
+
     emg_malloc(size: number): Ptr;
     emg_free(ptr: Ptr): void;
 
@@ -39,25 +64,116 @@ interface EnvmapgenExports
         width: number,
         height: number,
         camera_matrix: Ptr,
-    ): void;
+    ): EnvmapgenStatus;
+    emg_context_stamp_camera(
+        self: EnvmapgenContext,
+        image: Ptr,
+        width: number,
+        height: number,
+        camera: Ptr,
+    ): EnvmapgenStatus;
+    emg_context_stamp_batch(
+        self: EnvmapgenContext,
+        frames: Ptr,
+        num_frames: number,
+    ): EnvmapgenStatus;
+    emg_context_set_auto_gain(self: EnvmapgenContext, exposure: boolean, white_balance: boolean): void;
+    emg_context_set_prior(self: EnvmapgenContext, ground: boolean, ground_height: number, sky: boolean): void;
+    emg_context_get_last_gain(self: EnvmapgenContext, out_gain: Ptr): number;
     emg_context_get_image_size(self: EnvmapgenContext): number;
-    emg_context_process(self: EnvmapgenContext): void;
+    emg_context_get_coverage(self: EnvmapgenContext, out_faces: Ptr): number;
+    emg_context_suggest_view_direction(
+        self: EnvmapgenContext,
+        half_fov: number,
+        upper_hemisphere_only: boolean,
+        reference_direction: Ptr,
+        max_angle: number,
+        out_suggestion: Ptr,
+    ): number;
+    emg_context_coverage_map_len(self: EnvmapgenContext, mip_level: number): number;
+    emg_context_copy_coverage_map(
+        self: EnvmapgenContext,
+        mip_level: number,
+        buffer: Ptr,
+        len: number,
+    ): EnvmapgenStatus;
+    emg_context_process(self: EnvmapgenContext): EnvmapgenStatus;
     emg_context_get_output_image_data(
         self: EnvmapgenContext,
         mip_level: number,
         cube_face: number,
-    ): Ptr;
+        out_data: Ptr,
+    ): EnvmapgenStatus;
+
+    emg_context_output_size(self: EnvmapgenContext): number;
+    emg_context_copy_output(self: EnvmapgenContext, buffer: Ptr, len: number): EnvmapgenStatus;
+    emg_context_copy_changed_output(
+        self: EnvmapgenContext,
+        buffer: Ptr,
+        len: number,
+        out_levels: Ptr,
+    ): EnvmapgenStatus;
+
+    emg_context_start_recording(self: EnvmapgenContext, intrinsics: Ptr, downscale: number): EnvmapgenStatus;
+    emg_context_recording_len(self: EnvmapgenContext): number;
+    emg_context_copy_recording(self: EnvmapgenContext, buffer: Ptr, len: number): EnvmapgenStatus;
+    emg_context_stop_recording(self: EnvmapgenContext): void;
+
+    emg_context_profile_len(self: EnvmapgenContext): number;
+    emg_context_copy_profile(
+        self: EnvmapgenContext,
+        buffer: Ptr,
+        len: number,
+        reset: boolean,
+    ): EnvmapgenStatus;
+
+    emg_spherical_blur(size: number, images: Ptr): EnvmapgenStatus;
+}
+
+/**
+ * Throws an exception describing the last error if `status` indicates a
+ * failure.
+ */
+function checkStatus(emg: EnvmapgenExports, status: EnvmapgenStatus): void
+{
+    if (status === EnvmapgenStatus.Ok) {
+        return;
+    }
+
+    const len = emg.emg_last_error_len();
+    const buffer = emg.emg_malloc(len);
+    let message = `envmapgen error ${status}`;
+    if (buffer) {
+        const copied = emg.emg_last_error_copy(buffer, len);
+        const bytes = new Uint8Array(emg.memory.buffer, buffer, copied);
+        message += ': ';
+        for (let i = 0; i < bytes.length; ++i) {
+            message += String.fromCharCode(bytes[i]);
+        }
+        emg.emg_free(buffer);
+    }
+    throw new Error(message);
+}
 
-    emg_spherical_blur(size: number, images: Ptr): void;
+function mallocChecked(emg: EnvmapgenExports, size: number): Ptr
+{
+    const ptr = emg.emg_malloc(size);
+    if (!ptr) {
+        checkStatus(emg, EnvmapgenStatus.AllocationFailed);
+    }
+    return ptr;
 }
 
-const RESULT_BUFFER_SIZE = (() => {
-    let total = 0;
-    for (let i = SIZE; i; i >>= 1) {
-        total += i * i * 4 * 6;
+function priorOptionsEqual(
+    a: EnvironmentEstimatorPriorOptions | null,
+    b: EnvironmentEstimatorPriorOptions | null,
+): boolean
+{
+    if (!a || !b) {
+        return a === b;
     }
-    return total;
-})();
+    return a.ground === b.ground && a.groundHeight === b.groundHeight && a.sky === b.sky;
+}
 
 class EnvironmentEstimator
 {
@@ -68,10 +184,28 @@ class EnvironmentEstimator
         instance: WebAssembly.Instance;
         context: EnvmapgenContext;
         matrixBuffer: Ptr;
+        outLevelsBuffer: Ptr;
+        profileBuffer: {
+            ptr: Ptr;
+            len: number;
+        };
+        resultBuffer: {
+            ptr: Ptr;
+            size: number;
+        };
         cameraImageBuffer: null | {
             ptr: Ptr;
             size: number;
         };
+        /** The priors last passed to `emg_context_set_prior`. */
+        prior: EnvironmentEstimatorPriorOptions | null;
+        /** The value last passed to `emg_context_set_auto_gain`. */
+        autoGain: boolean;
+        /** The camera image size of the capture being recorded, if any. */
+        recording: null | {
+            width: number;
+            height: number;
+        };
     }>;
 
     constructor(param: EnvironmentEstimatorParam, private host: Host)
@@ -86,20 +220,44 @@ class EnvironmentEstimator
 
         this.envmapgen = (async () => {
             const helper = new WasmHelper();
-            const compiled = await envmapgenModule(helper.augumentImportObject());
+            const compiled = await envmapgenModule(helper.augumentImportObject({
+                env: {
+                    emg_clock_now: () => performance.now(),
+                },
+            }));
             helper.link(compiled.instance.exports);
 
             const emg: EnvmapgenExports = compiled.instance.exports;
             const context = emg.emg_context_new();
-            const matrixBuffer = emg.emg_malloc(64);
+            const matrixBuffer = mallocChecked(emg, 64);
+            const outLevelsBuffer = mallocChecked(emg, 4);
 
             assertEq(emg.emg_context_get_image_size(context), SIZE);
 
+            const profileLen = emg.emg_context_profile_len(context);
+            // `emg_malloc` only guarantees 4-byte alignment
+            const profileBuffer = {
+                ptr: (mallocChecked(emg, profileLen * 8 + 4) + 7) & ~7,
+                len: profileLen,
+            };
+
+            const resultSize = emg.emg_context_output_size(context);
+            const resultBuffer = {
+                ptr: mallocChecked(emg, resultSize),
+                size: resultSize,
+            };
+
             return {
                 instance: compiled.instance,
                 context,
                 matrixBuffer,
+                outLevelsBuffer,
+                profileBuffer,
+                resultBuffer,
                 cameraImageBuffer: null,
+                prior: null,
+                autoGain: false,
+                recording: null,
             };
         })();
     }
@@ -109,14 +267,17 @@ class EnvironmentEstimator
     {
         const emg = await this.envmapgen;
         const emgExports: EnvmapgenExports = emg.instance.exports;
-        const bufferPtr = emgExports.emg_malloc(data.image.length);
+        const bufferPtr = mallocChecked(emgExports, data.image.length);
 
         new Uint8Array(emgExports.memory.buffer, bufferPtr, data.image.length)
             .set(data.image);
 
-        emgExports.emg_spherical_blur(data.size, bufferPtr);
-
-        data.image.set(new Uint8Array(emgExports.memory.buffer, bufferPtr, data.image.length));
+        try {
+            checkStatus(emgExports, emgExports.emg_spherical_blur(data.size, bufferPtr));
+            data.image.set(new Uint8Array(emgExports.memory.buffer, bufferPtr, data.image.length));
+        } finally {
+            emgExports.emg_free(bufferPtr);
+        }
 
         // Send back the result
         this.host.getUnwrap(data.channel).postMessage(data, [data.image.buffer]);
@@ -127,11 +288,62 @@ class EnvironmentEstimator
     {
         const emg = await this.envmapgen;
         const emgExports: EnvmapgenExports = emg.instance.exports;
-        let t1, t2, t3, t4;
+        let t1, t2;
+
+        // The priors are disabled by default (`emg.prior === null`)
+        if (!priorOptionsEqual(data.prior, emg.prior)) {
+            const {prior} = data;
+            emgExports.emg_context_set_prior(emg.context,
+                !!prior && prior.ground, prior ? prior.groundHeight : 0, !!prior && prior.sky);
+            emg.prior = prior;
+        }
+
+        // Compensate for the camera's automatic exposure and white balance
+        // if requested. Disabled by default (`emg.autoGain === false`)
+        if (data.autoGain !== emg.autoGain) {
+            emgExports.emg_context_set_auto_gain(emg.context, data.autoGain, data.autoGain);
+            emg.autoGain = data.autoGain;
+        }
 
-        // Stamp the latest camere image onto the base cube map layer
-        // (We do nothing fancy (no exposure estimation nor highlight
-        // restoration) - we just stamp it)
+        // Start or stop recording. A capture file only holds images of the
+        // camera image size it was started with, so a new one is started
+        // if the size changes
+        let recording: ArrayBuffer | undefined;
+        {
+            const {width, height, pose} = data.camera;
+            if (emg.recording && (!data.record ||
+                emg.recording.width !== width || emg.recording.height !== height)) {
+                const len = emgExports.emg_context_recording_len(emg.context);
+                const ptr = mallocChecked(emgExports, len);
+                try {
+                    checkStatus(emgExports, emgExports.emg_context_copy_recording(emg.context, ptr, len));
+                    recording = new ArrayBuffer(len);
+                    new Uint8Array(recording).set(new Uint8Array(emgExports.memory.buffer, ptr, len));
+                } finally {
+                    emgExports.emg_free(ptr);
+                }
+                emgExports.emg_context_stop_recording(emg.context);
+                emg.recording = null;
+            }
+            if (data.record && !emg.recording) {
+                // `Intrinsics` (`width: u32, height: u32, focal_length: [f32; 2],
+                // principal_point: [f32; 2]`). The focal length is only known
+                // from `pose` (zero means unknown). `matrixBuffer` is free to
+                // use at this point
+                const focalLength = pose ? height / 2 / Math.tan(pose.fovY / 2) : 0;
+                new Uint32Array(emgExports.memory.buffer, emg.matrixBuffer, 2).set([width, height]);
+                new Float32Array(emgExports.memory.buffer, emg.matrixBuffer + 8, 4)
+                    .set([focalLength, focalLength, width / 2, height / 2]);
+                checkStatus(emgExports, emgExports.emg_context_start_recording(
+                    emg.context, emg.matrixBuffer, RECORDING_DOWNSCALE));
+                emg.recording = {width, height};
+            }
+        }
+
+        // Stamp the latest camere image onto the base cube map layer. If
+        // `autoGain` is set, its exposure and white balance are matched to
+        // the previously stamped images (no highlight restoration is done,
+        // though)
         {
             const {image, width, height} = data.camera;
             const size = width * height * 4;
@@ -140,7 +352,7 @@ class EnvironmentEstimator
                     emgExports.emg_free(emg.cameraImageBuffer.ptr);
                 }
 
-                const ptr = emgExports.emg_malloc(size);
+                const ptr = mallocChecked(emgExports, size);
                 emg.cameraImageBuffer = {
                     ptr,
                     size,
@@ -149,49 +361,143 @@ class EnvironmentEstimator
             new Uint8Array(emgExports.memory.buffer, emg.cameraImageBuffer.ptr, size)
                 .set(new Uint8Array(data.camera.image));
 
-            new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 16)
-                .set(data.camera.matrix);
-
-            t1 = performance.now();
-            emgExports.emg_context_stamp(emg.context, emg.cameraImageBuffer.ptr, width, height, emg.matrixBuffer);
+            const {matrix, pose} = data.camera;
+            if (pose) {
+                // `CameraParams` (`orientation: [f32; 4], fov_y: f32, aspect: f32`)
+                const params = new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 6);
+                params.set(pose.orientation);
+                params[4] = pose.fovY;
+                params[5] = width / height;
+
+                checkStatus(emgExports, emgExports.emg_context_stamp_camera(
+                    emg.context, emg.cameraImageBuffer.ptr, width, height, emg.matrixBuffer));
+            } else if (matrix) {
+                new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 16)
+                    .set(matrix);
+
+                checkStatus(emgExports, emgExports.emg_context_stamp(
+                    emg.context, emg.cameraImageBuffer.ptr, width, height, emg.matrixBuffer));
+            } else {
+                throw new Error("Either `matrix` or `pose` must be specified.");
+            }
         }
 
-        t2 = performance.now();
-        emgExports.emg_context_process(emg.context);
-        t3 = performance.now();
+        checkStatus(emgExports, emgExports.emg_context_process(emg.context));
+        t1 = performance.now();
 
-        // Generate the result image
-        const resultBuffer = data.resultBuffer || new ArrayBuffer(RESULT_BUFFER_SIZE);
-        {
+        // Generate the result image. `wasmResultBuffer` always holds the
+        // complete result, so only the levels changed since the last copy
+        // have to be updated there. If the buffer from the previous result
+        // was passed back, the same applies to it.
+        const {resultBuffer: wasmResultBuffer} = emg;
+        checkStatus(emgExports, emgExports.emg_context_copy_changed_output(
+            emg.context, wasmResultBuffer.ptr, wasmResultBuffer.size, emg.outLevelsBuffer));
+
+        let resultBuffer = data.resultBuffer;
+        if (resultBuffer && resultBuffer.byteLength === wasmResultBuffer.size) {
+            const changedLevels = new Uint32Array(emgExports.memory.buffer, emg.outLevelsBuffer, 1)[0];
             const outU8 = new Uint8Array(resultBuffer);
-            let outIndex = 0;
+            let offset = 0;
             for (let level = 0; level <= LOG_SIZE; ++level) {
-                const size = SIZE >> level;
-                const numTexels = size * size;
-                for (let i = 0; i < 6; ++i) {
-                    const wasmPtr = emgExports.emg_context_get_output_image_data(emg.context, level, i);
-                    outU8.set(new Uint8Array(emgExports.memory.buffer, wasmPtr, numTexels * 4), outIndex);
-                    outIndex += numTexels * 4;
+                const levelSize = ((SIZE >> level) ** 2) * 4 * 6;
+                if (changedLevels & (1 << level)) {
+                    outU8.set(new Uint8Array(emgExports.memory.buffer,
+                        wasmResultBuffer.ptr + offset, levelSize), offset);
                 }
+                offset += levelSize;
             }
+        } else {
+            resultBuffer = new ArrayBuffer(wasmResultBuffer.size);
+            new Uint8Array(resultBuffer).set(new Uint8Array(emgExports.memory.buffer,
+                wasmResultBuffer.ptr, wasmResultBuffer.size));
         }
 
-        t4 = performance.now();
+        t2 = performance.now();
+
+        // Report the scan progress. `matrixBuffer` is free to use at this point
+        const coverage = emgExports.emg_context_get_coverage(emg.context, emg.matrixBuffer);
+        const faceCoverage = Array.from(new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 6));
+
+        // `ViewSuggestionDesc` (`direction: [f32; 3], cone_angle: f32, solid_angle: f32`)
+        let viewSuggestion: EnvironmentEstimatorViewSuggestion | null = null;
+        if (emgExports.emg_context_suggest_view_direction(
+            emg.context, VIEW_SUGGESTION_HALF_FOV, false, 0, 0, emg.matrixBuffer)) {
+            const desc = new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 5);
+            viewSuggestion = {
+                direction: Array.from(desc.subarray(0, 3)),
+                coneAngle: desc[3],
+                solidAngle: desc[4],
+            };
+        }
+
+        let coverageMap: Float32Array | undefined;
+        if (data.coverageMapLevel != null) {
+            const len = emgExports.emg_context_coverage_map_len(emg.context, data.coverageMapLevel);
+            const ptr = mallocChecked(emgExports, len * 4);
+            try {
+                checkStatus(emgExports, emgExports.emg_context_copy_coverage_map(
+                    emg.context, data.coverageMapLevel, ptr, len));
+                coverageMap = new Float32Array(len);
+                coverageMap.set(new Float32Array(emgExports.memory.buffer, ptr, len));
+            } finally {
+                emgExports.emg_free(ptr);
+            }
+        }
 
         let performanceProfilingResult = '';
 
         if (data.profilePerformance) {
+            const {profileBuffer} = emg;
+            checkStatus(emgExports, emgExports.emg_context_copy_profile(
+                emg.context, profileBuffer.ptr, profileBuffer.len, true));
+            const profile = new Float64Array(emgExports.memory.buffer, profileBuffer.ptr, profileBuffer.len);
+
             const colWidth = 10;
-            performanceProfilingResult += `| Stamp              | ${fillWithRightAligned(String((t2 - t1) * 1000 | 0), colWidth, ' ')} μs |\n`;
-            performanceProfilingResult += `| Pyramid Generation | ${fillWithRightAligned(String((t3 - t2) * 1000 | 0), colWidth, ' ')} μs |\n`;
-            performanceProfilingResult += `| Copy Output        | ${fillWithRightAligned(String((t4 - t3) * 1000 | 0), colWidth, ' ')} μs |`;
+            const row = (label: string, seconds: number) => {
+                performanceProfilingResult += `| ${label} | ` +
+                    `${fillWithRightAligned(String(seconds * 1e6 | 0), colWidth, ' ')} μs |\n`;
+            };
+
+            // See `Profile::write_flat` for the layout
+            row('Stamp             ', profile[2]);
+            row('Downsample        ', profile[4]);
+            let i = 8;
+            for (let level = 0; level < profile[7]; ++level) {
+                const numPasses = profile[i++];
+                for (let pass = 0; pass < numPasses; ++pass) {
+                    row(`Blur L${level} P${pass}        `, profile[i++]);
+                }
+            }
+            row('Fill Holes        ', profile[5]);
+            row('Conversion        ', profile[6]);
+            row('Copy Output       ', (t2 - t1) * 1e-3);
+            performanceProfilingResult += `| Stamped Texels    | ${fillWithRightAligned(String(profile[1]), colWidth, ' ')}    |\n`;
+
+            // `matrixBuffer` is free to use at this point
+            const numGainSamples = emgExports.emg_context_get_last_gain(emg.context, emg.matrixBuffer);
+            const gain = new Float32Array(emgExports.memory.buffer, emg.matrixBuffer, 3);
+            performanceProfilingResult += `| Gain (R, G, B)    | ` +
+                `${Array.from(gain, (x) => x.toFixed(2)).join(', ')} (${numGainSamples} texels) |`;
+        }
+
+        const transfer = [resultBuffer, data.camera.image];
+        if (coverageMap) {
+            transfer.push(coverageMap.buffer);
+        }
+        if (recording) {
+            transfer.push(recording);
         }
 
         this.output.postMessage({
             cameraBuffer: data.camera.image,
             result: resultBuffer,
             performanceProfilingResult,
-        }, [resultBuffer, data.camera.image]);
+            coverage,
+            faceCoverage,
+            coverageMap,
+            viewSuggestion,
+            recording,
+        }, transfer);
     }
 }
 